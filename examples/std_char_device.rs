@@ -0,0 +1,35 @@
+use std::{env, process};
+use vroom::{Error, NvmeDevice, StdAllocator};
+
+pub fn main() -> Result<(), Error> {
+    env_logger::init();
+    let mut args = env::args();
+    args.next();
+    let path = match args.next() {
+        Some(arg) => arg,
+        None => {
+            eprintln!(
+                "Usage: cargo run --example std_char_device <character device, e.g. /dev/nvme0>"
+            );
+            process::exit(1);
+        }
+    };
+
+    let mut nvme = NvmeDevice::<StdAllocator>::from_char_device_with_std_allocator(&path)?;
+    println!("{:#?}", nvme.controller_information());
+
+    let namespace_ids = nvme.namespace_ids();
+    let namespace_id = namespace_ids
+        .first()
+        .expect("No namespaces exist.")
+        .to_owned();
+    println!("{:#?}", nvme.namespace(&namespace_id)?);
+
+    let mut io_queue_pair = nvme.create_io_queue_pair(&namespace_id, 64)?;
+    let mut buffer = io_queue_pair.allocate_buffer::<u8>(1)?;
+    io_queue_pair.read(&mut buffer, 0)?;
+    println!("First bytes of block 0: {:02X?}", &buffer[0..16]);
+    io_queue_pair.deallocate_buffer(buffer)?;
+
+    nvme.delete_io_queue_pair(io_queue_pair)
+}
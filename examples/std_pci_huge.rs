@@ -2,6 +2,7 @@ use std::{env, process};
 use vroom::Error;
 
 pub fn main() -> Result<(), Error> {
+    env_logger::init();
     let mut args = env::args();
     args.next();
     let pci_address = match args.next() {
@@ -26,7 +27,7 @@ pub fn main() -> Result<(), Error> {
     let mut io_queue_pair_1 = nvme.create_io_queue_pair(&namespace_id, queue_capacity)?;
     let mut io_queue_pair_2 = nvme.create_io_queue_pair(&namespace_id, queue_capacity)?;
 
-    const TEXT: &'static str = "Hello, world!";
+    const TEXT: &str = "Hello, world!";
     const LENGTH: usize = TEXT.len();
 
     let mut source_1 = io_queue_pair_1.allocate_buffer(LENGTH)?;
@@ -56,4 +57,5 @@ pub fn main() -> Result<(), Error> {
     io_queue_pair_2.deallocate_buffer(dest_2)?;
 
     nvme.shutdown(vec![io_queue_pair_1, io_queue_pair_2])
+        .map_err(|(_, _, error)| error)
 }
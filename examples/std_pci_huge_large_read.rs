@@ -0,0 +1,55 @@
+use std::{env, process};
+use vroom::Error;
+
+pub fn main() -> Result<(), Error> {
+    env_logger::init();
+    let mut args = env::args();
+    args.next();
+    let pci_address = match args.next() {
+        Some(arg) => arg,
+        None => {
+            eprintln!("Usage: cargo run --example std_pci_huge_large_read <PCI bus ID>");
+            process::exit(1);
+        }
+    };
+
+    let mut nvme = vroom::new_pci_and_huge(&pci_address)?;
+
+    let namespace_ids = nvme.namespace_ids();
+    let namespace_id = namespace_ids
+        .first()
+        .expect("No namespaces exist.")
+        .to_owned();
+    let queue_capacity = nvme
+        .controller_information()
+        .maximum_queue_entries_supported;
+    let maximum_transfer_size = nvme.controller_information().maximum_transfer_size;
+    let logical_block_address = 0;
+    let mut io_queue_pair = nvme.create_io_queue_pair(&namespace_id, queue_capacity)?;
+
+    // Transfer 1 MiB with a single command (or the maximum transfer size if it is smaller).
+    const LENGTH: usize = 1 << 20;
+    let length = maximum_transfer_size.map_or(LENGTH, |maximum| LENGTH.min(maximum));
+
+    let mut source = io_queue_pair.allocate_buffer::<u8>(length)?;
+    let mut destination = io_queue_pair.allocate_buffer::<u8>(length)?;
+    for i in 0..length {
+        source[i] = (i % 251) as u8;
+    }
+    io_queue_pair.write(&source, logical_block_address)?;
+    io_queue_pair.read(&mut destination, logical_block_address)?;
+
+    let matches = source[..length] == destination[..length];
+    println!("Read 0x{length:X} bytes in one command, data matches: {matches}");
+
+    io_queue_pair.deallocate_buffer(source)?;
+    io_queue_pair.deallocate_buffer(destination)?;
+
+    nvme.shutdown(vec![io_queue_pair])
+        .map_err(|(_, _, error)| error)?;
+    if !matches {
+        eprintln!("The data read does not match the data written.");
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use crate::nvme::{Namespace, NamespaceId};
+use alloc::vec::Vec;
+
+/// The Asymmetric Namespace Access state of an ANA group (NVMe base specification 8.1.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnaState {
+    Optimized,
+    NonOptimized,
+    Inaccessible,
+    PersistentLoss,
+    Change,
+    Reserved(u8),
+}
+
+impl AnaState {
+    pub(crate) fn from_u8(state: u8) -> Self {
+        match state {
+            0x1 => AnaState::Optimized,
+            0x2 => AnaState::NonOptimized,
+            0x3 => AnaState::Inaccessible,
+            0x4 => AnaState::PersistentLoss,
+            0xF => AnaState::Change,
+            state => AnaState::Reserved(state),
+        }
+    }
+
+    /// Whether commands can be processed through this controller.
+    pub fn is_accessible(&self) -> bool {
+        matches!(self, AnaState::Optimized | AnaState::NonOptimized)
+    }
+}
+
+/// NVMe base specification 5.16.1.12 ANA Group Descriptor
+#[derive(Debug, Clone)]
+pub struct AnaGroupDescriptor {
+    pub group_id: u32,
+    pub change_count: u64,
+    pub state: AnaState,
+    /// The attached namespaces in the group, empty if only the states were requested.
+    pub namespace_ids: Vec<NamespaceId>,
+}
+
+/// NVMe base specification 5.16.1.12 Asymmetric Namespace Access log page
+#[derive(Debug, Clone)]
+pub struct AnaLog {
+    pub change_count: u64,
+    pub groups: Vec<AnaGroupDescriptor>,
+}
+
+/// Size of the log page header and of a group descriptor without namespace IDs in bytes.
+const ANA_LOG_HEADER_SIZE: usize = 16;
+const ANA_GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+impl AnaLog {
+    /// Parses the log page in `bytes`; descriptors truncated by the end of `bytes` are skipped.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let read_u32 = |offset: usize| {
+            let mut value = [0u8; 4];
+            value.copy_from_slice(&bytes[offset..offset + 4]);
+            u32::from_le_bytes(value)
+        };
+        let read_u64 = |offset: usize| {
+            let mut value = [0u8; 8];
+            value.copy_from_slice(&bytes[offset..offset + 8]);
+            u64::from_le_bytes(value)
+        };
+        let number_of_groups = u16::from_le_bytes([bytes[8], bytes[9]]);
+        let mut groups = Vec::new();
+        let mut offset = ANA_LOG_HEADER_SIZE;
+        for _ in 0..number_of_groups {
+            if offset + ANA_GROUP_DESCRIPTOR_SIZE > bytes.len() {
+                break;
+            }
+            let number_of_namespaces = read_u32(offset + 4) as usize;
+            let namespace_ids_offset = offset + ANA_GROUP_DESCRIPTOR_SIZE;
+            let end = namespace_ids_offset + number_of_namespaces * 4;
+            if end > bytes.len() {
+                break;
+            }
+            groups.push(AnaGroupDescriptor {
+                group_id: read_u32(offset),
+                change_count: read_u64(offset + 8),
+                state: AnaState::from_u8(bytes[offset + 16] & 0b1111),
+                namespace_ids: (namespace_ids_offset..end)
+                    .step_by(4)
+                    .map(|offset| NamespaceId(read_u32(offset)))
+                    .collect(),
+            });
+            offset = end;
+        }
+        Self {
+            change_count: read_u64(0),
+            groups,
+        }
+    }
+
+    /// The ANA state of the group the `namespace` belongs to.
+    pub fn state_of(&self, namespace: &Namespace) -> Option<AnaState> {
+        self.state_of_group(namespace.ana_group_id)
+    }
+
+    /// The ANA state of the group with the `group_id`.
+    pub fn state_of_group(&self, group_id: u32) -> Option<AnaState> {
+        self.groups
+            .iter()
+            .find(|group| group.group_id == group_id)
+            .map(|group| group.state)
+    }
+}
@@ -0,0 +1,104 @@
+use crate::error::Error;
+use crate::smart::SmartHealthLog;
+use alloc::vec::Vec;
+
+/// Log Page Identifier of the Error Information log page.
+pub(crate) const ERROR_INFORMATION_LOG_IDENTIFIER: u8 = 0x01;
+
+/// Size of an Error Information log entry in bytes.
+pub(crate) const ERROR_INFORMATION_ENTRY_SIZE: usize = 64;
+
+/// Size in bytes read of log pages without a decoded representation.
+pub(crate) const OTHER_LOG_SIZE: usize = 512;
+
+/// Asynchronous Event Type of a completed Asynchronous Event Request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsyncEventType {
+    /// An error not associated with a command, reported in the Error Information log page.
+    Error,
+    /// A SMART / Health Information condition, e.g. a temperature threshold was crossed.
+    SmartHealthStatus,
+    Notice,
+    ImmediateCommandSetSpecific,
+    IoCommandSetSpecific,
+    VendorSpecific,
+    Reserved(u8),
+}
+
+impl AsyncEventType {
+    fn from_u8(event_type: u8) -> Self {
+        match event_type {
+            0 => AsyncEventType::Error,
+            1 => AsyncEventType::SmartHealthStatus,
+            2 => AsyncEventType::Notice,
+            3 => AsyncEventType::ImmediateCommandSetSpecific,
+            6 => AsyncEventType::IoCommandSetSpecific,
+            7 => AsyncEventType::VendorSpecific,
+            event_type => AsyncEventType::Reserved(event_type),
+        }
+    }
+}
+
+/// NVMe base specification 5.16.1.2 Error Information log entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorInformationEntry {
+    /// Error Count, a unique identifier of the error; 0 if the entry is empty.
+    pub error_count: u64,
+    /// Submission Queue ID of the failed command; 0xFFFF if not associated with a command.
+    pub submission_queue_id: u16,
+    /// Command ID of the failed command; 0xFFFF if not associated with a command.
+    pub command_id: u16,
+    /// Status Field of the completion of the failed command, without the phase tag.
+    pub status: u16,
+    pub parameter_error_location: u16,
+    /// The first logical block address the error occurred at.
+    pub logical_block_address: u64,
+    pub namespace_id: u32,
+}
+
+impl ErrorInformationEntry {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let read_u16 = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        Self {
+            error_count: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            submission_queue_id: read_u16(8),
+            command_id: read_u16(10),
+            status: read_u16(12) >> 1,
+            parameter_error_location: read_u16(14),
+            logical_block_address: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            namespace_id: u32::from_le_bytes(bytes[24..28].try_into().unwrap()),
+        }
+    }
+}
+
+/// The log page read for an asynchronous event, which also clears the event
+/// so the controller reports further events of its type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsyncEventLog {
+    /// The non-empty entries of the Error Information log page, the newest first.
+    ErrorInformation(Vec<ErrorInformationEntry>),
+    SmartHealth(SmartHealthLog),
+    /// The first 512 bytes of any other log page, e.g. the Firmware Slot Information.
+    Other(Vec<u8>),
+}
+
+/// NVMe base specification 5.2 Asynchronous Event Request command: a reported event.
+#[derive(Debug)]
+pub struct AsyncEvent {
+    pub event_type: AsyncEventType,
+    /// Asynchronous Event Information, the event within its type.
+    pub information: u8,
+    /// Log Page Identifier of the log page with the details of the event.
+    pub log_page_identifier: u8,
+    /// The log page, or the error reading it, in which case the event may not be cleared.
+    pub log: Result<AsyncEventLog, Error>,
+}
+
+/// Event type, information and log page identifier of dword 0 of the completion.
+pub(crate) fn decode_dword_0(dword_0: u32) -> (AsyncEventType, u8, u8) {
+    (
+        AsyncEventType::from_u8((dword_0 & 0b111) as u8),
+        (dword_0 >> 8) as u8,
+        (dword_0 >> 16) as u8,
+    )
+}
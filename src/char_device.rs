@@ -0,0 +1,128 @@
+use crate::cmd::NvmeCommand;
+use crate::error::Error;
+use crate::queue_pairs::IoQueuePairId;
+use std::boxed::Box;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::prelude::AsRawFd;
+
+// _IOWR('N', 0x41, struct nvme_passthru_cmd)
+const NVME_IOCTL_ADMIN_CMD: u32 = 0xC048_4E41;
+// _IOWR('N', 0x43, struct nvme_passthru_cmd)
+const NVME_IOCTL_IO_CMD: u32 = 0xC048_4E43;
+
+/// `struct nvme_passthru_cmd` of the Linux kernel.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct PassthroughCommand {
+    opcode: u8,
+    flags: u8,
+    _reserved: u16,
+    namespace_id: u32,
+    cdw2: u32,
+    cdw3: u32,
+    metadata: u64,
+    address: u64,
+    metadata_length: u32,
+    data_length: u32,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+    timeout_milliseconds: u32,
+    result: u32,
+}
+
+/// The character device (`/dev/nvmeX`) of a controller bound to the kernel driver.
+/// Commands are passed through to the kernel, which submits them on its own queues.
+/// Data pointers are virtual addresses, the kernel maps them for the transfer.
+#[derive(Debug)]
+pub(crate) struct CharDevice {
+    file: File,
+}
+
+impl CharDevice {
+    pub(crate) fn open(path: &str) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|error| Error::CharDeviceError(Box::new(error)))?;
+        Ok(Self { file })
+    }
+
+    /// Pass the admin `command` through, transferring `data_length` bytes at `data`.
+    /// Returns dword 0 of the completion queue entry.
+    pub(crate) fn admin(
+        &self,
+        command: &NvmeCommand,
+        data: *mut u8,
+        data_length: usize,
+    ) -> Result<u32, Error> {
+        self.passthrough(
+            NVME_IOCTL_ADMIN_CMD,
+            IoQueuePairId(0),
+            command,
+            data,
+            data_length,
+        )
+    }
+
+    /// Pass the I/O `command` of the queue pair with `queue_id` through,
+    /// transferring `data_length` bytes at `data`.
+    /// Returns dword 0 of the completion queue entry.
+    pub(crate) fn io(
+        &self,
+        queue_id: IoQueuePairId,
+        command: &NvmeCommand,
+        data: *mut u8,
+        data_length: usize,
+    ) -> Result<u32, Error> {
+        self.passthrough(NVME_IOCTL_IO_CMD, queue_id, command, data, data_length)
+    }
+
+    fn passthrough(
+        &self,
+        request: u32,
+        queue_id: IoQueuePairId,
+        command: &NvmeCommand,
+        data: *mut u8,
+        data_length: usize,
+    ) -> Result<u32, Error> {
+        let mut passthrough_command = PassthroughCommand {
+            opcode: command.opcode,
+            flags: command.flags,
+            namespace_id: command.namespace_id,
+            address: data as u64,
+            data_length: data_length as u32,
+            cdw10: command.cdw10,
+            cdw11: command.cdw11,
+            cdw12: command.cdw12,
+            cdw13: command.cdw13,
+            cdw14: command.cdw14,
+            cdw15: command.cdw15,
+            ..Default::default()
+        };
+        let result = unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                request as _,
+                &mut passthrough_command as *mut PassthroughCommand,
+            )
+        };
+        // negative on errors of the ioctl itself, positive with the NVMe status (without phase tag)
+        if result < 0 {
+            return Err(Error::CharDeviceError(Box::new(io::Error::last_os_error())));
+        }
+        if result > 0 {
+            return Err(Error::from_status(
+                queue_id,
+                command.command_id,
+                result as u16,
+            ));
+        }
+        Ok(passthrough_command.result)
+    }
+}
@@ -0,0 +1,128 @@
+use crate::dma::Dma;
+use crate::error::Error;
+use crate::queue_pairs::IoQueuePairId;
+use alloc::vec::Vec;
+
+/// Where the memory of an I/O queue is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueuePlacement {
+    /// In host memory allocated with the allocator of the device.
+    #[default]
+    Host,
+    /// In the Controller Memory Buffer, so the controller fetches submission queue entries
+    /// (or posts completion queue entries) without a transfer over PCIe.
+    ControllerMemoryBuffer,
+}
+
+/// NVMe base specification 3.1.4.12 Controller Memory Buffer Location (CMBLOC) and
+/// 3.1.4.13 Controller Memory Buffer Size (CMBSZ)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllerMemoryBuffer {
+    /// Base Indicator Register (BIR): the BAR the buffer is located in.
+    pub bar: u8,
+    /// Offset of the buffer in the BAR in bytes (OFST converted).
+    pub offset: u64,
+    /// Size of the buffer in bytes (SZ converted).
+    pub size: u64,
+    /// Whether I/O submission queues may be placed in the buffer (SQS).
+    pub submission_queue_support: bool,
+    /// Whether I/O completion queues may be placed in the buffer (CQS).
+    pub completion_queue_support: bool,
+}
+
+impl ControllerMemoryBuffer {
+    /// `None` if the controller has no buffer, i.e. its size is 0.
+    pub(crate) fn from_registers(cmbloc: u32, cmbsz: u32) -> Option<Self> {
+        let size_units = (cmbsz >> 8) & 0b1111; // SZU
+        if size_units > 6 {
+            return None; // reserved
+        }
+        let size_unit = 1u64 << (12 + 4 * size_units);
+        let size = (cmbsz >> 12) as u64 * size_unit; // SZ
+        if size == 0 {
+            return None;
+        }
+        Some(Self {
+            bar: (cmbloc & 0b111) as u8,
+            offset: (cmbloc >> 12) as u64 * size_unit,
+            size,
+            submission_queue_support: cmbsz & 0b1 == 1,
+            completion_queue_support: (cmbsz >> 1) & 0b1 == 1,
+        })
+    }
+}
+
+/// The Controller Memory Buffer as mapped with the BAR of the device and the ranges of it
+/// holding queues.
+#[derive(Debug)]
+pub(crate) struct MappedControllerMemoryBuffer {
+    pub(crate) buffer: ControllerMemoryBuffer,
+    virtual_address: usize,
+    physical_address: u64,
+    /// Queue pair ID, offset and size of the ranges in use, sorted by offset.
+    allocations: Vec<(IoQueuePairId, u64, u64)>,
+}
+
+impl MappedControllerMemoryBuffer {
+    pub(crate) fn new(
+        buffer: ControllerMemoryBuffer,
+        virtual_address: usize,
+        physical_address: u64,
+    ) -> Self {
+        Self {
+            buffer,
+            virtual_address,
+            physical_address,
+            allocations: Vec::new(),
+        }
+    }
+
+    /// Take the first free, `page_size` aligned range of the buffer with room for
+    /// `number_of_elements` of `T` for the queue pair with `queue_id`.
+    pub(crate) fn allocate<T>(
+        &mut self,
+        queue_id: IoQueuePairId,
+        number_of_elements: usize,
+        page_size: usize,
+    ) -> Result<Dma<T>, Error> {
+        let size = (number_of_elements * core::mem::size_of::<T>()).next_multiple_of(page_size);
+        let mut offset = 0;
+        let mut index = 0;
+        for &(_, allocation_offset, allocation_size) in &self.allocations {
+            if offset + size as u64 <= allocation_offset {
+                break;
+            }
+            offset = (allocation_offset + allocation_size).next_multiple_of(page_size as u64);
+            index += 1;
+        }
+        if offset + size as u64 > self.buffer.size {
+            return Err(Error::ControllerMemoryBufferFull(size));
+        }
+        self.allocations
+            .insert(index, (queue_id, offset, size as u64));
+        // SAFETY: the range is within the mapped buffer and not handed out twice.
+        Ok(unsafe {
+            Dma::from_raw_parts(
+                (self.virtual_address + offset as usize) as *mut T,
+                (self.physical_address + offset) as *mut T,
+                number_of_elements,
+            )
+        })
+    }
+
+    /// Free the ranges of the queue pair with `queue_id`.
+    pub(crate) fn release(&mut self, queue_id: IoQueuePairId) {
+        self.allocations.retain(|(id, _, _)| id != &queue_id);
+    }
+
+    /// Whether the `physical_address` lies within the buffer, i.e. a queue at it is placed
+    /// in the buffer instead of host memory.
+    pub(crate) fn contains(&self, physical_address: u64) -> bool {
+        (self.physical_address..self.physical_address + self.buffer.size)
+            .contains(&physical_address)
+    }
+
+    pub(crate) fn release_all(&mut self) {
+        self.allocations.clear();
+    }
+}
@@ -1,3 +1,5 @@
+use crate::nvme::{MetadataSettings, SecureEraseSetting};
+
 /// NVMe Spec 4.2
 /// Submission queue entry
 #[derive(Clone, Copy, Debug, Default)]
@@ -31,7 +33,13 @@ impl NvmeCommand {
         queue_id: u16,
         data_pointer: usize,
         size: u16,
+        interrupt_vector: Option<u16>,
     ) -> Self {
+        // Interrupt Vector (IV) and Interrupts Enabled (IEN)
+        let interrupts = match interrupt_vector {
+            Some(vector) => ((vector as u32) << 16) | (1 << 1),
+            None => 0,
+        };
         Self {
             opcode: 5,
             flags: 0,
@@ -41,7 +49,7 @@ impl NvmeCommand {
             metadata_pointer: 0,
             data_pointer: [data_pointer as u64, 0],
             cdw10: ((size as u32) << 16) | (queue_id as u32),
-            cdw11: 1, // Physically Contiguous
+            cdw11: interrupts | 1, // Physically Contiguous
             cdw12: 0,
             cdw13: 0,
             cdw14: 0,
@@ -150,20 +158,125 @@ impl NvmeCommand {
         }
     }
 
+    pub(crate) fn identify_io_command_set(
+        command_id: u16,
+        data_pointer: usize,
+        controller_id: u16,
+    ) -> Self {
+        Self {
+            opcode: 6,
+            flags: 0,
+            command_id,
+            namespace_id: 0,
+            _reserved: 0,
+            metadata_pointer: 0,
+            data_pointer: [data_pointer as u64, 0],
+            cdw10: ((controller_id as u32) << 16) | 0x1C,
+            cdw11: 0,
+            cdw12: 0,
+            cdw13: 0,
+            cdw14: 0,
+            cdw15: 0,
+        }
+    }
+
+    /// Identify the I/O command set specific data of a namespace (CNS 0x05)
+    /// for the command set with the `command_set_identifier` (CSI).
+    pub(crate) fn identify_namespace_command_set(
+        command_id: u16,
+        data_pointer: usize,
+        namespace_id: u32,
+        command_set_identifier: u8,
+    ) -> Self {
+        Self {
+            opcode: 6,
+            flags: 0,
+            command_id,
+            namespace_id,
+            _reserved: 0,
+            metadata_pointer: 0,
+            data_pointer: [data_pointer as u64, 0],
+            cdw10: 0x05,
+            cdw11: (command_set_identifier as u32) << 24,
+            cdw12: 0,
+            cdw13: 0,
+            cdw14: 0,
+            cdw15: 0,
+        }
+    }
+
+    /// Vendor specific command; `cdws` are the command dwords 10 to 15.
+    pub(crate) fn vendor_specific(
+        command_id: u16,
+        opcode: u8,
+        namespace_id: u32,
+        prp_1: usize,
+        prp_2: usize,
+        cdws: [u32; 6],
+    ) -> Self {
+        Self {
+            opcode,
+            flags: 0,
+            command_id,
+            namespace_id,
+            _reserved: 0,
+            metadata_pointer: 0,
+            data_pointer: [prp_1 as u64, prp_2 as u64],
+            cdw10: cdws[0],
+            cdw11: cdws[1],
+            cdw12: cdws[2],
+            cdw13: cdws[3],
+            cdw14: cdws[4],
+            cdw15: cdws[5],
+        }
+    }
+
     pub(crate) fn get_features(
-        _command_id: u16,
+        command_id: u16,
         data_pointer: usize,
         feature_id: FeatureIdentifier,
         select: Select,
+    ) -> Self {
+        Self::get_namespace_features(command_id, data_pointer, 0, feature_id, select)
+    }
+
+    /// Get Features of a namespace specific feature.
+    pub(crate) fn get_namespace_features(
+        command_id: u16,
+        data_pointer: usize,
+        namespace_id: u32,
+        feature_id: FeatureIdentifier,
+        select: Select,
     ) -> Self {
         Self {
             opcode: 0xA,
+            command_id,
+            namespace_id,
             data_pointer: [data_pointer as u64, 0],
             cdw10: ((select as u32) << 11) | feature_id as u32,
             ..Default::default()
         }
     }
 
+    /// Set Features with the feature specific command dword 11, the value is not saved.
+    pub(crate) fn set_features(
+        command_id: u16,
+        data_pointer: usize,
+        namespace_id: u32,
+        feature_id: FeatureIdentifier,
+        cdw11: u32,
+    ) -> Self {
+        Self {
+            opcode: 0x9,
+            command_id,
+            namespace_id,
+            data_pointer: [data_pointer as u64, 0],
+            cdw10: feature_id as u32,
+            cdw11,
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn io_read(
         command_id: u16,
         namespace_id: u32,
@@ -171,6 +284,7 @@ impl NvmeCommand {
         number_of_blocks: u16,
         prp_1: u64,
         prp_2: u64,
+        options: IoOptions,
     ) -> Self {
         Self {
             opcode: 2,
@@ -182,8 +296,8 @@ impl NvmeCommand {
             data_pointer: [prp_1, prp_2],
             cdw10: logical_block_address as u32,
             cdw11: (logical_block_address >> 32) as u32,
-            cdw12: number_of_blocks as u32,
-            cdw13: 0,
+            cdw12: options.cdw12() | number_of_blocks as u32,
+            cdw13: options.cdw13(),
             cdw14: 0,
             cdw15: 0,
         }
@@ -196,6 +310,7 @@ impl NvmeCommand {
         number_of_blocks: u16,
         prp_1: u64,
         prp_2: u64,
+        options: IoOptions,
     ) -> Self {
         Self {
             opcode: 1,
@@ -207,6 +322,31 @@ impl NvmeCommand {
             data_pointer: [prp_1, prp_2],
             cdw10: logical_block_address as u32,
             cdw11: (logical_block_address >> 32) as u32,
+            cdw12: options.cdw12() | number_of_blocks as u32,
+            cdw13: options.cdw13(),
+            cdw14: 0,
+            cdw15: 0,
+        }
+    }
+
+    pub(crate) fn compare(
+        command_id: u16,
+        namespace_id: u32,
+        logical_block_address: u64,
+        number_of_blocks: u16,
+        prp_1: u64,
+        prp_2: u64,
+    ) -> Self {
+        Self {
+            opcode: 0x05,
+            flags: 0,
+            command_id,
+            namespace_id,
+            _reserved: 0,
+            metadata_pointer: 0,
+            data_pointer: [prp_1, prp_2],
+            cdw10: logical_block_address as u32,
+            cdw11: (logical_block_address >> 32) as u32,
             cdw12: number_of_blocks as u32,
             cdw13: 0,
             cdw14: 0,
@@ -214,7 +354,48 @@ impl NvmeCommand {
         }
     }
 
-    pub(crate) fn format_nvm(command_id: u16, namespace_id: u32) -> Self {
+    pub(crate) fn flush(command_id: u16, namespace_id: u32) -> Self {
+        Self {
+            opcode: 0x00,
+            command_id,
+            namespace_id,
+            ..Default::default()
+        }
+    }
+
+    /// Dataset Management of `number_of_ranges` range descriptors at PRP1 and PRP2,
+    /// with Attribute - Deallocate (AD) set if `deallocate`.
+    pub(crate) fn dataset_management(
+        command_id: u16,
+        namespace_id: u32,
+        number_of_ranges: u16,
+        deallocate: bool,
+        prp_1: u64,
+        prp_2: u64,
+    ) -> Self {
+        Self {
+            opcode: 0x09,
+            command_id,
+            namespace_id,
+            data_pointer: [prp_1, prp_2],
+            cdw10: (number_of_ranges - 1) as u32,
+            cdw11: (deallocate as u32) << 2,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn format_nvm(
+        command_id: u16,
+        namespace_id: u32,
+        lba_format: u8,
+        secure_erase: SecureEraseSetting,
+        metadata: MetadataSettings,
+    ) -> Self {
+        // the lower 4 bits of the LBA format index are in LBAF, the upper 2 bits in LBAFU
+        let lba_format = (lba_format as u32 & 0xF) | ((lba_format as u32 & 0x30) << 8);
+        let metadata = (metadata.extended_lba as u32) << 4 // MSET
+            | (metadata.protection_information as u32) << 5 // PI
+            | (metadata.protection_information_first as u32) << 8; // PIL
         Self {
             opcode: 0x80,
             flags: 0,
@@ -223,8 +404,7 @@ impl NvmeCommand {
             _reserved: 0,
             metadata_pointer: 0,
             data_pointer: [0, 0],
-            cdw10: 1 << 9,
-            // TODO: dealloc and prinfo bits
+            cdw10: ((secure_erase as u32) << 9) | metadata | lba_format,
             cdw11: 0,
             cdw12: 0,
             cdw13: 0,
@@ -233,7 +413,25 @@ impl NvmeCommand {
         }
     }
 
-    #[allow(dead_code)]
+    /// Abort of the command with `command_id` submitted to the submission queue with
+    /// `submission_queue_id`.
+    pub(crate) fn abort(command_id: u16, submission_queue_id: u16, abort_command_id: u16) -> Self {
+        Self {
+            opcode: 0x08,
+            command_id,
+            cdw10: (abort_command_id as u32) << 16 | submission_queue_id as u32,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn keep_alive(command_id: u16) -> Self {
+        Self {
+            opcode: 0x18,
+            command_id,
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn async_event_req(command_id: u16) -> Self {
         Self {
             opcode: 0xC,
@@ -252,9 +450,9 @@ impl NvmeCommand {
         }
     }
 
-    #[allow(dead_code)]
     pub(crate) fn get_log_page(
         command_id: u16,
+        namespace_id: u32,
         numd: u32,
         ptr0: u64,
         ptr1: u64,
@@ -262,7 +460,9 @@ impl NvmeCommand {
         lpid: u16,
     ) -> Self {
         Self {
+            opcode: 2,
             command_id,
+            namespace_id,
             data_pointer: [ptr0, ptr1],
             cdw10: (numd << 16) | lid as u32,
             cdw11: ((lpid as u32) << 16) | numd >> 16,
@@ -270,7 +470,73 @@ impl NvmeCommand {
         }
     }
 
-    #[allow(dead_code)]
+    /// Get Log Page starting at the byte `offset` into the log page,
+    /// with the Log Specific Field `log_specific`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn get_log_page_at_offset(
+        command_id: u16,
+        namespace_id: u32,
+        numd: u32,
+        ptr0: u64,
+        ptr1: u64,
+        lid: u8,
+        log_specific: u8,
+        offset: u64,
+    ) -> Self {
+        Self {
+            opcode: 2,
+            command_id,
+            namespace_id,
+            data_pointer: [ptr0, ptr1],
+            cdw10: (numd << 16) | ((log_specific as u32) << 8) | lid as u32,
+            cdw11: numd >> 16,
+            cdw12: offset as u32,
+            cdw13: (offset >> 32) as u32,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn zone_management_send(
+        command_id: u16,
+        namespace_id: u32,
+        zone_start_logical_block_address: u64,
+        select_all: bool,
+        action: ZoneSendAction,
+    ) -> Self {
+        Self {
+            opcode: 0x79,
+            command_id,
+            namespace_id,
+            cdw10: zone_start_logical_block_address as u32,
+            cdw11: (zone_start_logical_block_address >> 32) as u32,
+            cdw13: ((select_all as u32) << 8) | action as u32,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn zone_management_receive(
+        command_id: u16,
+        namespace_id: u32,
+        zone_start_logical_block_address: u64,
+        number_of_dwords: u32,
+        partial_report: bool,
+        prp_1: u64,
+        prp_2: u64,
+    ) -> Self {
+        Self {
+            opcode: 0x7A,
+            command_id,
+            namespace_id,
+            data_pointer: [prp_1, prp_2],
+            cdw10: zone_start_logical_block_address as u32,
+            cdw11: (zone_start_logical_block_address >> 32) as u32,
+            cdw12: number_of_dwords - 1,
+            // Zone Receive Action: Report Zones, Reporting Options: all zones
+            cdw13: (partial_report as u32) << 16,
+            ..Default::default()
+        }
+    }
+
     // not supported by samsung
     pub(crate) fn write_zeroes(
         command_id: u16,
@@ -288,7 +554,7 @@ impl NvmeCommand {
             metadata_pointer: 0,
             data_pointer: [0, 0],
             cdw10: slba as u32,
-            // TODO: dealloc and prinfo bits
+            // TODO: prinfo bits
             cdw11: (slba >> 32) as u32,
             cdw12: ((deac as u32) << 25) | nlb as u32,
             cdw13: 0,
@@ -296,6 +562,50 @@ impl NvmeCommand {
             cdw15: 0,
         }
     }
+
+    /// Copy with source range entries in descriptor format 0 at the data pointer.
+    pub(crate) fn copy(
+        command_id: u16,
+        namespace_id: u32,
+        destination_lba: u64,
+        number_of_ranges_zero_based: u8,
+        prp_1: u64,
+        prp_2: u64,
+    ) -> Self {
+        Self {
+            opcode: 0x19,
+            command_id,
+            namespace_id,
+            data_pointer: [prp_1, prp_2],
+            cdw10: destination_lba as u32,
+            cdw11: (destination_lba >> 32) as u32,
+            cdw12: number_of_ranges_zero_based as u32, // NR
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn verify(command_id: u16, namespace_id: u32, slba: u64, nlb: u16) -> Self {
+        Self {
+            opcode: 0x0C,
+            command_id,
+            namespace_id,
+            cdw10: slba as u32,
+            cdw11: (slba >> 32) as u32,
+            cdw12: nlb as u32,
+            ..Default::default()
+        }
+    }
+}
+
+/// ZSA
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ZoneSendAction {
+    Close = 0x1,
+    Finish = 0x2,
+    Open = 0x3,
+    Reset = 0x4,
+    Offline = 0x5,
 }
 
 #[allow(dead_code)]
@@ -308,13 +618,13 @@ pub(crate) enum Select {
     SupportedCapabilites = 0b011,
 }
 
-#[allow(dead_code)]
 /// FID
-#[derive(Debug, Clone, Copy)]
-pub(crate) enum FeatureIdentifier {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureIdentifier {
     Arbitration = 0x1,
     PowerManagement = 0x2,
     TemperatureThreshold = 0x4,
+    ErrorRecovery = 0x5,
     VolatileWriteCache = 0x6,
     NumberOfQueues = 0x7,
     InterruptCoalescing = 0x08,
@@ -349,10 +659,149 @@ pub(crate) enum FeatureIdentifier {
     ReservationNotificationMask = 0x82,
     ReservationPersistence = 0x83,
     NamespaceWriteProtectionConfig = 0x84,
-    BootPartitionWriteProtectionConfi = 0x85,
+    BootPartitionWriteProtectionConfig = 0x85,
     // I/O Command Set specific features
 }
 
+impl FeatureIdentifier {
+    pub const ALL: [FeatureIdentifier; 39] = [
+        FeatureIdentifier::Arbitration,
+        FeatureIdentifier::PowerManagement,
+        FeatureIdentifier::TemperatureThreshold,
+        FeatureIdentifier::ErrorRecovery,
+        FeatureIdentifier::VolatileWriteCache,
+        FeatureIdentifier::NumberOfQueues,
+        FeatureIdentifier::InterruptCoalescing,
+        FeatureIdentifier::InterruptVectorConfiguration,
+        FeatureIdentifier::AsynchronousEventConfiguration,
+        FeatureIdentifier::AutonomousPowerStateTransition,
+        FeatureIdentifier::HostMemoryBuffer,
+        FeatureIdentifier::Timestamp,
+        FeatureIdentifier::KeepAliveTimer,
+        FeatureIdentifier::HostControlledThermalManagement,
+        FeatureIdentifier::NonOperationalPowerStateConfig,
+        FeatureIdentifier::ReadRecoveryLevelConfig,
+        FeatureIdentifier::PredictableLatencyModeConfig,
+        FeatureIdentifier::PredictableLatencyModeWindow,
+        FeatureIdentifier::HostBehaviorSupport,
+        FeatureIdentifier::SanitizeConfig,
+        FeatureIdentifier::EnduranceGroupEventConfiguration,
+        FeatureIdentifier::IOCommandSetProfile,
+        FeatureIdentifier::SpinupControl,
+        FeatureIdentifier::PowerLossSignalingConfig,
+        FeatureIdentifier::FlexibleDataPlacement,
+        FeatureIdentifier::FlexibleDataPlacementEvents,
+        FeatureIdentifier::NamespaceAdminLabel,
+        FeatureIdentifier::ControllerDataQueue,
+        FeatureIdentifier::EmbeddedManagementControllerAddress,
+        FeatureIdentifier::HostManagementAgentAddress,
+        FeatureIdentifier::EnhancedControllerMetadata1,
+        FeatureIdentifier::ControllerMetadata1,
+        FeatureIdentifier::NamespaceMetadata1,
+        FeatureIdentifier::SoftwareProgressMarker,
+        FeatureIdentifier::HostIdentifier,
+        FeatureIdentifier::ReservationNotificationMask,
+        FeatureIdentifier::ReservationPersistence,
+        FeatureIdentifier::NamespaceWriteProtectionConfig,
+        FeatureIdentifier::BootPartitionWriteProtectionConfig,
+    ];
+}
+
+/// The capabilities of a feature, reported by Get Features with the select field set to
+/// "supported capabilities".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureCaps {
+    pub saveable: bool,
+    pub namespace_specific: bool,
+    pub changeable: bool,
+}
+
+impl FeatureCaps {
+    pub(crate) fn from_u32(dword_0: u32) -> Self {
+        Self {
+            saveable: dword_0 & 0b1 != 0,
+            namespace_specific: (dword_0 >> 1) & 0b1 != 0,
+            changeable: (dword_0 >> 2) & 0b1 != 0,
+        }
+    }
+}
+
+/// Options of Read and Write commands, none are set by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoOptions {
+    /// Limited Retry (LR): the controller applies limited retry efforts instead of
+    /// all available error recovery, so a failing command completes sooner.
+    pub limited_retry: bool,
+    /// Force Unit Access (FUA): writes are on non-volatile media when completed,
+    /// reads are taken from non-volatile media instead of e.g. the volatile write cache.
+    pub force_unit_access: bool,
+    /// Access Latency hint of the Dataset Management field (DSM), e.g. to prioritize the command.
+    pub access_latency: AccessLatency,
+}
+
+impl IoOptions {
+    /// The bits of the options in command dword 12, the number of blocks is added by the caller.
+    fn cdw12(&self) -> u32 {
+        (self.limited_retry as u32) << 31 | (self.force_unit_access as u32) << 30
+    }
+
+    /// The Dataset Management field in command dword 13.
+    fn cdw13(&self) -> u32 {
+        (self.access_latency as u32) << 4
+    }
+}
+
+/// Access Latency (AL) of the Dataset Management field of Read and Write commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccessLatency {
+    /// No latency information provided.
+    #[default]
+    None = 0b00,
+    /// Longer latency acceptable.
+    Idle = 0b01,
+    /// Typical latency.
+    Normal = 0b10,
+    /// Smallest possible latency, i.e. a high-priority command.
+    Low = 0b11,
+}
+
+/// Threshold Type Select (THSEL) of the Temperature Threshold feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdKind {
+    /// An asynchronous event is reported when the temperature rises above the threshold.
+    OverTemperature = 0b00,
+    /// An asynchronous event is reported when the temperature falls below the threshold.
+    UnderTemperature = 0b01,
+}
+
+/// The number of I/O queues allocated by the controller, reported in dword 0 of the completion
+/// of Get or Set Features with the feature Number of Queues.
+/// Both are 0's based values of I/O queues only, the admin queue is not included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberOfQueues {
+    /// Number of I/O Submission Queues Allocated (NSQA)
+    pub submission_queues: u16,
+    /// Number of I/O Completion Queues Allocated (NCQA)
+    pub completion_queues: u16,
+}
+
+impl NumberOfQueues {
+    pub(crate) fn from_u32(dword_0: u32) -> Self {
+        Self {
+            submission_queues: dword_0 as u16,
+            completion_queues: (dword_0 >> 16) as u16,
+        }
+    }
+
+    /// The number of I/O queue pairs which can be created, at most 65535 as queue IDs are 16
+    /// bits wide and 0 is the one of the admin queue.
+    pub fn queue_pairs(&self) -> u16 {
+        self.submission_queues
+            .min(self.completion_queues)
+            .saturating_add(1)
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone)]
 pub(crate) struct IdentifyNamespace {
@@ -396,3 +845,38 @@ pub(crate) struct IdentifyNamespace {
     pub(crate) lba_formats_list: [u32; 64],                  // LBAF0, LBAF1, ... LBAF63
     pub(crate) vendor_specific: [u8; 3712],
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_options_are_encoded_in_dwords_12_and_13() {
+        let options = IoOptions {
+            limited_retry: true,
+            force_unit_access: true,
+            access_latency: AccessLatency::Low,
+        };
+        let command = NvmeCommand::io_write(1, 1, 0, 7, 0, 0, options);
+        assert_eq!({ command.cdw12 }, 1 << 31 | 1 << 30 | 7);
+        assert_eq!({ command.cdw13 }, 0b11 << 4);
+
+        let command = NvmeCommand::io_read(1, 1, 0, 7, 0, 0, IoOptions::default());
+        assert_eq!({ command.cdw12 }, 7);
+        assert_eq!({ command.cdw13 }, 0);
+
+        let limited_retry = IoOptions {
+            limited_retry: true,
+            ..Default::default()
+        };
+        let command = NvmeCommand::io_read(1, 1, 0, 0, 0, 0, limited_retry);
+        assert_eq!({ command.cdw12 }, 1 << 31);
+    }
+
+    #[test]
+    fn number_of_queues_are_0_based() {
+        assert_eq!(NumberOfQueues::from_u32(0).queue_pairs(), 1);
+        assert_eq!(NumberOfQueues::from_u32(0x0003_0007).queue_pairs(), 4);
+        assert_eq!(NumberOfQueues::from_u32(0xFFFF_FFFF).queue_pairs(), 0xFFFF);
+    }
+}
@@ -38,15 +38,38 @@ impl<T> Dma<T> {
         self.size
     }
 
+    /// # Safety
+    /// The returned `Dma` points to null and must not be accessed or deallocated.
     pub unsafe fn new_uninitialized() -> Dma<T> {
         Dma {
-            virtual_address: 0 as *mut T,
-            physical_address: 0 as *mut T,
+            virtual_address: core::ptr::null_mut(),
+            physical_address: core::ptr::null_mut(),
             number_of_elements: 0,
             size: 0,
         }
     }
 
+    /// A `Dma` of memory not allocated by an allocator, e.g. in a mapped BAR.
+    ///
+    /// # Safety
+    /// The memory must be valid for `number_of_elements` of `T` as long as the `Dma` is used
+    /// and must not be deallocated through it.
+    pub(crate) unsafe fn from_raw_parts(
+        virtual_address: *mut T,
+        physical_address: *mut T,
+        number_of_elements: usize,
+    ) -> Dma<T> {
+        Dma {
+            virtual_address,
+            physical_address,
+            number_of_elements,
+            size: core::mem::size_of::<T>() * number_of_elements,
+        }
+    }
+
+    /// The memory is requested with an alignment of `page_size`, which is sufficient for any `T`.
+    /// Allocators not honoring the alignment of the layout may return under-aligned memory,
+    /// use [`Dma::read_value`] and [`Dma::write_value`] where that matters.
     pub(crate) fn allocate<A: Allocator>(
         number_of_elements: usize,
         page_size: usize,
@@ -68,6 +91,71 @@ impl<T> Dma<T> {
         Ok(dma)
     }
 
+    /// Returns a reference to the element at `index` or `None` if it is out of bounds.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index < self.number_of_elements {
+            Some(unsafe { &*self.virtual_address.add(index) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the element at `index` or `None` if it is out of bounds.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index < self.number_of_elements {
+            Some(unsafe { &mut *self.virtual_address.add(index) })
+        } else {
+            None
+        }
+    }
+
+    /// Reads the first element without requiring the memory to be aligned for `T`.
+    pub fn read_value(&self) -> T
+    where
+        T: Copy,
+    {
+        assert!(self.number_of_elements > 0, "Index out of bounds");
+        unsafe { core::ptr::read_unaligned(self.virtual_address) }
+    }
+
+    /// Writes `value` to the first element without requiring the memory to be aligned for `T`.
+    pub fn write_value(&mut self, value: T)
+    where
+        T: Copy,
+    {
+        assert!(self.number_of_elements > 0, "Index out of bounds");
+        unsafe { core::ptr::write_unaligned(self.virtual_address, value) }
+    }
+
+    /// Whether the memory is physically contiguous, as the controller requires for queues
+    /// it accesses by their base address. Every page of `page_size` after the first one is
+    /// translated and compared with the address following the previous page.
+    pub(crate) fn is_physically_contiguous<A: Allocator>(
+        &self,
+        page_size: usize,
+        allocator: &A,
+    ) -> Result<bool, Error> {
+        let virtual_address = self.virtual_address as usize;
+        let first_page = virtual_address & !(page_size - 1);
+        let first_physical_page = self.physical_address as usize - (virtual_address - first_page);
+        let number_of_pages = (virtual_address - first_page + self.size).div_ceil(page_size);
+        for page_index in 1..number_of_pages {
+            let physical_address = allocator
+                .translate_virtual_to_physical((first_page + page_index * page_size) as *const u8)
+                .map_err(Error::TranslateVirtualToPhysical)?
+                as usize;
+            if physical_address != first_physical_page + page_index * page_size {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Sets every byte of the allocated memory to 0.
+    pub(crate) fn zero(&mut self) {
+        unsafe { core::ptr::write_bytes(self.virtual_address as *mut u8, 0, self.size) };
+    }
+
     pub(crate) fn deallocate<A: Allocator>(self, allocator: &A) -> Result<(), Error> {
         let slice =
             core::ptr::slice_from_raw_parts_mut(self.virtual_address, self.number_of_elements);
@@ -75,8 +163,82 @@ impl<T> Dma<T> {
     }
 }
 
-unsafe impl<T> Send for Dma<T> {}
-unsafe impl<T> Sync for Dma<T> {}
+impl Dma<u8> {
+    /// A `Dma` of the first `size` bytes of this one, to transfer only part of it.
+    ///
+    /// # Safety
+    /// The returned `Dma` shares the memory: it must not be deallocated, outlive this one
+    /// or be accessed while this one is.
+    pub(crate) unsafe fn prefix(&self, size: usize) -> Dma<u8> {
+        let size = size.min(self.size);
+        Dma {
+            virtual_address: self.virtual_address,
+            physical_address: self.physical_address,
+            number_of_elements: size,
+            size,
+        }
+    }
+
+    /// A `Dma` of the `size` bytes at `offset` into this one, to transfer into part of it.
+    /// The physical address of the region is translated, as the memory is only physically
+    /// contiguous within a page of `page_size`.
+    ///
+    /// # Safety
+    /// The returned `Dma` shares the memory: it must not be deallocated, outlive this one
+    /// or be accessed while this one is.
+    pub(crate) unsafe fn region<A: Allocator>(
+        &self,
+        offset: usize,
+        size: usize,
+        page_size: usize,
+        allocator: &A,
+    ) -> Result<Dma<u8>, Error> {
+        if offset.checked_add(size).is_none_or(|end| end > self.size) {
+            return Err(Error::MemoryAccessOutOfBounds);
+        }
+        let virtual_address = self.virtual_address as usize + offset;
+        let first_page_end = (self.virtual_address as usize + 1).next_multiple_of(page_size);
+        let physical_address = if virtual_address < first_page_end {
+            self.physical_address as usize + offset
+        } else {
+            let page = virtual_address & !(page_size - 1);
+            allocator
+                .translate_virtual_to_physical(page as *const u8)
+                .map_err(Error::TranslateVirtualToPhysical)? as usize
+                + (virtual_address - page)
+        };
+        Ok(Dma {
+            virtual_address: virtual_address as *mut u8,
+            physical_address: physical_address as *mut u8,
+            number_of_elements: size,
+            size,
+        })
+    }
+
+    /// Returns the bytes in `range` or an error if the range is out of bounds,
+    /// instead of panicking like indexing does.
+    pub fn try_slice(&self, range: Range<usize>) -> Result<&[u8], Error> {
+        if range.start > range.end || range.end > self.number_of_elements {
+            return Err(Error::MemoryAccessOutOfBounds);
+        }
+        Ok(&self[range])
+    }
+
+    /// Returns the bytes in `range` mutably or an error if the range is out of bounds,
+    /// instead of panicking like indexing does.
+    pub fn try_slice_mut(&mut self, range: Range<usize>) -> Result<&mut [u8], Error> {
+        if range.start > range.end || range.end > self.number_of_elements {
+            return Err(Error::MemoryAccessOutOfBounds);
+        }
+        Ok(&mut self[range])
+    }
+}
+
+// SAFETY: `Dma` exclusively owns the memory it points to, like a `Box<[T]>`,
+// so it can be sent or shared between threads whenever `T` can.
+// The physical address is only handed to the controller and never dereferenced.
+unsafe impl<T: Send> Send for Dma<T> {}
+unsafe impl<T: Sync> Sync for Dma<T> {}
 
 impl<T> Deref for Dma<T> {
     type Target = T;
@@ -170,3 +332,72 @@ impl IndexMut<RangeFull> for Dma<u8> {
         &mut self[0..len]
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::Allocator;
+    use alloc::boxed::Box;
+    use core::alloc::Layout;
+    use core::cell::Cell;
+
+    /// Alignment of every allocation of [`TestAllocator`], the page size of the tests.
+    const ALIGNMENT: usize = 4096;
+
+    /// Offset of the "physical" addresses of [`TestAllocator`] from the virtual ones,
+    /// so PRPs and SGLs holding virtual addresses are noticed.
+    pub(crate) const PHYSICAL_OFFSET: usize = 1 << 40;
+
+    /// Allocates page aligned heap memory for unit tests.
+    /// Fails every allocation once `allocations_left` is used up.
+    pub(crate) struct TestAllocator {
+        /// Allocations succeeding before every further one fails, `None` for no limit.
+        pub(crate) allocations_left: Cell<Option<usize>>,
+        /// Number of allocations not deallocated yet.
+        pub(crate) live_allocations: Cell<usize>,
+    }
+
+    impl TestAllocator {
+        pub(crate) fn new() -> Self {
+            Self {
+                allocations_left: Cell::new(None),
+                live_allocations: Cell::new(0),
+            }
+        }
+
+        fn layout(size: usize) -> Layout {
+            Layout::from_size_align(size.max(1), ALIGNMENT).unwrap()
+        }
+    }
+
+    impl Allocator for TestAllocator {
+        fn allocate<T>(&self, layout: Layout) -> Result<*mut [T], Box<dyn core::error::Error>> {
+            assert!(layout.align() <= ALIGNMENT);
+            if let Some(allocations_left) = self.allocations_left.get() {
+                if allocations_left == 0 {
+                    return Err("out of test memory".into());
+                }
+                self.allocations_left.set(Some(allocations_left - 1));
+            }
+            let ptr = unsafe { alloc::alloc::alloc_zeroed(Self::layout(layout.size())) };
+            if ptr.is_null() {
+                return Err("heap allocation failed".into());
+            }
+            self.live_allocations.set(self.live_allocations.get() + 1);
+            Ok(core::ptr::slice_from_raw_parts_mut(ptr, layout.size()) as *mut [T])
+        }
+
+        fn deallocate<T>(&self, slice: *mut [T]) -> Result<(), Box<dyn core::error::Error>> {
+            let size = slice.len() * core::mem::size_of::<T>();
+            unsafe { alloc::alloc::dealloc(slice as *mut u8, Self::layout(size)) };
+            self.live_allocations.set(self.live_allocations.get() - 1);
+            Ok(())
+        }
+
+        fn translate_virtual_to_physical<T>(
+            &self,
+            virtual_address: *const T,
+        ) -> Result<*const T, Box<dyn core::error::Error>> {
+            Ok((virtual_address as usize + PHYSICAL_OFFSET) as *const T)
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use crate::cmd::FeatureIdentifier;
+use crate::lba::LbaRange;
 use crate::nvme::NamespaceId;
 use crate::queue_pairs::IoQueuePairId;
+use crate::status::{CompletionStatus, StatusCode};
 use alloc::boxed::Box;
 use alloc::string::String;
 use core::fmt;
@@ -20,23 +23,102 @@ pub enum Error {
     PageSizeMoreThanControllerMaximum(usize, u64),
     PageSizeNotAPowerOfTwo(usize),
     ControllerTypeInvalid(String),
+    ControllerConfigurationRejected(u32, u32),
     NamespaceDoesNotExist(NamespaceId),
+    InvalidLbaFormat(u8),
+    InvalidProtectionInformationType(u8),
+    OpcodeNotVendorSpecific(u8),
     NumberOfQueueEntriesLessThanTwo(u32),
     NumberOfQueueEntriesMoreThanMaximum(u32, u32),
     MaximumNumberOfQueuesReached,
     IoQueuePairDoesNotExist(IoQueuePairId),
     MemoryAccessOutOfBounds,
     UnixPciError(Box<dyn core::error::Error>),
+    CharDeviceError(Box<dyn core::error::Error>),
     VirtualAddressIsNotDwordAligned(usize),
+    VirtualAddressIsNotQwordAligned(usize),
     VirtualAddressIsNotPageAligned(usize),
+    PhysicalAddressIsNotPageAligned(usize),
     NumberOfElementsIsZero,
+    NumberOfBlocksIsZero,
+    NumberOfBlocksMoreThanMaximum(u64, u32),
+    LbaRangeOutOfBounds(LbaRange, u64),
     BufferLengthBiggerThanMaximumTransferSize(usize, usize),
     BufferLengthNotAMultipleOfNamespaceBlockSize(usize, u64),
-    IoCompletionQueueFailure(u16),
+    /// Queue ID (0 for the admin queue), command ID and status of the failed command.
+    IoCompletionQueueFailure(IoQueuePairId, u16, CompletionStatus),
+    FormatInProgress,
+    FormatTimeout(NamespaceId),
     SubmissionQueueFull,
     CompletionQueueCompletionFailure,
+    CompletionQueueOverflow(usize),
+    ControllerProcessingPaused,
+    ControllerFatalStatus,
     PrpContainerAlreadyExists(u16),
-    PrpMultipleNotSupported, // FIXME: remove error once supported
+    NoFreeCommandId(usize),
+    PrpListDoesNotDescribeBuffer,
+    DeallocateNotSupported,
+    BootPartitionsNotSupported,
+    BootPartitionIdInvalid(u8),
+    BootPartitionReadSizeInvalid(usize, usize),
+    BootPartitionReadFailed(u8),
+    BootPartitionReadTimeout(u8),
+    BootPartitionBufferNotPhysicallyContiguous(usize),
+    AnaReportingNotSupported,
+    ReadRecoveryLevelNotSupported(u8),
+    DatasetManagementNotSupported,
+    TooManyDatasetRanges(usize),
+    FeatureNotSupported(FeatureIdentifier),
+    ShutdownTimeout,
+    ControllerReadyTimeout(bool),
+    WriteZeroesNotSupported,
+    TelemetryNotSupported,
+    IoQueuePairsRemaining(usize),
+    CompletionTimeout(u16),
+    DrainTimeout(IoQueuePairId),
+    InterruptsNotEnabled(IoQueuePairId),
+    InterruptsNotSupported,
+    InterruptError(Box<dyn core::error::Error>),
+    VfioError(Box<dyn core::error::Error>),
+    ControllerMemoryBufferNotAvailable,
+    ControllerMemoryBufferQueueNotSupported(bool),
+    ControllerMemoryBufferFull(usize),
+    CompareNotSupported,
+    VerifyNotSupported,
+    CopyNotSupported,
+    NoCopySourceRanges,
+    TooManyCopySourceRanges(usize, u16),
+    CopySourceRangeTooLong(u32, u16),
+    CopyLengthTooLong(u64, u32),
+    AsyncEventRequestsNotSupported,
+    AsyncEventRequestLimitExceeded(u16),
+    CompletionQueueInvalid(IoQueuePairId),
+    InvalidQueueIdentifier(IoQueuePairId),
+    InvalidQueueSize(IoQueuePairId, u32),
+    InvalidInterruptVector(IoQueuePairId, u16),
+    FeatureNotChangeable(FeatureIdentifier),
+    PatternIsEmpty,
+    TemperatureSensorInvalid(u8),
+    KeepAliveNotSupported,
+    AbortCommandLimitExceeded(u16),
+    DeallocatedOrUnwrittenBlock(IoQueuePairId, u16),
+    DeallocatedOrUnwrittenBlockErrorNotSupported(NamespaceId),
+    QueueNotPhysicallyContiguous(usize),
+}
+
+impl Error {
+    /// The error for a completion with the non-zero `status` (without the phase tag)
+    /// of the command with `command_id` submitted to the queue with `queue_id`.
+    pub(crate) fn from_status(queue_id: IoQueuePairId, command_id: u16, status: u16) -> Self {
+        let status = CompletionStatus::from_u16(status);
+        match status.code {
+            StatusCode::FormatInProgress => Error::FormatInProgress,
+            StatusCode::DeallocatedOrUnwrittenLogicalBlock => {
+                Error::DeallocatedOrUnwrittenBlock(queue_id, command_id)
+            }
+            _ => Error::IoCompletionQueueFailure(queue_id, command_id, status),
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -82,7 +164,20 @@ impl fmt::Display for Error {
             Error::ControllerTypeInvalid(type_name) => write!(f,
                 "The controller type is not \"I/O controller\" but instead \"{type_name}\"."
             ),
+            Error::ControllerConfigurationRejected(written, read) => write!(f,
+                "The controller did not accept the configuration: \
+                wrote 0x{written:08X} to the controller configuration register (CC) but read back 0x{read:08X}."
+            ),
             Error::NamespaceDoesNotExist(id) => write!(f, "The namespace with ID {} does not exist", id.0),
+            Error::InvalidLbaFormat(index) => write!(f,
+                "The LBA format with index {index} is not supported by the namespace."
+            ),
+            Error::InvalidProtectionInformationType(protection_information) => write!(f,
+                "The protection information type {protection_information} is not 0 to 3."
+            ),
+            Error::OpcodeNotVendorSpecific(opcode) => write!(f,
+                "The opcode 0x{opcode:02X} is not in the vendor specific range."
+            ),
             Error::NumberOfQueueEntriesLessThanTwo(entries) => write!(f,
                 "The number of queue entries ({entries}) must not be smaller than 2."
             ),
@@ -94,34 +189,213 @@ impl fmt::Display for Error {
             Error::IoQueuePairDoesNotExist(id) => write!(f, "The I/O queue pair with ID {} does not exist", id.0),
             Error::MemoryAccessOutOfBounds => write!(f, "Memory access out of bounds."),
             Error::UnixPciError(error) => write!(f, "{error}"),
+            Error::CharDeviceError(error) => write!(f, "Character device error: {error}."),
             Error::VirtualAddressIsNotDwordAligned(address) => write!(f,
                 "The virtual address {address:X} is not dword aligned."
             ),
+            Error::VirtualAddressIsNotQwordAligned(address) => write!(f,
+                "The virtual address {address:X} is not qword aligned."
+            ),
             Error::VirtualAddressIsNotPageAligned(address) => write!(f,
                 "The virtual address {address:X} is not page aligned."
             ),
+            Error::PhysicalAddressIsNotPageAligned(address) => write!(f,
+                "The physical address {address:X} is not 4 KiB page aligned."
+            ),
             Error::NumberOfElementsIsZero => write!(f,
                 "The provided number of elements is 0."
             ),
+            Error::NumberOfBlocksIsZero => write!(f,
+                "The provided number of logical blocks is 0."
+            ),
+            Error::NumberOfBlocksMoreThanMaximum(blocks, maximum) => write!(f,
+                "The number of logical blocks ({blocks}) is bigger than the maximum of {maximum} blocks per command."
+            ),
+            Error::LbaRangeOutOfBounds(range, blocks) => write!(f,
+                "The logical block range {:X}..{:X} exceeds the namespace capacity of {blocks:X} blocks.",
+                range.start,
+                range.end()
+            ),
             Error::BufferLengthBiggerThanMaximumTransferSize(buffer_length, maximum_transfer_size) => write!(f,
                 "The buffer length ({buffer_length:X}) is bigger than the maximum transfer size ({maximum_transfer_size:X})."
             ),
             Error::BufferLengthNotAMultipleOfNamespaceBlockSize(buffer_length, block_size) => write!(f,
                 "The buffer length ({buffer_length:X}) is not a multiple of the namespace block size ({block_size:X})."
             ),
-            Error::IoCompletionQueueFailure(status) => write!(f,
-                "I/O completion queue failed with status code 0x{:X} and type 0x{:X}",
-                status & 0xFF,
-                (status >> 8) & 0x7
+            Error::IoCompletionQueueFailure(queue_id, command_id, status) => write!(f,
+                "Command {command_id} on queue {} failed with {status}",
+                queue_id.0
+            ),
+            Error::FormatInProgress => write!(f,
+                "A format of the namespace is in progress."
+            ),
+            Error::FormatTimeout(namespace_id) => write!(f,
+                "The format of the namespace with ID {} did not complete within the timeout.",
+                namespace_id.0
             ),
             Error::SubmissionQueueFull => write!(f, "The submission queue is full."),
             Error::CompletionQueueCompletionFailure => write!(f,
                 "The completion queue could not complete the command."
             ),
+            Error::CompletionQueueOverflow(depth) => write!(f,
+                "The number of outstanding completions would exceed the completion queue depth ({depth})."
+            ),
+            Error::ControllerFatalStatus => write!(f,
+                "The controller reports a fatal status (CSTS.CFS), it needs to be reset."
+            ),
+            Error::ControllerProcessingPaused => write!(f,
+                "The controller paused processing commands (CSTS.PP); \
+                the command is still outstanding and may complete later."
+            ),
             Error::PrpContainerAlreadyExists(command_id) => write!(f,
                 "PRP container already exists for the command ID {command_id}."
             ),
-            Error::PrpMultipleNotSupported => write!(f, "The buffer is bigger than the currently supported maximum of 2 pages."),
+            Error::NoFreeCommandId(number_of_ids) => write!(f,
+                "All {number_of_ids} command IDs are in use by commands not completed yet."
+            ),
+            Error::PrpListDoesNotDescribeBuffer => write!(f,
+                "The PRP list was not built for the provided buffer."
+            ),
+            Error::DeallocateNotSupported => write!(f,
+                "The controller does not support deallocating blocks with Write Zeroes for this namespace."
+            ),
+            Error::BootPartitionsNotSupported => write!(f,
+                "The controller does not support boot partitions."
+            ),
+            Error::BootPartitionIdInvalid(partition) => write!(f,
+                "The boot partition ID {partition} is invalid, only 0 and 1 exist."
+            ),
+            Error::BootPartitionReadSizeInvalid(size, partition_size) => write!(f,
+                "The boot partition read size ({size:X}) must be a non-zero multiple of 4 KiB, \
+                at most 0x3FF000 and not bigger than the boot partition ({partition_size:X})."
+            ),
+            Error::BootPartitionReadFailed(partition) => write!(f,
+                "Reading the boot partition {partition} failed."
+            ),
+            Error::BootPartitionBufferNotPhysicallyContiguous(size) => write!(f,
+                "The boot partition buffer of {size} bytes is not physically contiguous - huge pages enabled?"
+            ),
+            Error::BootPartitionReadTimeout(partition) => write!(f,
+                "Reading the boot partition {partition} did not complete within the controller timeout."
+            ),
+            Error::AnaReportingNotSupported => write!(f,
+                "The controller does not support Asymmetric Namespace Access reporting."
+            ),
+            Error::ReadRecoveryLevelNotSupported(level) => write!(f,
+                "The controller does not support the read recovery level {level}."
+            ),
+            Error::DatasetManagementNotSupported => write!(f,
+                "The controller does not support the Dataset Management command."
+            ),
+            Error::TooManyDatasetRanges(number_of_ranges) => write!(f,
+                "{number_of_ranges} ranges exceed the maximum of 256 ranges per Dataset Management command."
+            ),
+            Error::FeatureNotSupported(feature_id) => write!(f,
+                "The controller does not support the feature {feature_id:?}."
+            ),
+            Error::ShutdownTimeout => write!(f,
+                "The controller did not complete the shutdown within its timeout."
+            ),
+            Error::WriteZeroesNotSupported => write!(f,
+                "The controller does not support the Write Zeroes command."
+            ),
+            Error::TelemetryNotSupported => write!(f,
+                "The controller does not support the telemetry log pages."
+            ),
+            Error::IoQueuePairsRemaining(number) => write!(f,
+                "{number} I/O queue pairs were not deleted before the shutdown."
+            ),
+            Error::CompletionTimeout(command_id) => write!(f,
+                "Command {command_id} did not complete within the spin budget."
+            ),
+            Error::DrainTimeout(queue_id) => write!(f,
+                "The commands of the queue with ID {} did not complete within the timeout.",
+                queue_id.0
+            ),
+            Error::InterruptsNotEnabled(queue_id) => write!(f,
+                "Interrupts are not enabled for the I/O queue pair with ID {}.",
+                queue_id.0
+            ),
+            Error::InterruptsNotSupported => write!(f,
+                "MSI-X interrupts are only supported for devices opened through VFIO."
+            ),
+            Error::InterruptError(error) => write!(f, "Interrupt error: {error}."),
+            Error::VfioError(error) => write!(f, "VFIO error: {error}."),
+            Error::ControllerMemoryBufferNotAvailable => write!(f,
+                "The controller has no controller memory buffer in the mapped BAR or it is not enabled."
+            ),
+            Error::ControllerMemoryBufferQueueNotSupported(submission) => write!(f,
+                "The controller memory buffer does not support {} queues.",
+                if *submission { "submission" } else { "completion" }
+            ),
+            Error::ControllerMemoryBufferFull(size) => write!(f,
+                "The controller memory buffer has no room for a queue of {size} bytes."
+            ),
+            Error::CompareNotSupported => write!(f,
+                "The controller does not support the Compare command."
+            ),
+            Error::VerifyNotSupported => write!(f,
+                "The controller does not support the Verify command."
+            ),
+            Error::CopyNotSupported => write!(f,
+                "The controller does not support the Copy command."
+            ),
+            Error::NoCopySourceRanges => write!(f,
+                "A Copy command needs at least one source range."
+            ),
+            Error::TooManyCopySourceRanges(number_of_ranges, maximum) => write!(f,
+                "{number_of_ranges} source ranges exceed the maximum of {maximum} ranges per Copy command (MSRC)."
+            ),
+            Error::CopySourceRangeTooLong(blocks, maximum) => write!(f,
+                "The source range of {blocks} blocks exceeds the maximum of {maximum} blocks per range (MSSRL)."
+            ),
+            Error::CopyLengthTooLong(blocks, maximum) => write!(f,
+                "The copy of {blocks} blocks exceeds the maximum of {maximum} blocks per Copy command (MCL)."
+            ),
+            Error::AsyncEventRequestsNotSupported => write!(f,
+                "Asynchronous Event Requests require the admin queue of this crate, the kernel driver handles them for the character device."
+            ),
+            Error::AsyncEventRequestLimitExceeded(limit) => write!(f,
+                "The controller supports at most {limit} outstanding Asynchronous Event Requests (AERL)."
+            ),
+            Error::CompletionQueueInvalid(queue_id) => write!(f,
+                "The completion queue of the I/O submission queue with ID {} does not exist.", queue_id.0
+            ),
+            Error::InvalidQueueIdentifier(queue_id) => write!(f,
+                "The controller rejected the I/O queue ID {}, it is in use or exceeds the number of queues allocated.", queue_id.0
+            ),
+            Error::InvalidQueueSize(queue_id, entries) => write!(f,
+                "The controller rejected {entries} entries for the I/O queues with ID {}, the size must be between 2 and the maximum queue size (MQES).", queue_id.0
+            ),
+            Error::InvalidInterruptVector(queue_id, vector) => write!(f,
+                "The controller rejected the interrupt vector {vector} for the I/O completion queue with ID {}.", queue_id.0
+            ),
+            Error::FeatureNotChangeable(feature_id) => write!(f,
+                "The controller does not allow changing the feature {feature_id:?}."
+            ),
+            Error::PatternIsEmpty => write!(f, "The pattern to write is empty."),
+            Error::TemperatureSensorInvalid(sensor) => write!(f,
+                "The temperature sensor {sensor} is invalid, only the composite temperature (0) and sensors 1 to 8 have thresholds."
+            ),
+            Error::KeepAliveNotSupported => write!(f,
+                "The controller does not support Keep Alive (KAS)."
+            ),
+            Error::AbortCommandLimitExceeded(limit) => write!(f,
+                "The controller supports at most {limit} outstanding Abort commands (ACL)."
+            ),
+            Error::DeallocatedOrUnwrittenBlock(queue_id, command_id) => write!(f,
+                "The command with ID {command_id} on the queue with ID {} read deallocated or unwritten blocks.", queue_id.0
+            ),
+            Error::DeallocatedOrUnwrittenBlockErrorNotSupported(namespace_id) => write!(f,
+                "The namespace with ID {} does not support the Deallocated or Unwritten Logical Block error.", namespace_id.0
+            ),
+            Error::QueueNotPhysicallyContiguous(entries) => write!(f,
+                "The memory of a queue with {entries} entries is not physically contiguous - huge pages enabled?"
+            ),
+            Error::ControllerReadyTimeout(ready) => write!(f,
+                "The controller did not become {} within its timeout.",
+                if *ready { "ready" } else { "not ready" }
+            ),
         }
     }
 }
@@ -17,7 +17,7 @@ pub struct HugePageAllocator;
 impl Allocator for HugePageAllocator {
     fn allocate<T>(&self, layout: core::alloc::Layout) -> Result<*mut [T], Box<dyn Error>> {
         let size = layout.size();
-        let size = if size % HUGE_PAGE_SIZE != 0 {
+        let size = if !size.is_multiple_of(HUGE_PAGE_SIZE) {
             ((size >> HUGE_PAGE_BITS) + 1) << HUGE_PAGE_BITS
         } else {
             size
@@ -68,23 +68,62 @@ impl Allocator for HugePageAllocator {
         &self,
         virtual_address: *const T,
     ) -> Result<*const T, Box<dyn Error>> {
-        let pagesize = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        translate_virtual_to_physical(virtual_address)
+    }
+}
 
-        let mut file = fs::OpenOptions::new()
-            .read(true)
-            .open("/proc/self/pagemap")?;
+/// Whether a huge page can be mapped, trying it with a file that is removed again.
+pub(crate) fn huge_pages_available() -> bool {
+    let path = format!("/mnt/huge/nvme-probe-{}", process::id());
+    let Ok(file) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+    else {
+        return false;
+    };
+    let ptr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            HUGE_PAGE_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_HUGETLB,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    let available = ptr != libc::MAP_FAILED;
+    if available {
+        unsafe { libc::munmap(ptr, HUGE_PAGE_SIZE) };
+    }
+    drop(file);
+    let _ = fs::remove_file(&path);
+    available
+}
 
-        file.seek(io::SeekFrom::Start(
-            (virtual_address as usize / pagesize * mem::size_of::<usize>()) as u64,
-        ))?;
+/// Translates the `virtual_address` of this process with the page map of the kernel,
+/// which requires root privileges (`CAP_SYS_ADMIN`).
+pub(crate) fn translate_virtual_to_physical<T>(
+    virtual_address: *const T,
+) -> Result<*const T, Box<dyn Error>> {
+    let pagesize = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
 
-        let mut buffer = [0; mem::size_of::<usize>()];
-        file.read_exact(&mut buffer)?;
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .open("/proc/self/pagemap")?;
 
-        let phys = usize::from_ne_bytes(buffer);
-        Ok(
-            ((phys & 0x007F_FFFF_FFFF_FFFF) * pagesize + virtual_address as usize % pagesize)
-                as *const T,
-        )
-    }
+    file.seek(io::SeekFrom::Start(
+        (virtual_address as usize / pagesize * mem::size_of::<usize>()) as u64,
+    ))?;
+
+    let mut buffer = [0; mem::size_of::<usize>()];
+    file.read_exact(&mut buffer)?;
+
+    let phys = usize::from_ne_bytes(buffer);
+    Ok(
+        ((phys & 0x007F_FFFF_FFFF_FFFF) * pagesize + virtual_address as usize % pagesize)
+            as *const T,
+    )
 }
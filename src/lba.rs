@@ -0,0 +1,62 @@
+use crate::error::Error;
+use crate::nvme::Namespace;
+
+/// A range of logical blocks starting at the logical block address `start`.
+/// `count` is always the actual number of blocks (1-based);
+/// the conversion to the 0-based "Number of Logical Blocks (NLB)" wire format happens internally.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LbaRange {
+    pub start: u64,
+    pub count: u32,
+}
+
+impl LbaRange {
+    pub fn new(start: u64, count: u32) -> Self {
+        Self { start, count }
+    }
+
+    /// The first logical block address after the range (exclusive end),
+    /// saturated at `u64::MAX` for ranges reaching past the last representable address.
+    pub fn end(&self) -> u64 {
+        self.start.saturating_add(self.count as u64)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn overlaps(&self, other: &LbaRange) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.start < other.end()
+            && other.start < self.end()
+    }
+
+    pub fn contains(&self, logical_block_address: u64) -> bool {
+        self.start <= logical_block_address && logical_block_address < self.end()
+    }
+
+    /// Whether every block of the range lies within the capacity of the `namespace`.
+    pub fn is_within(&self, namespace: &Namespace) -> bool {
+        self.start
+            .checked_add(self.count as u64)
+            .is_some_and(|end| end <= namespace.blocks)
+    }
+
+    /// The number of blocks in the 0-based 16 bit format used by
+    /// read, write, write zeroes, verify, compare and the copy source ranges.
+    pub(crate) fn number_of_blocks_zero_based(&self) -> Result<u16, Error> {
+        if self.is_empty() {
+            return Err(Error::NumberOfBlocksIsZero);
+        }
+        let maximum = u16::MAX as u32 + 1;
+        if self.count > maximum {
+            return Err(Error::NumberOfBlocksMoreThanMaximum(
+                self.count as u64,
+                maximum,
+            ));
+        }
+        Ok((self.count - 1) as u16)
+    }
+}
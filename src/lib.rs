@@ -1,31 +1,119 @@
 #![no_std]
 #![cfg_attr(target_arch = "aarch64", feature(stdarch_arm_hints))]
+mod ana;
+mod async_event;
+#[cfg(feature = "std")]
+mod char_device;
+mod cmb;
 mod cmd;
 mod dma;
 mod error;
 #[cfg(feature = "std")]
 mod huge_pages;
+mod lba;
+#[cfg(feature = "std")]
+mod namespace_io;
 mod nvme;
 #[cfg(feature = "std")]
+mod page_allocator;
+#[cfg(feature = "std")]
 mod pci;
+mod power_state;
 mod prp;
 mod queue_pairs;
 mod queues;
+mod sgl;
+mod smart;
+mod status;
+mod telemetry;
+mod timeout;
+#[cfg(feature = "std")]
+mod vfio;
+mod zns;
 
 extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
+pub use ana::{AnaGroupDescriptor, AnaLog, AnaState};
+pub use async_event::{AsyncEvent, AsyncEventLog, AsyncEventType, ErrorInformationEntry};
+pub use cmb::{ControllerMemoryBuffer, QueuePlacement};
+pub use cmd::{
+    AccessLatency, FeatureCaps, FeatureIdentifier, IoOptions, NumberOfQueues, ThresholdKind,
+};
 pub use dma::{Allocator, Dma};
 pub use error::Error;
 #[cfg(feature = "std")]
 pub use huge_pages::{HugePageAllocator, HUGE_PAGE_SIZE};
-pub use nvme::{ControllerInformation, Namespace, NamespaceId, NvmeDevice};
+pub use lba::LbaRange;
+#[cfg(feature = "std")]
+pub use namespace_io::NamespaceIo;
+pub use nvme::{
+    BufferPlan, ControllerConfiguration, ControllerInformation, HostBehavior,
+    IoCommandSetCombination, LbaFormat, MetadataSettings, Namespace, NamespaceDetails, NamespaceId,
+    NvmeConfig, NvmeDevice, OptionalNvmCommandSupport, SecureEraseSetting, ShutdownError,
+    ShutdownType,
+};
+#[cfg(feature = "std")]
+pub use page_allocator::{DefaultAllocator, PageAllocator, StdAllocator};
+pub use power_state::PowerStateDescriptor;
+pub use prp::PrpList;
 pub use queue_pairs::{IoQueuePair, IoQueuePairId};
+pub use sgl::SglSupport;
+pub use smart::{CriticalWarnings, SmartHealthLog};
+pub use status::{CompletionStatus, StatusCode, StatusCodeType};
+pub use telemetry::{Telemetry, TelemetryHeader};
+#[cfg(feature = "std")]
+pub use vfio::VfioAllocator;
+pub use zns::{ZoneDescriptor, ZoneState, ZonedNamespace};
+
+/// Returns the PCI addresses of all NVMe devices on the system.
+/// This includes devices still bound to a kernel driver, see [`bound_driver`].
+#[cfg(feature = "std")]
+pub fn list_nvme_devices() -> Result<alloc::vec::Vec<alloc::string::String>, Error> {
+    pci::list_devices(pci::NVME_CLASS_ID).map_err(Error::UnixPciError)
+}
+
+/// Returns the name of the kernel driver bound to the device at `pci_address`, if any.
+/// Opening the device unbinds this driver.
+#[cfg(feature = "std")]
+pub fn bound_driver(pci_address: &str) -> Result<Option<alloc::string::String>, Error> {
+    pci::bound_driver(pci_address).map_err(Error::UnixPciError)
+}
+
+/// Binds the kernel `driver` (e.g. `"nvme"`) to the device at `pci_address`.
+#[cfg(feature = "std")]
+pub fn bind_driver(pci_address: &str, driver: &str) -> Result<(), Error> {
+    pci::bind_driver(pci_address, driver).map_err(Error::UnixPciError)
+}
 
 #[cfg(feature = "std")]
 pub fn new_pci_and_huge(pci_address: &str) -> Result<NvmeDevice<HugePageAllocator>, Error> {
     let allocator = HugePageAllocator {};
-    let nvme = NvmeDevice::from_pci_address(pci_address, HUGE_PAGE_SIZE, allocator)?;
+    let config = NvmeConfig {
+        page_size: HUGE_PAGE_SIZE,
+        ..Default::default()
+    };
+    let nvme = NvmeDevice::from_pci_address(pci_address, config, allocator)?;
     Ok(nvme)
 }
+
+/// Opens the NVMe device at `pci_address` with defaults that work on most systems:
+/// - memory is allocated from huge pages if available, from locked pages otherwise
+///   (see [`DefaultAllocator`]),
+/// - the memory page size of the controller is 4 KiB, or its minimum if it is larger,
+/// - the number of I/O queue pairs is the one allocated by the controller.
+#[cfg(feature = "std")]
+pub fn open(pci_address: &str) -> Result<NvmeDevice<DefaultAllocator>, Error> {
+    let config = NvmeConfig::default();
+    match NvmeDevice::from_pci_address(pci_address, config, DefaultAllocator::new()) {
+        Err(Error::PageSizeLessThanControllerMinimum(_, minimum_memory_page_size)) => {
+            let config = NvmeConfig {
+                page_size: minimum_memory_page_size as usize,
+                ..config
+            };
+            NvmeDevice::from_pci_address(pci_address, config, DefaultAllocator::new())
+        }
+        result => result,
+    }
+}
@@ -0,0 +1,149 @@
+use crate::dma::{Allocator, Dma};
+use crate::error::Error;
+use crate::queue_pairs::IoQueuePair;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::string::ToString;
+
+/// Size of the bounce buffer in bytes, if the maximum transfer size allows it.
+const BOUNCE_BUFFER_SIZE: usize = 128 * 1024;
+
+/// Byte oriented access to the namespace of an I/O queue pair through
+/// [`std::io::Read`], [`std::io::Write`] and [`std::io::Seek`].
+/// Data is transferred through a bounce buffer; writes not covering whole blocks
+/// read the blocks first (read-modify-write).
+/// The namespace is flushed when dropped, use [`NamespaceIo::into_inner`] to get the
+/// queue pair back.
+#[derive(Debug)]
+pub struct NamespaceIo<A: Allocator> {
+    // both are only taken by `into_inner`
+    io_queue_pair: Option<IoQueuePair<A>>,
+    buffer: Option<Dma<u8>>,
+    position: u64,
+}
+
+impl<A: Allocator> NamespaceIo<A> {
+    pub fn new(io_queue_pair: IoQueuePair<A>) -> Result<Self, Error> {
+        let block_size = io_queue_pair.namespace.block_size as usize;
+        let size = io_queue_pair
+            .maximum_transfer_size
+            .map_or(BOUNCE_BUFFER_SIZE, |maximum| {
+                BOUNCE_BUFFER_SIZE.min(maximum)
+            });
+        // at least one block, allocations are rounded up to whole blocks
+        let buffer =
+            io_queue_pair.allocate_buffer::<u8>((size / block_size).max(1) * block_size)?;
+        Ok(Self {
+            io_queue_pair: Some(io_queue_pair),
+            buffer: Some(buffer),
+            position: 0,
+        })
+    }
+
+    /// Flush the namespace and return the queue pair.
+    pub fn into_inner(mut self) -> Result<IoQueuePair<A>, Error> {
+        let mut io_queue_pair = self.io_queue_pair.take().expect("taken only once");
+        let buffer = self.buffer.take().expect("taken only once");
+        let result = io_queue_pair.flush();
+        io_queue_pair.deallocate_buffer(buffer)?;
+        result.map(|_| io_queue_pair)
+    }
+
+    /// The size of the namespace in bytes.
+    pub fn size(&self) -> u64 {
+        let namespace = &self
+            .io_queue_pair
+            .as_ref()
+            .expect("taken only once")
+            .namespace;
+        namespace.blocks * namespace.block_size
+    }
+
+    /// Transfer the blocks covering up to `length` bytes at the current position through the
+    /// bounce buffer, `write`ing `data` if given, and return the offset of the position in
+    /// the buffer and the number of bytes covered.
+    fn transfer(&mut self, length: usize, data: Option<&[u8]>) -> Result<(usize, usize), Error> {
+        let size = self.size();
+        let io_queue_pair = self.io_queue_pair.as_mut().expect("taken only once");
+        let buffer = self.buffer.as_mut().expect("taken only once");
+        let block_size = io_queue_pair.namespace.block_size;
+        let logical_block_address = self.position / block_size;
+        let offset = (self.position % block_size) as usize;
+        let length = length
+            .min((size - self.position) as usize)
+            .min(buffer.size() - offset);
+        let transfer_size = (offset + length).next_multiple_of(block_size as usize);
+        // SAFETY: `buffer` is not accessed while `view` exists.
+        let mut view = unsafe { buffer.prefix(transfer_size) };
+        let partial = offset != 0 || !length.is_multiple_of(block_size as usize);
+        if data.is_none() || partial {
+            io_queue_pair.read(&mut view, logical_block_address)?;
+        }
+        if let Some(data) = data {
+            view[offset..offset + length].copy_from_slice(&data[..length]);
+            io_queue_pair.write(&view, logical_block_address)?;
+        }
+        Ok((offset, length))
+    }
+}
+
+impl<A: Allocator> Read for NamespaceIo<A> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.size() {
+            return Ok(0);
+        }
+        let (offset, length) = self.transfer(buf.len(), None).map_err(io_error)?;
+        let buffer = self.buffer.as_ref().expect("taken only once");
+        buf[..length].copy_from_slice(&buffer[offset..offset + length]);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl<A: Allocator> Write for NamespaceIo<A> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.size() {
+            return Ok(0);
+        }
+        let (_, length) = self.transfer(buf.len(), Some(buf)).map_err(io_error)?;
+        self.position += length as u64;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let io_queue_pair = self.io_queue_pair.as_mut().expect("taken only once");
+        io_queue_pair.flush().map_err(io_error)
+    }
+}
+
+impl<A: Allocator> Seek for NamespaceIo<A> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match position {
+            SeekFrom::Start(position) => (position, 0),
+            SeekFrom::End(offset) => (self.size(), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        self.position = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl<A: Allocator> Drop for NamespaceIo<A> {
+    fn drop(&mut self) {
+        if let (Some(mut io_queue_pair), Some(buffer)) =
+            (self.io_queue_pair.take(), self.buffer.take())
+        {
+            let _ = io_queue_pair.flush();
+            let _ = io_queue_pair.deallocate_buffer(buffer);
+        }
+    }
+}
+
+/// The crate errors are not `Send` and `Sync`, so only their message is kept.
+fn io_error(error: Error) -> io::Error {
+    io::Error::other(error.to_string())
+}
@@ -1,17 +1,70 @@
-use crate::cmd::{FeatureIdentifier, IdentifyNamespace, NvmeCommand, Select};
+use crate::ana::AnaLog;
+use crate::async_event::{
+    self, AsyncEvent, AsyncEventLog, AsyncEventType, ErrorInformationEntry,
+    ERROR_INFORMATION_ENTRY_SIZE, ERROR_INFORMATION_LOG_IDENTIFIER, OTHER_LOG_SIZE,
+};
+#[cfg(feature = "std")]
+use crate::char_device::CharDevice;
+use crate::cmb::{ControllerMemoryBuffer, MappedControllerMemoryBuffer, QueuePlacement};
+use crate::cmd::{
+    FeatureCaps, FeatureIdentifier, IdentifyNamespace, NumberOfQueues, NvmeCommand, Select,
+    ThresholdKind,
+};
 use crate::dma::{Allocator, Dma};
 use crate::error::Error;
+use crate::lba::LbaRange;
+#[cfg(feature = "std")]
+use crate::page_allocator::StdAllocator;
 #[cfg(feature = "std")]
 use crate::pci;
-use crate::queue_pairs::{AdminQueuePair, IoQueuePair, IoQueuePairId};
+use crate::power_state::{
+    PowerStateDescriptor, POWER_STATE_DESCRIPTORS_OFFSET, POWER_STATE_DESCRIPTOR_SIZE,
+};
+use crate::prp;
+use crate::queue_pairs::{AdminQueuePair, CommandIds, IoQueuePair, IoQueuePairId};
 use crate::queues::*;
+use crate::sgl::SglSupport;
+use crate::smart::{CriticalWarnings, SmartHealthLog, SMART_HEALTH_LOG_SIZE};
+use crate::status::StatusCode;
+use crate::telemetry::{
+    Telemetry, TelemetryHeader, CREATE_TELEMETRY_HOST_INITIATED_DATA, TELEMETRY_BLOCK_SIZE,
+    TELEMETRY_HOST_INITIATED_LOG_IDENTIFIER,
+};
+use crate::timeout::Deadline;
+#[cfg(feature = "std")]
+use crate::vfio::{self, VfioAllocator};
+use crate::zns::{self, ZonedNamespace};
 use ahash::RandomState;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::hint::spin_loop;
 use hashbrown::HashMap;
-use log::debug;
+use log::{debug, warn};
+
+/// Opcodes reserved for vendor specific admin commands.
+const VENDOR_SPECIFIC_ADMIN_OPCODES: core::ops::RangeInclusive<u8> = 0xC0..=0xFF;
+
+/// Log page identifier of the Asymmetric Namespace Access log page.
+const ANA_LOG_IDENTIFIER: u8 = 0x0C;
+
+/// Log page identifier of the SMART / Health Information log page.
+const SMART_HEALTH_LOG_IDENTIFIER: u8 = 0x02;
+
+/// Size of the I/O Command Set data structure: 512 combinations of 8 bytes.
+const IO_COMMAND_SET_DATA_SIZE: usize = 4096;
+
+/// Maximum number of entries of the admin queues, their sizes in AQA are 12 bit 0's based values.
+const ADMIN_QUEUE_MAXIMUM_ENTRIES: usize = 4096;
+
+/// Maximum number of free PRP list pages kept per I/O queue pair for reuse.
+const PRP_LIST_POOL_CAPACITY: usize = 32;
+
+/// Maximum number of identify namespace commands in flight while opening a device,
+/// each needs its own data buffer.
+const IDENTIFY_NAMESPACE_PIPELINE_DEPTH: usize = 16;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -23,9 +76,508 @@ pub struct Namespace {
     pub id: NamespaceId,
     pub blocks: u64,
     pub block_size: u64,
+    /// NGUID; all zeros if not reported by the controller.
+    pub namespace_globally_unique_identifier: [u8; 16],
+    /// EUI64; 0 if not reported by the controller.
+    pub ieee_extended_unique_identifier: u64,
+    /// Whether the controller supports the Deallocate bit in the Write Zeroes command
+    /// for this namespace (DLFEAT).
+    pub deallocate_in_write_zeroes_supported: bool,
+    /// The value every byte of a deallocated or unwritten block reads as (DLFEAT);
+    /// `None` if not reported.
+    pub deallocated_read_value: Option<u8>,
+    /// Whether reads of deallocated or unwritten blocks may fail with
+    /// [`Error::DeallocatedOrUnwrittenBlock`] instead (NSFEAT: DAE), see
+    /// [`NvmeDevice::set_deallocated_or_unwritten_block_error`].
+    pub deallocated_or_unwritten_block_error_supported: bool,
+    /// Whether the metadata is transferred at the end of each logical block (extended LBA)
+    /// instead of in a separate buffer.
+    pub extended_lba: bool,
+    /// Number of metadata bytes per logical block.
+    pub metadata_size: u16,
+    /// Namespace Preferred Write Granularity (NPWG) in logical blocks; 0 if not reported.
+    pub preferred_write_granularity: u32,
+    /// Namespace Preferred Deallocate Granularity (NPDG) in logical blocks; 0 if not reported.
+    pub preferred_deallocate_granularity: u32,
+    /// Namespace Preferred Deallocate Alignment (NPDA) in logical blocks; 0 if not reported.
+    pub preferred_deallocate_alignment: u32,
+    /// Index of the LBA format the namespace is formatted with.
+    pub lba_format_index: u8,
+    /// ANA Group Identifier (ANAGRPID); 0 if the namespace is not in an ANA group.
+    pub ana_group_id: u32,
+    /// Maximum Single Source Range Length (MSSRL) of a Copy command in logical blocks;
+    /// 0 if not reported.
+    pub maximum_single_source_range_length: u16,
+    /// Maximum Copy Length (MCL) of a Copy command in logical blocks; 0 if not reported.
+    pub maximum_copy_length: u32,
+    /// Maximum Source Range Count (MSRC converted) of a Copy command.
+    pub maximum_source_range_count: u16,
+    /// LBA Format Support (LBAF0 to LBAF63), see [`Namespace::lba_formats`].
+    pub(crate) lba_formats_list: [u32; 64],
+    /// Number of supported LBA formats (NLBAF converted).
+    pub(crate) number_of_lba_formats: u8,
 }
 
-#[derive(Debug)]
+/// NVMe base specification (NVM command set) 4.1.5.1 LBA Format Data Structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LbaFormat {
+    /// Index of the format, to be passed to [`NvmeDevice::reformat_namespace`].
+    pub index: u8,
+    /// LBA Data Size (LBADS) in bytes; `None` if it is out of the valid range.
+    pub data_size: Option<u64>,
+    /// Metadata Size (MS) in bytes per logical block.
+    pub metadata_size: u16,
+    /// Relative Performance (RP), 0 is the best and 3 the worst.
+    pub relative_performance: u8,
+    /// Whether the namespace is formatted with this format.
+    pub in_use: bool,
+}
+
+impl LbaFormat {
+    fn from_u32(index: u8, lba_format: u32, in_use: bool) -> Self {
+        let lba_data_size = (lba_format >> 16) & 0xFF; // LBADS
+        Self {
+            index,
+            data_size: (9..32)
+                .contains(&lba_data_size)
+                .then_some(1 << lba_data_size),
+            metadata_size: (lba_format & 0xFFFF) as u16, // MS
+            relative_performance: ((lba_format >> 24) & 0b11) as u8, // RP
+            in_use,
+        }
+    }
+}
+
+impl Namespace {
+    /// The LBA formats the namespace supports; the one in use is at `lba_format_index`.
+    pub fn lba_formats(&self) -> Vec<LbaFormat> {
+        (0..self.number_of_lba_formats)
+            .map(|index| {
+                LbaFormat::from_u32(
+                    index,
+                    self.lba_formats_list[index as usize],
+                    index == self.lba_format_index,
+                )
+            })
+            .collect()
+    }
+
+    pub(crate) fn from_identify_namespace(
+        id: NamespaceId,
+        namespace_data: &IdentifyNamespace,
+    ) -> Self {
+        // figure out block size
+        let flbas = namespace_data.formatted_lba_size;
+        // bits 3:0 are the lower and bits 6:5 the upper bits of the format index
+        let flba_index = ((flbas & 0xF) | ((flbas >> 1) & 0x30)) as usize;
+        let lba_format = namespace_data.lba_formats_list[flba_index];
+        let flba_data = (lba_format >> 16) & 0xFF;
+        let block_size = if !(9..32).contains(&flba_data) {
+            0
+        } else {
+            1 << flba_data
+        };
+        let metadata_size = (lba_format & 0xFFFF) as u16; // MS
+        let extended_lba = (flbas >> 4) & 0b1 == 1;
+
+        let deallocate_in_write_zeroes_supported =
+            (namespace_data.deallocate_logical_block_features >> 3) & 0b1 == 1; // DLFEAT
+        let deallocated_read_value = match namespace_data.deallocate_logical_block_features & 0b111
+        {
+            0b001 => Some(0x00),
+            0b010 => Some(0xFF),
+            _ => None,
+        }; // DLFEAT
+        let deallocated_or_unwritten_block_error_supported =
+            (namespace_data.namespace_features >> 2) & 0b1 == 1; // NSFEAT: DAE
+
+        // NPWG, NPDG and NPDA are only valid if the optimal performance fields are supported
+        // (NSFEAT: OPTPERF)
+        let optimal_performance = (namespace_data.namespace_features >> 4) & 0b1 == 1;
+        let (
+            preferred_write_granularity,
+            preferred_deallocate_granularity,
+            preferred_deallocate_alignment,
+        ) = if optimal_performance {
+            (
+                namespace_data.namespace_preferred_write_granularity as u32 + 1,
+                namespace_data.namespace_preferred_dallocate_granularity as u32 + 1,
+                namespace_data.namespace_preferred_dallocate_alignment as u32 + 1,
+            )
+        } else {
+            (0, 0, 0)
+        };
+
+        Namespace {
+            id,
+            blocks: namespace_data.namespace_capacity,
+            block_size,
+            namespace_globally_unique_identifier: namespace_data
+                .namespace_globally_unique_identifier,
+            ieee_extended_unique_identifier: namespace_data.ieee_extended_unique_identifier,
+            deallocate_in_write_zeroes_supported,
+            deallocated_read_value,
+            deallocated_or_unwritten_block_error_supported,
+            extended_lba,
+            metadata_size,
+            preferred_write_granularity,
+            preferred_deallocate_granularity,
+            preferred_deallocate_alignment,
+            lba_format_index: flba_index as u8,
+            ana_group_id: namespace_data.ana_group_identifier,
+            maximum_single_source_range_length: namespace_data.maximum_single_source_range_length,
+            maximum_copy_length: namespace_data.maximum_copy_length,
+            maximum_source_range_count: namespace_data.maximum_source_range_count as u16 + 1,
+            lba_formats_list: namespace_data.lba_formats_list,
+            number_of_lba_formats: (namespace_data.number_of_lba_formats as usize + 1).min(64)
+                as u8,
+        }
+    }
+
+    /// Whether `byte_length` is a multiple of the block size, as the size of transfer buffers
+    /// must be; always `false` if the block size is unknown (0).
+    pub fn is_block_aligned(&self, byte_length: usize) -> bool {
+        self.block_size != 0 && (byte_length as u64).is_multiple_of(self.block_size)
+    }
+
+    /// `byte_length` rounded up to the next multiple of the block size, the size to allocate
+    /// a transfer buffer with; unchanged if the block size is unknown (0).
+    pub fn round_up_to_block(&self, byte_length: usize) -> usize {
+        if self.block_size == 0 {
+            return byte_length;
+        }
+        byte_length.next_multiple_of(self.block_size as usize)
+    }
+
+    /// Checks that a transfer of `byte_length` bytes at the `logical_block_address`
+    /// is a non-zero multiple of the block size, does not exceed `maximum_transfer_size`
+    /// (`None` for no limit) nor the 65536 blocks a command can address
+    /// and lies within the namespace, and returns the number of blocks it covers.
+    pub fn validate_transfer(
+        &self,
+        byte_length: usize,
+        logical_block_address: u64,
+        maximum_transfer_size: Option<usize>,
+    ) -> Result<u32, Error> {
+        if byte_length == 0 {
+            return Err(Error::NumberOfBlocksIsZero);
+        }
+        if let Some(maximum_transfer_size) = maximum_transfer_size {
+            if byte_length > maximum_transfer_size {
+                return Err(Error::BufferLengthBiggerThanMaximumTransferSize(
+                    byte_length,
+                    maximum_transfer_size,
+                ));
+            }
+        }
+        if !(byte_length as u64).is_multiple_of(self.block_size) {
+            return Err(Error::BufferLengthNotAMultipleOfNamespaceBlockSize(
+                byte_length,
+                self.block_size,
+            ));
+        }
+        let blocks = byte_length as u64 / self.block_size;
+        let maximum_blocks = u16::MAX as u32 + 1;
+        if blocks > maximum_blocks as u64 {
+            return Err(Error::NumberOfBlocksMoreThanMaximum(blocks, maximum_blocks));
+        }
+        let blocks = blocks as u32;
+        let range = LbaRange::new(logical_block_address, blocks);
+        if !range.is_within(self) {
+            return Err(Error::LbaRangeOutOfBounds(range, self.blocks));
+        }
+        Ok(blocks)
+    }
+
+    /// The maximum number of blocks transferred by one command:
+    /// the `maximum_transfer_size` in bytes (see [`ControllerInformation`]) in blocks,
+    /// but at most the 65536 blocks a command can address.
+    pub fn max_transfer_blocks(&self, maximum_transfer_size: Option<usize>) -> u32 {
+        let maximum_blocks = u16::MAX as u32 + 1;
+        match maximum_transfer_size {
+            Some(maximum_transfer_size) if self.block_size != 0 => {
+                (maximum_transfer_size as u64 / self.block_size).min(maximum_blocks as u64) as u32
+            }
+            _ => maximum_blocks,
+        }
+    }
+
+    pub fn is_extended_lba(&self) -> bool {
+        self.extended_lba
+    }
+
+    /// The size of a logical block in bytes (usually 512 or 4096), excluding metadata.
+    pub fn logical_block_bytes(&self) -> u64 {
+        self.block_size
+    }
+
+    /// The preferred write size in bytes, which usually corresponds to the physical block size.
+    /// A namespace with 512 byte logical blocks and 4096 byte physical blocks is a 512e namespace.
+    /// Falls back to the logical block size if the namespace does not report it.
+    pub fn physical_block_bytes(&self) -> u64 {
+        self.block_size * self.preferred_write_granularity.max(1) as u64
+    }
+
+    /// Shrink the `range` to start at the preferred deallocate alignment (NPDA) and to cover
+    /// whole granules of the preferred deallocate granularity (NPDG).
+    /// Returns `None` if no whole granule is left or the range reaches past `u64::MAX`.
+    /// Ranges are unchanged if the namespace does not report the preferences.
+    pub fn align_deallocate_range(&self, range: &LbaRange) -> Option<LbaRange> {
+        let end = range.start.checked_add(range.count as u64)?;
+        let alignment = self.preferred_deallocate_alignment.max(1) as u64;
+        let granularity = self.preferred_deallocate_granularity.max(1) as u64;
+        let start = range.start.checked_next_multiple_of(alignment)?;
+        let blocks = end.saturating_sub(start) / granularity * granularity;
+        if blocks == 0 {
+            return None;
+        }
+        Some(LbaRange::new(start, blocks as u32))
+    }
+
+    /// Whether both namespaces refer to the same namespace, even across controller resets or
+    /// re-enumerations, where namespace IDs are not stable.
+    /// The NGUID is compared if reported for both namespaces, otherwise the EUI64.
+    /// Namespaces without any globally unique identifier never have the same identity.
+    pub fn same_identity_as(&self, other: &Namespace) -> bool {
+        let nguid = self.namespace_globally_unique_identifier;
+        let other_nguid = other.namespace_globally_unique_identifier;
+        if nguid != [0; 16] && other_nguid != [0; 16] {
+            return nguid == other_nguid;
+        }
+        let eui64 = self.ieee_extended_unique_identifier;
+        let other_eui64 = other.ieee_extended_unique_identifier;
+        eui64 != 0 && other_eui64 != 0 && eui64 == other_eui64
+    }
+}
+
+/// SES: the secure erase to perform when formatting a namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureEraseSetting {
+    NoSecureErase = 0b000,
+    UserDataErase = 0b001,
+    CryptographicErase = 0b010,
+}
+
+/// Size of the Host Behavior Support data structure in bytes.
+const HOST_BEHAVIOR_SIZE: usize = 512;
+
+/// NVMe base specification 5.27.1.22 Host Behavior Support data structure.
+/// All behaviors are disabled by default, as they are until the host enables them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostBehavior {
+    /// Advanced Command Retry Enable (ACRE): the controller may set a Command Retry Delay in
+    /// the status of failed commands, after which they may be retried, instead of failing
+    /// them with Do Not Retry.
+    pub advanced_command_retry_enable: bool,
+    /// Extended Telemetry Data Area 4 Supported (ETDAS): the host supports reading
+    /// telemetry data area 4.
+    pub extended_telemetry_data_area_4_supported: bool,
+    /// LBA Format Extension Enable (LBAFEE): the host supports the extended LBA formats,
+    /// e.g. with larger protection information.
+    pub lba_format_extension_enable: bool,
+    /// Host Dispersed Namespace Support (HDISNS): the host supports dispersed namespaces.
+    pub host_dispersed_namespace_support: bool,
+    /// Copy Descriptor Formats Enable (CDFE): bit n enables Copy descriptor format n.
+    pub copy_descriptor_formats_enable: u16,
+}
+
+impl HostBehavior {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            advanced_command_retry_enable: bytes[0] & 0b1 == 1,
+            extended_telemetry_data_area_4_supported: bytes[1] & 0b1 == 1,
+            lba_format_extension_enable: bytes[2] & 0b1 == 1,
+            host_dispersed_namespace_support: bytes[3] & 0b1 == 1,
+            copy_descriptor_formats_enable: u16::from_le_bytes([bytes[4], bytes[5]]),
+        }
+    }
+
+    fn to_bytes(self, bytes: &mut [u8]) {
+        bytes.fill(0);
+        bytes[0] = self.advanced_command_retry_enable as u8;
+        bytes[1] = self.extended_telemetry_data_area_4_supported as u8;
+        bytes[2] = self.lba_format_extension_enable as u8;
+        bytes[3] = self.host_dispersed_namespace_support as u8;
+        bytes[4..6].copy_from_slice(&self.copy_descriptor_formats_enable.to_le_bytes());
+    }
+}
+
+/// How metadata is transferred and protected after formatting a namespace
+/// with an LBA format with metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetadataSettings {
+    /// Metadata Settings (MSET): transfer the metadata at the end of each logical block
+    /// (extended LBA) instead of in a separate buffer.
+    pub extended_lba: bool,
+    /// Protection Information (PI): the type of end-to-end protection, 0 for none or 1 to 3.
+    pub protection_information: u8,
+    /// Protection Information Location (PIL): the protection information is in the first
+    /// instead of the last bytes of the metadata.
+    pub protection_information_first: bool,
+}
+
+/// The shutdown notification (CC.SHN) sent to the controller when the device is dropped
+/// without [`NvmeDevice::shutdown`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShutdownType {
+    /// Leave the controller running.
+    None,
+    /// Let the controller finish its work, e.g. flush its volatile write cache.
+    #[default]
+    Normal,
+    /// Shut down as fast as possible, e.g. on an imminent power loss.
+    Abrupt,
+}
+
+/// Settings of a device applied when it is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvmeConfig {
+    /// The memory page size of the controller (CC.MPS) in bytes, a power of two between the
+    /// minimum and maximum the controller supports. 4 KiB by default.
+    pub page_size: usize,
+    /// The shutdown notification sent when the device is dropped without
+    /// [`NvmeDevice::shutdown`], [`ShutdownType::Normal`] by default.
+    /// [`ShutdownType::Abrupt`] shortens the shutdown for environments prone to power loss
+    /// at the cost of data in the volatile write cache.
+    pub shutdown_on_drop: ShutdownType,
+}
+
+impl Default for NvmeConfig {
+    fn default() -> Self {
+        Self {
+            page_size: 4096,
+            shutdown_on_drop: ShutdownType::Normal,
+        }
+    }
+}
+
+/// Optional NVM commands and features supported by the controller (ONCS).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OptionalNvmCommandSupport {
+    pub compare: bool,
+    pub write_uncorrectable: bool,
+    pub dataset_management: bool,
+    pub write_zeroes: bool,
+    pub save_field_in_features: bool,
+    pub reservations: bool,
+    pub timestamp: bool,
+    pub verify: bool,
+    pub copy: bool,
+}
+
+impl OptionalNvmCommandSupport {
+    pub(crate) fn from_u16(oncs: u16) -> Self {
+        Self {
+            compare: oncs & 0b1 != 0,
+            write_uncorrectable: (oncs >> 1) & 0b1 != 0,
+            dataset_management: (oncs >> 2) & 0b1 != 0,
+            write_zeroes: (oncs >> 3) & 0b1 != 0,
+            save_field_in_features: (oncs >> 4) & 0b1 != 0,
+            reservations: (oncs >> 5) & 0b1 != 0,
+            timestamp: (oncs >> 6) & 0b1 != 0,
+            verify: (oncs >> 7) & 0b1 != 0,
+            copy: (oncs >> 8) & 0b1 != 0,
+        }
+    }
+}
+
+/// The DMA memory a workload of one I/O queue pair needs, see [`NvmeDevice::plan_buffers`].
+/// Sizes are requested sizes; allocators round every allocation up to their page size,
+/// e.g. [`crate::HugePageAllocator`] uses at least one huge page per allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPlan {
+    /// Data buffers, one per command in flight.
+    pub data_bytes: usize,
+    /// PRP list pages, needed for transfers of more than 2 pages while they are in flight.
+    pub prp_list_bytes: usize,
+    /// The submission and the completion queue.
+    pub queue_bytes: usize,
+    /// Number of separate allocations of the data buffers, PRP list pages and queues.
+    pub allocations: usize,
+}
+
+impl BufferPlan {
+    pub fn total_bytes(&self) -> usize {
+        self.data_bytes + self.prp_list_bytes + self.queue_bytes
+    }
+}
+
+/// The identify namespace data of a namespace beyond the fields of [`Namespace`].
+#[derive(Debug, Clone, Copy)]
+pub struct NamespaceDetails {
+    pub namespace: Namespace,
+    /// Namespace Size (NSZE) in logical blocks.
+    pub size: u64,
+    /// Namespace Utilization (NUSE) in logical blocks.
+    pub utilization: u64,
+    /// NVM Capacity (NVMCAP) in bytes; 0 if not reported.
+    pub nvm_capacity: u128,
+    /// Whether the namespace is thin provisioned (NSFEAT).
+    pub thin_provisioning: bool,
+    /// Whether the namespace may be attached to several controllers (NMIC).
+    pub shared: bool,
+    /// Whether the namespace is write protected (NSATTR).
+    pub write_protected: bool,
+    /// Number of supported LBA formats (NLBAF converted).
+    pub number_of_lba_formats: u8,
+    /// NVM Set Identifier (NVMSETID); 0 if not reported.
+    pub nvm_set_id: u16,
+    /// Endurance Group Identifier (ENDGID); 0 if not reported.
+    pub endurance_group_id: u16,
+}
+
+impl NamespaceDetails {
+    pub(crate) fn from_identify_namespace(
+        id: NamespaceId,
+        namespace_data: &IdentifyNamespace,
+    ) -> Self {
+        let namespace = Namespace::from_identify_namespace(id, namespace_data);
+        Self {
+            // capped like the LBA format list of the namespace, NLBAF comes from the device
+            number_of_lba_formats: namespace.number_of_lba_formats,
+            namespace,
+            size: namespace_data.namespace_size,
+            utilization: namespace_data.namespace_uitilization,
+            nvm_capacity: namespace_data.nvm_capacity,
+            thin_provisioning: namespace_data.namespace_features & 0b1 == 1,
+            shared: namespace_data.namespace_multi_path_io_and_namespace_sharing_capabilites & 0b1
+                == 1,
+            write_protected: namespace_data.namespace_attributes & 0b1 == 1,
+            nvm_set_id: namespace_data.nvm_set_identifier,
+            endurance_group_id: namespace_data.endurance_group_identifier,
+        }
+    }
+}
+
+/// An I/O command set combination the controller supports (Identify CNS 0x1C).
+/// Every bit of the vector stands for one I/O command set; the index of the combination
+/// in the list is the one to select with the I/O Command Set Profile feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoCommandSetCombination(pub u64);
+
+impl IoCommandSetCombination {
+    pub fn nvm(&self) -> bool {
+        self.0 & 0b1 != 0
+    }
+
+    pub fn key_value(&self) -> bool {
+        (self.0 >> 1) & 0b1 != 0
+    }
+
+    pub fn zoned_namespace(&self) -> bool {
+        (self.0 >> 2) & 0b1 != 0
+    }
+
+    pub fn subsystem_local_memory(&self) -> bool {
+        (self.0 >> 3) & 0b1 != 0
+    }
+
+    pub fn computational_programs(&self) -> bool {
+        (self.0 >> 4) & 0b1 != 0
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct ControllerInformation {
     pub pci_vendor_id: u16,
     pub pci_subsystem_vendor_id: u16,
@@ -37,32 +589,269 @@ pub struct ControllerInformation {
     pub memory_page_size: usize,
     pub maximum_number_of_io_queue_pairs: u16,
     pub maximum_queue_entries_supported: u32,
-    pub maximum_transfer_size: usize,
+    /// Maximum Data Transfer Size (MDTS) in bytes; `None` if the controller has no limit.
+    pub maximum_transfer_size: Option<usize>,
     pub controller_id: u16,
     pub version: u32,
+    pub optional_nvm_command_support: OptionalNvmCommandSupport,
+    pub boot_partition_support: bool,
+    /// Whether the controller reports Asymmetric Namespace Access (CMIC.ANARS).
+    pub asymmetric_namespace_access_reporting: bool,
+    /// Read Recovery Levels Supported (RRLS), bit n is set if level n is supported;
+    /// 0 if the controller does not support read recovery levels (CTRATT).
+    pub read_recovery_levels_supported: u16,
+    /// Firmware Update Granularity (FWUG) in bytes, firmware images must be downloaded in
+    /// pieces of a multiple of it; `None` if there is no restriction or it is not reported.
+    pub firmware_update_granularity: Option<usize>,
+    /// Worst-case time to wait for the controller to become ready or to shut down
+    /// in milliseconds (CAP.TO converted); 0 if the registers are not accessible.
+    pub timeout_milliseconds: u32,
+    /// Whether the controller supports the telemetry log pages (LPA).
+    pub telemetry_supported: bool,
+    /// The maximum number of outstanding Asynchronous Event Requests (AERL converted).
+    pub asynchronous_event_request_limit: u16,
+    /// The number of entries of the Error Information log page (ELPE converted).
+    pub error_log_page_entries: u16,
+    /// The maximum number of outstanding Abort commands (ACL converted).
+    pub abort_command_limit: u16,
+    /// Keep Alive Support (KAS) in milliseconds, the granularity of the keep alive timeout;
+    /// 0 if the controller does not support Keep Alive.
+    pub keep_alive_granularity_milliseconds: u32,
+    /// The power state descriptors, indexed by power state (NPSS + 1 entries).
+    pub power_states: Vec<PowerStateDescriptor>,
+    /// Whether I/O commands may describe their buffers with SGLs instead of PRPs (SGLS).
+    pub sgl_support: SglSupport,
+    /// The Controller Memory Buffer, if the controller has one.
+    pub controller_memory_buffer: Option<ControllerMemoryBuffer>,
+}
+
+impl ControllerInformation {
+    /// The maximum number of commands in flight on one I/O queue pair:
+    /// one queue entry less than the maximum queue size (MQES),
+    /// as a full completion queue cannot be distinguished from an empty one.
+    pub fn maximum_queue_depth(&self) -> u32 {
+        self.maximum_queue_entries_supported.saturating_sub(1)
+    }
+
+    /// The maximum number of commands in flight across all I/O queue pairs
+    /// the controller allocated, each created with the maximum queue size.
+    pub fn maximum_outstanding_commands(&self) -> usize {
+        self.maximum_queue_depth() as usize * self.maximum_number_of_io_queue_pairs as usize
+    }
+}
+
+/// The Controller Configuration (CC) register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ControllerConfiguration {
+    /// Enable (EN)
+    pub enable: bool,
+    /// I/O Command Set Selected (CSS)
+    pub io_command_set_selected: u8,
+    /// Memory Page Size (MPS), the page size is 2 ^ (12 + MPS) bytes.
+    pub memory_page_size: u8,
+    /// Arbitration Mechanism Selected (AMS)
+    pub arbitration_mechanism_selected: u8,
+    /// Shutdown Notification (SHN)
+    pub shutdown_notification: u8,
+    /// I/O Submission Queue Entry Size (I/OSQES), the entry size is 2 ^ I/OSQES bytes.
+    pub io_submission_queue_entry_size: u8,
+    /// I/O Completion Queue Entry Size (I/OCQES), the entry size is 2 ^ I/OCQES bytes.
+    pub io_completion_queue_entry_size: u8,
+    /// Controller Ready Independent of Media Enable (CRIME)
+    pub controller_ready_independent_of_media_enable: bool,
+}
+
+impl ControllerConfiguration {
+    pub fn from_u32(cc: u32) -> Self {
+        Self {
+            enable: cc & 0b1 == 1,
+            io_command_set_selected: ((cc >> 4) & 0b111) as u8,
+            memory_page_size: ((cc >> 7) & 0b1111) as u8,
+            arbitration_mechanism_selected: ((cc >> 11) & 0b111) as u8,
+            shutdown_notification: ((cc >> 14) & 0b11) as u8,
+            io_submission_queue_entry_size: ((cc >> 16) & 0b1111) as u8,
+            io_completion_queue_entry_size: ((cc >> 20) & 0b1111) as u8,
+            controller_ready_independent_of_media_enable: (cc >> 24) & 0b1 == 1,
+        }
+    }
+
+    /// The register value, reserved bits are 0.
+    pub fn to_u32(&self) -> u32 {
+        self.enable as u32
+            | (self.io_command_set_selected as u32 & 0b111) << 4
+            | (self.memory_page_size as u32 & 0b1111) << 7
+            | (self.arbitration_mechanism_selected as u32 & 0b111) << 11
+            | (self.shutdown_notification as u32 & 0b11) << 14
+            | (self.io_submission_queue_entry_size as u32 & 0b1111) << 16
+            | (self.io_completion_queue_entry_size as u32 & 0b1111) << 20
+            | (self.controller_ready_independent_of_media_enable as u32) << 24
+    }
+}
+
+/// How admin commands reach the controller.
+#[derive(Debug)]
+enum AdminTransport {
+    /// The admin queue pair in host memory, with the doorbells in the mapped BAR.
+    Queue(Box<AdminQueuePair>),
+    /// Passthrough via the character device of the kernel driver.
+    #[cfg(feature = "std")]
+    CharDevice(Arc<CharDevice>),
+}
+
+impl AdminTransport {
+    fn queue_pair_mut(&mut self) -> Option<&mut AdminQueuePair> {
+        match self {
+            AdminTransport::Queue(admin_queue_pair) => Some(admin_queue_pair),
+            #[cfg(feature = "std")]
+            AdminTransport::CharDevice(_) => None,
+        }
+    }
 }
 
+/// The error of a failed [`NvmeDevice::shutdown`]: the device, the queue pairs not deleted yet
+/// and the cause.
+pub type ShutdownError<A> = (Box<NvmeDevice<A>>, Vec<IoQueuePair<A>>, Error);
+
 #[derive(Debug)]
 pub struct NvmeDevice<A> {
     allocator: Arc<A>,
     address: *mut u8, // BAR address
     length: usize,    // BAR length
     doorbell_stride: u16,
-    admin_queue_pair: AdminQueuePair,
+    admin: AdminTransport,
     io_queue_pair_ids: Vec<IoQueuePairId>,
     information: ControllerInformation,
     namespaces: HashMap<NamespaceId, Namespace, RandomState>,
     buffer: Dma<u8>,
+    #[cfg(feature = "std")]
+    pci_address: Option<String>,
+    /// The kernel driver bound to the device before it was opened.
+    #[cfg(feature = "std")]
+    kernel_driver: Option<String>,
+    /// The BAR mapped by `from_pci_address` or `from_vfio`, unmapped when the device is dropped.
+    #[cfg(feature = "std")]
+    bar: Option<pci::MappedBar>,
+    /// The MSI-X vectors of a device opened with `from_vfio`.
+    #[cfg(feature = "std")]
+    msix_interrupts: Option<vfio::MsixInterrupts>,
+    /// Set once the controller completed a shutdown, so dropping the device does not repeat it.
+    shut_down: bool,
+    shutdown_on_drop: ShutdownType,
+    /// Set by [`Self::enable_controller_memory_buffer`].
+    controller_memory_buffer: Option<MappedControllerMemoryBuffer>,
+    /// Events of a [`Self::poll_async_events`] failing to post new requests,
+    /// handed out by the next poll.
+    undelivered_async_events: Vec<AsyncEvent>,
+}
+
+// SAFETY: The BAR address is only dereferenced through methods taking `&mut self`,
+// so moving the device to another thread cannot create concurrent register accesses.
+// The allocator is shared with the I/O queue pairs through an `Arc`, hence `A: Send + Sync`.
+// `NvmeDevice` is deliberately not `Sync`: to share it between threads,
+// wrap it in a lock, which only requires `Send`.
+unsafe impl<A: Send + Sync> Send for NvmeDevice<A> {}
+
+impl<A> NvmeDevice<A> {
+    /// Notify the controller of a shutdown (CC.SHN) and wait until it reports the shutdown
+    /// processing as complete (CSTS.SHST), at most for the controller timeout (CAP.TO).
+    /// A normal shutdown lets the controller finish its work, e.g. flush its caches;
+    /// an `abrupt` shutdown is for an imminent power loss that leaves no time for that.
+    /// Does nothing if the controller is already shut down, so it may be called repeatedly.
+    pub fn notify_shutdown(&mut self, abrupt: bool) -> Result<(), Error> {
+        if self.shut_down {
+            return Ok(());
+        }
+        let csts = get_register_32(NvmeRegs32::CSTS, self.address, self.length)?;
+        if (csts >> 2) & 0b11 == 0b10 {
+            debug!("Controller already shut down");
+            self.shut_down = true;
+            return Ok(());
+        }
+        debug!("Send shutdown signal");
+        let mut cc = get_register_32(NvmeRegs32::CC, self.address, self.length)?;
+        let shutdown_notification = if abrupt { 0b10 } else { 0b01 };
+        cc = (cc & !(0b11 << 14)) | (shutdown_notification << 14); // SHN
+        set_register_32(NvmeRegs32::CC, cc, self.address, self.length)?;
+
+        // Wait for "shutdown" signal
+        let mut deadline =
+            Deadline::after_milliseconds(self.information.timeout_milliseconds as u64);
+        loop {
+            let csts = get_register_32(NvmeRegs32::CSTS, self.address, self.length)?;
+            let shutdown_status = (csts >> 2) & 0b11; // SHST
+            let shutdown_type = csts >> 7; // ST
+            if shutdown_status == 0b10 && shutdown_type == 0 {
+                break;
+            }
+            if deadline.expired() {
+                return Err(Error::ShutdownTimeout);
+            }
+            spin_loop();
+        }
+        self.shut_down = true;
+        debug!("Controller shutdown successful");
+        Ok(())
+    }
 }
 
-unsafe impl<A> Send for NvmeDevice<A> {}
-unsafe impl<A> Sync for NvmeDevice<A> {}
+/// Devices not shut down explicitly are shut down as set with
+/// [`NvmeConfig::shutdown_on_drop`] when dropped; failures are only logged.
+/// A BAR mapped by the device is unmapped afterwards.
+impl<A> Drop for NvmeDevice<A> {
+    fn drop(&mut self) {
+        if !self.io_queue_pair_ids.is_empty() {
+            warn!(
+                "Device dropped with {} I/O queue pairs not deleted, using them is undefined behavior",
+                self.io_queue_pair_ids.len()
+            );
+        }
+        // The kernel driver is responsible for controllers accessed through its character device.
+        if self.shut_down || self.admin.queue_pair_mut().is_none() {
+            return;
+        }
+        let abrupt = match self.shutdown_on_drop {
+            ShutdownType::None => return,
+            ShutdownType::Normal => false,
+            ShutdownType::Abrupt => true,
+        };
+        if let Err(error) = self.notify_shutdown(abrupt) {
+            debug!("Shutdown of dropped device failed: {error}");
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl NvmeDevice<StdAllocator> {
+    /// Open the controller through its character device (e.g. `/dev/nvme0`) like
+    /// [`NvmeDevice::from_char_device`], with buffers allocated by the [`StdAllocator`],
+    /// which needs neither huge pages nor the page map.
+    pub fn from_char_device_with_std_allocator(path: &str) -> Result<Self, Error> {
+        Self::from_char_device(path, StdAllocator::new())
+    }
+}
+
+#[cfg(feature = "std")]
+impl NvmeDevice<VfioAllocator> {
+    /// Open the device at `pci_address` (e.g. `"0000:01:00.0"`), which must be bound to the
+    /// `vfio-pci` driver, through the VFIO `group` it belongs to (`/dev/vfio/<group>`).
+    /// DMA addresses are translated by the IOMMU, so neither huge pages nor root privileges
+    /// are required, only access to the group.
+    pub fn from_vfio(group: u32, pci_address: &str, config: NvmeConfig) -> Result<Self, Error> {
+        // the BAR is unmapped again if the device cannot be initialized
+        let (allocator, bar) = vfio::open(group, pci_address).map_err(Error::VfioError)?;
+        let msix_interrupts = vfio::MsixInterrupts::new(bar.file()).map_err(Error::VfioError)?;
+        let mut nvme = NvmeDevice::new(bar.address(), bar.length(), config, allocator)?;
+        nvme.bar = Some(bar);
+        nvme.msix_interrupts = Some(msix_interrupts);
+        Ok(nvme)
+    }
+}
 
 impl<A: Allocator> NvmeDevice<A> {
     #[cfg(feature = "std")]
     pub fn from_pci_address(
         pci_address: &str,
-        page_size: usize,
+        config: NvmeConfig,
         allocator: A,
     ) -> Result<Self, Error> {
         let mut vendor_file =
@@ -78,24 +867,92 @@ impl<A: Allocator> NvmeDevice<A> {
             .map_err(|error| Error::UnixPciError(error.into()))?
             >> 16;
 
-        // 0x01 -> mass storage device class id
-        // 0x08 -> nvme subclass
-        if class_id != 0x0108 {
+        if class_id != pci::NVME_CLASS_ID as u32 {
             return Err(Error::NotABlockDevice(pci_address.to_string()));
         }
 
-        let (address, length) = pci::mmap_resource(pci_address).map_err(Error::UnixPciError)?;
-        NvmeDevice::new(address, length, page_size, allocator)
+        let kernel_driver = pci::bound_driver(pci_address).map_err(Error::UnixPciError)?;
+        // the BAR is unmapped again if the device cannot be initialized
+        let bar = pci::mmap_resource(pci_address).map_err(Error::UnixPciError)?;
+        let mut nvme = match NvmeDevice::new(bar.address(), bar.length(), config, allocator) {
+            Ok(nvme) => nvme,
+            Err(error) => {
+                // return the device to the kernel driver it was taken from
+                drop(bar);
+                if let Some(driver) = &kernel_driver {
+                    if let Err(error) = pci::bind_driver(pci_address, driver) {
+                        warn!("Binding kernel driver {driver} failed: {error}");
+                    }
+                }
+                return Err(error);
+            }
+        };
+        nvme.pci_address = Some(pci_address.to_string());
+        nvme.kernel_driver = kernel_driver;
+        nvme.bar = Some(bar);
+        // the controller memory buffer is optional, the device is usable without it
+        if nvme.information.controller_memory_buffer.is_some() {
+            let enabled = pci::bar_0_address(&mut config_file)
+                .map_err(|error| Error::UnixPciError(error.into()))
+                .and_then(|address| nvme.enable_controller_memory_buffer(address));
+            if let Err(error) = enabled {
+                debug!("Controller memory buffer not usable: {error}");
+            }
+        }
+        Ok(nvme)
+    }
+
+    /// Open the controller through its character device (e.g. `/dev/nvme0`),
+    /// leaving it bound to the kernel driver.
+    /// Admin and I/O commands are passed through to the kernel with ioctls instead of being
+    /// submitted on queues of this crate, so the BAR is not mapped and register accesses fail.
+    /// Buffers are still allocated with the `allocator`, the kernel maps them for every transfer.
+    #[cfg(feature = "std")]
+    pub fn from_char_device(path: &str, allocator: A) -> Result<Self, Error> {
+        let char_device = CharDevice::open(path)?;
+        // The kernel driver has configured the controller, its capabilities are not accessible.
+        let page_size = 4096;
+        let information = ControllerInformation {
+            minimum_memory_page_size: page_size as u64,
+            maximum_memory_page_size: page_size as u64,
+            memory_page_size: page_size,
+            maximum_queue_entries_supported: 1 << 16,
+            ..Default::default()
+        };
+        let buffer = Dma::allocate(page_size, page_size, &allocator)?;
+        let mut nvme = Self {
+            allocator: Arc::new(allocator),
+            address: core::ptr::null_mut(),
+            length: 0,
+            doorbell_stride: 0,
+            admin: AdminTransport::CharDevice(Arc::new(char_device)),
+            io_queue_pair_ids: Vec::new(),
+            information,
+            namespaces: HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0)),
+            buffer,
+            pci_address: None,
+            kernel_driver: None,
+            bar: None,
+            msix_interrupts: None,
+            shut_down: false,
+            undelivered_async_events: Vec::new(),
+            shutdown_on_drop: ShutdownType::Normal,
+            controller_memory_buffer: None,
+        };
+        nvme.identify()?;
+        Ok(nvme)
     }
 
+    /// Initialize the controller mapped at `address` with the settings of the `config`.
+    /// The crate logs through the `log` facade and never installs a logger itself,
+    /// initialize one (e.g. `env_logger`) in the application to see the messages.
     pub fn new(
         address: *mut u8,
         length: usize,
-        page_size: usize,
+        config: NvmeConfig,
         allocator: A,
     ) -> Result<Self, Error> {
-        #[cfg(feature = "std")]
-        env_logger::init();
+        let page_size = config.page_size;
         // TODO: follow the Memory-based Controller Initialization (PCIe) from
         // the NVMe specification more closely
         debug!("Get capabilities");
@@ -104,18 +961,18 @@ impl<A: Allocator> NvmeDevice<A> {
         let _contiguous_queues_required = ((cap >> 16) & 0b1) == 1; // CQR
         let _weighted_round_robin_with_urgent_priority_class = ((cap >> 17) & 0b1) == 1; // AMS: WRRUPC
         let _vendor_specific_ams = ((cap >> 18) & 0b1) == 1; // AMS: VS
-        let _timeout_milliseconds = ((cap >> 24) & 0b1111_1111) as u32 * 500; // TO (converted)
+        let timeout_milliseconds = ((cap >> 24) & 0b1111_1111) as u32 * 500; // TO (converted)
         let doorbell_stride = ((cap >> 32) & 0b1111) as u16; // DSTRD
         let _nvm_subsystem_reset_supported = ((cap >> 36) & 0b1) == 1; // NSSRS
         let nvm_command_set_support = ((cap >> 37) & 0b1) == 1; // CSS: NCSS
-        let _io_command_set_support = ((cap >> 43) & 0b1) == 1; // CSS: I/OCSS
+        let io_command_set_support = ((cap >> 43) & 0b1) == 1; // CSS: I/OCSS
         let _no_io_command_set_support = ((cap >> 44) & 0b1) == 1; // CSS: NOI/OCSS
-        let _boot_partition_support = ((cap >> 45) & 0b1) == 1; // BPS
+        let boot_partition_support = ((cap >> 45) & 0b1) == 1; // BPS
         let _controller_power_scope = ((cap >> 46) & 0b11) as u8; // CPS
         let minimum_memory_page_size = 1u64 << (((cap >> 48) & 0b1111) + 12); // MPSMIN (converted)
         let maximum_memory_page_size = 1u64 << (((cap >> 52) & 0b1111) + 12); // MPSMAX (converted)
         let _persistend_memory_region_supported = ((cap >> 56) & 0b1) == 1; // PMRS
-        let _controller_memory_buffer_supported = ((cap >> 57) & 0b1) == 1; // CMBS
+        let controller_memory_buffer_supported = ((cap >> 57) & 0b1) == 1; // CMBS
         let _nvm_subsystem_shutdown_supported = ((cap >> 58) & 0b1) == 1; // NSSS
         let _controller_ready_with_media_support = ((cap >> 59) & 0b1) == 1; // CRMS: CRIMS
         let _controller_ready_independent_of_media_support = ((cap >> 60) & 0b1) == 1; // CRMS: CRWMS
@@ -159,87 +1016,108 @@ impl<A: Allocator> NvmeDevice<A> {
         }
 
         debug!("Disable controller");
-        let mut cc = get_register_32(NvmeRegs32::CC, address, length)?;
-        cc &= 0xFFFF_FFFE; // Set Enable (EN) to 0 to disable the controller.
-        set_register_32(NvmeRegs32::CC, cc, address, length)?;
-
-        // Wait for "not ready" signal
-        loop {
-            let csts = get_register_32(NvmeRegs32::CSTS, address, length)?;
-            if csts & 1 == 1 {
-                spin_loop();
-            } else {
-                break;
-            }
-        }
+        disable_controller(address, length, timeout_milliseconds)?;
 
         debug!("Configure admin queues");
-        let admin_sq = SubmissionQueue::new(
-            maximum_queue_entries_supported as usize,
-            page_size,
-            0,
-            &allocator,
-        )?;
-        let admin_cq = CompletionQueue::new(
-            maximum_queue_entries_supported as usize,
-            page_size,
-            0,
-            &allocator,
-        )?;
+        let (admin_sq, admin_cq) =
+            allocate_admin_queues(maximum_queue_entries_supported, page_size, &allocator)?;
+        let admin_queue_entries = admin_cq.len() as u32;
         set_register_64(NvmeRegs64::ASQ, admin_sq.get_addr() as u64, address, length)?;
         set_register_64(NvmeRegs64::ACQ, admin_cq.get_addr() as u64, address, length)?;
-        let aqa = (maximum_queue_entries_supported as u32 - 1) << 16
-            | (maximum_queue_entries_supported as u32 - 1);
+        let aqa = (admin_queue_entries - 1) << 16 | (admin_queue_entries - 1);
         set_register_32(NvmeRegs32::AQA, aqa, address, length)?;
-        let mut admin_queue_pair = AdminQueuePair {
-            submission: admin_sq,
-            completion: admin_cq,
-        };
 
         debug!("Set controller configuration");
-        let enable = 0b1; // EN
-        let reserved_1 = 0b000 << 1;
-        let io_command_set_selected = 0b000 << 4; // CSS TODO
-        let memory_page_size = ((page_size.ilog2() - 12) & 0b1111) << 7; // MPS
-        let arbitration_mechanism_selected = 0b000 << 11; // AMS TODO
-        let shutdown_notification = 0b00 << 14; // SHN
-        let io_submission_queue_entry_size = 6 << 16; // I/OSQES (2^n) TODO
-        let io_completion_queue_entry_size = 4 << 20; // I/OCQES (2^n) TODO
-        let controller_ready_independent_of_media_enable = 0b0 << 24; // CRIME TODO
-        let reserved_2 = 0b000_0000 << 25;
-        let cc = enable
-            | reserved_1
-            | io_command_set_selected
-            | memory_page_size
-            | arbitration_mechanism_selected
-            | shutdown_notification
-            | io_submission_queue_entry_size
-            | io_completion_queue_entry_size
-            | controller_ready_independent_of_media_enable
-            | reserved_2;
-        set_register_32(NvmeRegs32::CC, cc, address, length)?;
+        let configuration = ControllerConfiguration {
+            enable: true,
+            // all supported I/O command sets, if supported, to allow command set specific identify
+            io_command_set_selected: if io_command_set_support { 0b110 } else { 0b000 },
+            memory_page_size: (page_size.ilog2() - 12) as u8,
+            arbitration_mechanism_selected: 0b000, // TODO
+            shutdown_notification: 0b00,
+            io_submission_queue_entry_size: 6, // TODO
+            io_completion_queue_entry_size: 4, // TODO
+            controller_ready_independent_of_media_enable: false, // TODO
+        };
+        let cc = configuration.to_u32();
 
         debug!("Enable controller");
-        // Wait for "ready" signal
-        loop {
-            let csts = get_register_32(NvmeRegs32::CSTS, address, length)?;
-            if csts & 1 == 0 {
-                spin_loop();
-            } else {
-                break;
-            }
+        set_register_32(NvmeRegs32::CC, cc, address, length)?;
+        wait_for_ready(true, address, length, timeout_milliseconds)?;
+
+        debug!("Verify controller configuration");
+        // Some controllers silently clamp or ignore fields they do not support.
+        let cc_read_back = get_register_32(NvmeRegs32::CC, address, length)?;
+        let read_back = ControllerConfiguration::from_u32(cc_read_back);
+        if read_back.io_command_set_selected != configuration.io_command_set_selected
+            || read_back.memory_page_size != configuration.memory_page_size
+            || read_back.io_submission_queue_entry_size
+                != configuration.io_submission_queue_entry_size
+            || read_back.io_completion_queue_entry_size
+                != configuration.io_completion_queue_entry_size
+        {
+            // not left running on the admin queues, which are deallocated
+            disable_controller(address, length, timeout_milliseconds)?;
+            admin_sq.deallocate(&allocator)?;
+            admin_cq.deallocate(&allocator)?;
+            return Err(Error::ControllerConfigurationRejected(cc, cc_read_back));
         }
+        let admin_queue_pair = AdminQueuePair::new(admin_sq, admin_cq, length);
 
         debug!("Allocate buffer");
         let buffer = Dma::allocate(page_size, page_size, &allocator)?;
 
-        debug!("Identify controller");
-        admin_queue_pair.submit_and_complete(
-            NvmeCommand::identify_controller,
-            &buffer,
+        // Controllers supporting CMBMSC only report the buffer once its registers are enabled (CRE).
+        if controller_memory_buffer_supported {
+            set_register_64(NvmeRegs64::CMBMSC, 0b1, address, length)?;
+        }
+        let controller_memory_buffer = ControllerMemoryBuffer::from_registers(
+            get_register_32(NvmeRegs32::CMBLOC, address, length)?,
+            get_register_32(NvmeRegs32::CMBSZ, address, length)?,
+        );
+
+        let information = ControllerInformation {
+            minimum_memory_page_size,
+            maximum_memory_page_size,
+            memory_page_size: page_size,
+            maximum_queue_entries_supported,
+            boot_partition_support,
+            timeout_milliseconds,
+            controller_memory_buffer,
+            ..Default::default()
+        };
+        let mut nvme = Self {
+            allocator: Arc::new(allocator),
             address,
             doorbell_stride,
-        )?;
+            length,
+            admin: AdminTransport::Queue(Box::new(admin_queue_pair)),
+            io_queue_pair_ids: Vec::new(),
+            buffer,
+            information,
+            namespaces: HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0)),
+            #[cfg(feature = "std")]
+            pci_address: None,
+            #[cfg(feature = "std")]
+            kernel_driver: None,
+            #[cfg(feature = "std")]
+            bar: None,
+            #[cfg(feature = "std")]
+            msix_interrupts: None,
+            shut_down: false,
+            undelivered_async_events: Vec::new(),
+            shutdown_on_drop: config.shutdown_on_drop,
+            controller_memory_buffer: None,
+        };
+        nvme.identify()?;
+        Ok(nvme)
+    }
+
+    /// Identify the controller and its active namespaces.
+    fn identify(&mut self) -> Result<(), Error> {
+        debug!("Identify controller");
+        self.submit_and_complete_admin(NvmeCommand::identify_controller)?;
+        let buffer = &self.buffer;
         fn read_c_string_from_slice(slice: &[u8]) -> String {
             let mut string = String::new();
             for &byte in slice {
@@ -255,13 +1133,54 @@ impl<A: Allocator> NvmeDevice<A> {
         let serial_number = read_c_string_from_slice(&buffer[4..=23]); // SN
         let model_number = read_c_string_from_slice(&buffer[24..=63]); // MN
         let firmware_revision = read_c_string_from_slice(&buffer[64..=71]); // FR
-        let maximum_data_transfer_size = 1usize << buffer[77]; // MDTS (converted)
+
+        // MDTS (converted), 0 means there is no limit
+        let maximum_data_transfer_size = match buffer[77] {
+            0 => None,
+            mdts => Some(1usize << mdts),
+        };
         let controller_id = ((buffer[79] as u16) << 8) | buffer[78] as u16; // CNTLID
         let version = ((buffer[83] as u32) << 24)
             | ((buffer[82] as u32) << 16)
             | ((buffer[81] as u32) << 8)
             | buffer[80] as u32; // VER
+        let asymmetric_namespace_access_reporting = (buffer[76] >> 3) & 0b1 == 1; // CMIC: ANARS
+        let read_recovery_levels = (buffer[96] >> 3) & 0b1 == 1; // CTRATT: Read Recovery Levels
+        let read_recovery_levels_supported = if read_recovery_levels {
+            ((buffer[101] as u16) << 8) | buffer[100] as u16 // RRLS
+        } else {
+            0
+        };
         let controller_type = buffer[111]; // CNTRLTYPE
+        let telemetry_supported = (buffer[261] >> 3) & 0b1 == 1; // LPA: Telemetry
+        let asynchronous_event_request_limit = buffer[259] as u16 + 1; // AERL (converted)
+        let error_log_page_entries = buffer[262] as u16 + 1; // ELPE (converted)
+        let abort_command_limit = buffer[258] as u16 + 1; // ACL (converted)
+        let keep_alive_granularity_milliseconds =
+            u16::from_le_bytes([buffer[320], buffer[321]]) as u32 * 100; // KAS (converted)
+        let firmware_update_granularity = match buffer[319] {
+            0x00 | 0xFF => None,
+            granularity => Some(granularity as usize * 4096),
+        }; // FWUG (converted)
+        let optional_nvm_command_support =
+            OptionalNvmCommandSupport::from_u16(((buffer[521] as u16) << 8) | buffer[520] as u16); // ONCS
+
+        // NPSS (converted), the data structure has room for 32 descriptors
+        let number_of_power_states = (buffer[263] as usize + 1).min(32);
+        let power_states = (0..number_of_power_states)
+            .map(|state| {
+                let offset = POWER_STATE_DESCRIPTORS_OFFSET + state * POWER_STATE_DESCRIPTOR_SIZE;
+                PowerStateDescriptor::from_bytes(
+                    &buffer[offset..offset + POWER_STATE_DESCRIPTOR_SIZE],
+                )
+            })
+            .collect();
+        let sgl_support = SglSupport::from_u32(u32::from_le_bytes([
+            buffer[536],
+            buffer[537],
+            buffer[538],
+            buffer[539],
+        ])); // SGLS
 
         if controller_type != 1 {
             let type_name = match controller_type {
@@ -272,297 +1191,1928 @@ impl<A: Allocator> NvmeDevice<A> {
             };
             return Err(Error::ControllerTypeInvalid(type_name.to_string()));
         }
-        let maximum_transfer_size = minimum_memory_page_size as usize * maximum_data_transfer_size;
+        let minimum_memory_page_size = self.information.minimum_memory_page_size as usize;
+        let maximum_transfer_size =
+            maximum_data_transfer_size.map(|units| minimum_memory_page_size * units);
 
         debug!("Get features");
-        let completion_queue_entry = admin_queue_pair.submit_and_complete(
-            |command_id, address| {
-                NvmeCommand::get_features(
-                    command_id,
-                    address,
-                    FeatureIdentifier::NumberOfQueues,
-                    Select::Current,
-                )
-            },
-            &buffer,
-            address,
-            doorbell_stride,
-        )?;
-        let dword_0 = completion_queue_entry.command_specific;
-        // Not adding 1 to account for the admin queue pair.
-        // These are normally 0's based values.
-        let number_of_io_submission_queues_allocated = dword_0 as u16;
-        let number_of_io_completion_queues_allocated = (dword_0 >> 16) as u16;
-        debug!(
-            "Number of io submission queues allocated: {number_of_io_submission_queues_allocated}"
-        );
-        debug!(
-            "Number of io completion queues allocated: {number_of_io_completion_queues_allocated}"
-        );
-        let maximum_number_of_io_queue_pairs =
-            number_of_io_submission_queues_allocated.min(number_of_io_completion_queues_allocated);
+        let number_of_queues = self.number_of_queues()?;
+        debug!("{number_of_queues:?}");
+        let maximum_number_of_io_queue_pairs = number_of_queues.queue_pairs();
 
-        let information = ControllerInformation {
-            pci_vendor_id,
-            pci_subsystem_vendor_id,
-            serial_number,
-            model_number,
-            firmware_revision,
-            minimum_memory_page_size,
-            maximum_memory_page_size,
-            memory_page_size: page_size,
-            maximum_number_of_io_queue_pairs,
-            maximum_queue_entries_supported,
-            maximum_transfer_size,
-            controller_id,
-            version,
-        };
+        let information = &mut self.information;
+        information.pci_vendor_id = pci_vendor_id;
+        information.pci_subsystem_vendor_id = pci_subsystem_vendor_id;
+        information.serial_number = serial_number;
+        information.model_number = model_number;
+        information.firmware_revision = firmware_revision;
+        information.maximum_number_of_io_queue_pairs = maximum_number_of_io_queue_pairs;
+        information.maximum_transfer_size = maximum_transfer_size;
+        information.controller_id = controller_id;
+        information.version = version;
+        information.optional_nvm_command_support = optional_nvm_command_support;
+        information.asymmetric_namespace_access_reporting = asymmetric_namespace_access_reporting;
+        information.read_recovery_levels_supported = read_recovery_levels_supported;
+        information.firmware_update_granularity = firmware_update_granularity;
+        information.telemetry_supported = telemetry_supported;
+        information.asynchronous_event_request_limit = asynchronous_event_request_limit;
+        information.error_log_page_entries = error_log_page_entries;
+        information.abort_command_limit = abort_command_limit;
+        information.keep_alive_granularity_milliseconds = keep_alive_granularity_milliseconds;
+        information.sgl_support = sgl_support;
+        information.power_states = power_states;
         debug!("{information:?}");
 
         debug!("Identify active namespace IDs");
-        // Identify active namespace IDs
-        admin_queue_pair.submit_and_complete(
-            |c_id, address| NvmeCommand::identify_namespace_list(c_id, address, 0),
-            &buffer,
-            address,
-            doorbell_stride,
-        )?;
-        let buffer_as_u32: &[u32] = unsafe {
+        let namespace_ids = self.active_namespace_ids()?;
+        debug!("{namespace_ids:?}");
+
+        debug!("Identify individual namespaces");
+        // Identify individual namespaces
+        let namespaces =
+            self.identify_namespaces_pipelined(&namespace_ids, Namespace::from_identify_namespace)?;
+        // namespaces deleted or detached since the last identify, e.g. across a reset, are gone
+        self.namespaces.clear();
+        for namespace in namespaces {
+            debug!("{namespace:?}");
+            self.namespaces.insert(namespace.id, namespace);
+        }
+        Ok(())
+    }
+
+    /// The IDs of the active namespaces, as reported by the controller.
+    fn active_namespace_ids(&mut self) -> Result<Vec<NamespaceId>, Error> {
+        self.submit_and_complete_admin(|c_id, address| {
+            NvmeCommand::identify_namespace_list(c_id, address, 0)
+        })?;
+        let buffer = &self.buffer;
+        let buffer_as_u32: &[u32] = unsafe {
             core::slice::from_raw_parts(
                 buffer.virtual_address() as *const u32,
                 buffer.number_of_elements() / 4,
             )
         };
-        let namespace_ids = buffer_as_u32
+        Ok(buffer_as_u32
             .iter()
             .copied()
             .take_while(|&id| id != 0)
             .map(NamespaceId)
-            .collect::<Vec<NamespaceId>>();
-        debug!("{namespace_ids:?}");
+            .collect())
+    }
 
-        debug!("Identify individual namespaces");
-        // Identify individual namespaces
-        let mut namespaces = HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0));
-        for namespace_id in namespace_ids {
-            admin_queue_pair.submit_and_complete(
-                |c_id, address| NvmeCommand::identify_namespace(c_id, address, namespace_id.0),
-                &buffer,
+    /// The details of every active namespace, identified with several commands in flight.
+    /// The namespaces known to the device are updated.
+    pub fn identify_all_namespaces(&mut self) -> Result<Vec<NamespaceDetails>, Error> {
+        let namespace_ids = self.active_namespace_ids()?;
+        let details = self.identify_namespaces_pipelined(
+            &namespace_ids,
+            NamespaceDetails::from_identify_namespace,
+        )?;
+        self.namespaces.clear();
+        for namespace_details in &details {
+            let namespace = namespace_details.namespace;
+            self.namespaces.insert(namespace.id, namespace);
+        }
+        Ok(details)
+    }
+
+    /// Identify the namespaces with the `namespace_ids`, keeping several commands in flight,
+    /// bounded by the free admin queue entries and [`IDENTIFY_NAMESPACE_PIPELINE_DEPTH`].
+    /// The identify namespace data of each namespace is converted with `parse`.
+    fn identify_namespaces_pipelined<T>(
+        &mut self,
+        namespace_ids: &[NamespaceId],
+        parse: fn(NamespaceId, &IdentifyNamespace) -> T,
+    ) -> Result<Vec<T>, Error> {
+        let Some(admin_queue_pair) = self.admin.queue_pair_mut() else {
+            // passed through commands complete before the next one can be submitted
+            return namespace_ids
+                .iter()
+                .map(|&namespace_id| {
+                    let namespace_data = self.identify_namespace_data(namespace_id)?;
+                    Ok(parse(namespace_id, &namespace_data))
+                })
+                .collect();
+        };
+        let free_entries =
+            admin_queue_pair.completion.len() - 1 - admin_queue_pair.outstanding_commands;
+        let depth = IDENTIFY_NAMESPACE_PIPELINE_DEPTH
+            .min(free_entries)
+            .min(namespace_ids.len());
+        if depth <= 1 {
+            return namespace_ids
+                .iter()
+                .map(|&namespace_id| {
+                    let namespace_data = self.identify_namespace_data(namespace_id)?;
+                    Ok(parse(namespace_id, &namespace_data))
+                })
+                .collect();
+        }
+        let page_size = self.information.memory_page_size;
+        let mut buffers: Vec<Dma<u8>> = Vec::with_capacity(depth);
+        for _ in 0..depth {
+            match Dma::allocate(page_size, page_size, self.allocator.as_ref()) {
+                Ok(buffer) => buffers.push(buffer),
+                Err(error) => {
+                    for buffer in buffers {
+                        buffer.deallocate(self.allocator.as_ref())?;
+                    }
+                    return Err(error);
+                }
+            }
+        }
+
+        let mut namespaces = Vec::with_capacity(namespace_ids.len());
+        let mut result = Ok(());
+        for chunk in namespace_ids.chunks(depth) {
+            // every submitted command is completed before its buffer is reused or freed
+            let mut submitted = Vec::with_capacity(chunk.len());
+            for (&namespace_id, buffer) in chunk.iter().zip(&buffers) {
+                match admin_queue_pair.submit(
+                    |command_id, address| {
+                        NvmeCommand::identify_namespace(command_id, address, namespace_id.0)
+                    },
+                    buffer,
+                    self.address,
+                    self.doorbell_stride,
+                ) {
+                    Ok(command_id) => submitted.push((namespace_id, buffer, command_id)),
+                    Err(error) => {
+                        result = Err(error);
+                        break;
+                    }
+                }
+            }
+            for (namespace_id, buffer, command_id) in submitted {
+                match admin_queue_pair.complete(command_id, self.address, self.doorbell_stride) {
+                    Ok(_) if result.is_ok() => {
+                        let namespace_data =
+                            unsafe { &*(buffer.virtual_address() as *const IdentifyNamespace) };
+                        namespaces.push(parse(namespace_id, namespace_data));
+                    }
+                    Ok(_) => {}
+                    Err(error) => {
+                        if result.is_ok() {
+                            result = Err(error);
+                        }
+                    }
+                }
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        for buffer in buffers {
+            buffer.deallocate(self.allocator.as_ref())?;
+        }
+        result.map(|()| namespaces)
+    }
+
+    pub fn controller_information(&self) -> &ControllerInformation {
+        &self.information
+    }
+
+    /// Every power state with whether it is non-operational (NOPS).
+    /// Non-operational states save the most power while idle and are the candidates for
+    /// Autonomous Power State Transitions; see [`ControllerInformation::power_states`]
+    /// for their power and latencies.
+    pub fn idle_power_states(&self) -> Vec<(u8, bool)> {
+        self.information
+            .power_states
+            .iter()
+            .enumerate()
+            .map(|(state, descriptor)| (state as u8, descriptor.non_operational))
+            .collect()
+    }
+
+    /// Wait until no command is in flight on the admin queue and on the `queue_pairs`,
+    /// e.g. before reconfiguring or resetting the controller.
+    /// Completions of admin commands are kept for their waiters;
+    /// the first failed I/O command is returned after every queue is drained.
+    /// Draining an I/O queue pair stops with an error if the controller pauses processing,
+    /// unless the queue pair waits while processing is paused.
+    /// Fails with [`Error::DrainTimeout`] if the queues are not drained within
+    /// `timeout_milliseconds`; the commands still in flight stay outstanding then.
+    pub fn quiesce(
+        &mut self,
+        queue_pairs: &mut [IoQueuePair<A>],
+        timeout_milliseconds: u64,
+    ) -> Result<(), Error> {
+        let mut deadline = Deadline::after_milliseconds(timeout_milliseconds);
+        if let Some(admin_queue_pair) = self.admin.queue_pair_mut() {
+            admin_queue_pair.drain(self.address, self.doorbell_stride, &mut deadline)?;
+        }
+        let mut first_failure = None;
+        for queue_pair in queue_pairs {
+            match queue_pair.drain(&mut deadline) {
+                Ok(()) => {}
+                Err(error) if queue_pair.outstanding_commands == 0 => {
+                    first_failure.get_or_insert(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        first_failure.map_or(Ok(()), Err)
+    }
+
+    /// The physical addresses of the admin submission and completion queue (ASQ and ACQ).
+    /// `None` if the controller is accessed through the character device of the kernel driver.
+    pub fn admin_queue_physical_addresses(&self) -> Option<(usize, usize)> {
+        match &self.admin {
+            AdminTransport::Queue(admin_queue_pair) => Some((
+                admin_queue_pair.submission.get_addr(),
+                admin_queue_pair.completion.get_addr(),
+            )),
+            #[cfg(feature = "std")]
+            AdminTransport::CharDevice(_) => None,
+        }
+    }
+
+    pub fn namespace_ids(&self) -> Vec<NamespaceId> {
+        self.namespaces.keys().copied().collect()
+    }
+
+    /// The active namespaces, the biggest first by capacity in bytes,
+    /// namespaces of equal capacity by ascending ID.
+    pub fn namespaces_by_capacity(&self) -> Vec<&Namespace> {
+        let mut namespaces: Vec<&Namespace> = self.namespaces.values().collect();
+        namespaces.sort_by(|a, b| {
+            let capacity =
+                |namespace: &Namespace| namespace.blocks as u128 * namespace.block_size as u128;
+            capacity(b).cmp(&capacity(a)).then(a.id.cmp(&b.id))
+        });
+        namespaces
+    }
+
+    pub fn namespace(&self, namespace_id: &NamespaceId) -> Result<&Namespace, Error> {
+        self.namespaces
+            .get(namespace_id)
+            .ok_or(Error::NamespaceDoesNotExist(*namespace_id))
+    }
+
+    /// The DMA memory needed by an I/O queue pair for the namespace with the `namespace_id`
+    /// keeping `queue_depth` transfers of `transfer_size` bytes in flight,
+    /// without allocating anything.
+    pub fn plan_buffers(
+        &self,
+        namespace_id: &NamespaceId,
+        transfer_size: usize,
+        queue_depth: usize,
+    ) -> Result<BufferPlan, Error> {
+        let namespace = self.namespace(namespace_id)?;
+        namespace.validate_transfer(transfer_size, 0, self.information.maximum_transfer_size)?;
+        // one queue entry stays empty to tell a full queue from an empty one
+        let number_of_queue_entries =
+            u32::try_from(queue_depth.saturating_add(1)).unwrap_or(u32::MAX);
+        if number_of_queue_entries < 2 {
+            return Err(Error::NumberOfQueueEntriesLessThanTwo(
+                number_of_queue_entries,
+            ));
+        }
+        if number_of_queue_entries > self.information.maximum_queue_entries_supported {
+            return Err(Error::NumberOfQueueEntriesMoreThanMaximum(
+                number_of_queue_entries,
+                self.information.maximum_queue_entries_supported,
+            ));
+        }
+        let page_size = self.information.memory_page_size;
+        let prp_lists_per_transfer =
+            prp::number_of_prp_lists(transfer_size.div_ceil(page_size), page_size);
+        let queue_entries = number_of_queue_entries as usize;
+        Ok(BufferPlan {
+            data_bytes: queue_depth * transfer_size,
+            prp_list_bytes: queue_depth * prp_lists_per_transfer * page_size,
+            queue_bytes: queue_entries * core::mem::size_of::<NvmeCommand>()
+                + queue_entries * core::mem::size_of::<CompletionQueueEntry>(),
+            allocations: queue_depth * (1 + prp_lists_per_transfer) + 2,
+        })
+    }
+
+    /// Create a pair consisting of 1 submission and 1 completion queue.
+    pub fn create_io_queue_pair(
+        &mut self,
+        namespace_id: &NamespaceId,
+        number_of_queue_entries: u32,
+    ) -> Result<IoQueuePair<A>, Error> {
+        self.create_io_queue_pair_with_interrupt_vector(namespace_id, number_of_queue_entries, None)
+    }
+
+    /// Create a pair consisting of 1 submission and 1 completion queue.
+    /// If an `interrupt_vector` is given, interrupts are enabled for the completion queue
+    /// and posted to that (MSI-X) vector, which allows the interrupt coalescing feature to be
+    /// combined with queues that interrupt on every completion on separate vectors.
+    /// Otherwise interrupts are disabled for the completion queue.
+    pub fn create_io_queue_pair_with_interrupt_vector(
+        &mut self,
+        namespace_id: &NamespaceId,
+        number_of_queue_entries: u32,
+        interrupt_vector: Option<u16>,
+    ) -> Result<IoQueuePair<A>, Error> {
+        self.create_placed_io_queue_pair(
+            namespace_id,
+            number_of_queue_entries,
+            interrupt_vector,
+            QueuePlacement::Host,
+            QueuePlacement::Host,
+        )
+    }
+
+    /// Create a pair consisting of 1 submission and 1 completion queue, placing each queue
+    /// in host memory or in the Controller Memory Buffer, which has to be enabled with
+    /// [`Self::enable_controller_memory_buffer`] and support queues of that kind.
+    /// A submission queue in the buffer saves the controller a read of host memory for every
+    /// command it fetches. Interrupts are disabled for the completion queue.
+    pub fn create_io_queue_pair_with_placement(
+        &mut self,
+        namespace_id: &NamespaceId,
+        number_of_queue_entries: u32,
+        submission_queue_placement: QueuePlacement,
+        completion_queue_placement: QueuePlacement,
+    ) -> Result<IoQueuePair<A>, Error> {
+        self.create_placed_io_queue_pair(
+            namespace_id,
+            number_of_queue_entries,
+            None,
+            submission_queue_placement,
+            completion_queue_placement,
+        )
+    }
+
+    fn create_placed_io_queue_pair(
+        &mut self,
+        namespace_id: &NamespaceId,
+        number_of_queue_entries: u32,
+        interrupt_vector: Option<u16>,
+        submission_queue_placement: QueuePlacement,
+        completion_queue_placement: QueuePlacement,
+    ) -> Result<IoQueuePair<A>, Error> {
+        if number_of_queue_entries < 2 {
+            return Err(Error::NumberOfQueueEntriesLessThanTwo(
+                number_of_queue_entries,
+            ));
+        }
+        if number_of_queue_entries > self.information.maximum_queue_entries_supported {
+            return Err(Error::NumberOfQueueEntriesMoreThanMaximum(
+                number_of_queue_entries,
+                self.information.maximum_queue_entries_supported,
+            ));
+        }
+        let namespace = *self.namespace(namespace_id)?;
+        let queue_id = self.free_io_queue_pair_id()?;
+
+        debug!("Requesting I/O queue pair with ID {}", queue_id.0);
+
+        // The kernel submits passed through commands on its own queues,
+        // the queues of this crate only keep track of the submitted commands.
+        #[cfg(feature = "std")]
+        if let AdminTransport::CharDevice(char_device) = &self.admin {
+            if submission_queue_placement != QueuePlacement::Host
+                || completion_queue_placement != QueuePlacement::Host
+            {
+                return Err(Error::ControllerMemoryBufferNotAvailable);
+            }
+            let char_device = Some(char_device.clone());
+            let (submission_queue, completion_queue) = self.new_io_queues(
+                queue_id,
+                number_of_queue_entries as usize,
+                (0, QueuePlacement::Host),
+                (0, QueuePlacement::Host),
+            )?;
+            let mut io_queue_pair =
+                self.new_io_queue_pair(queue_id, namespace, submission_queue, completion_queue);
+            io_queue_pair.char_device = char_device;
+            self.io_queue_pair_ids.push(queue_id);
+            return Ok(io_queue_pair);
+        }
+
+        let offset = 0x1000 + ((4 << self.doorbell_stride) * (2 * queue_id.0 + 1) as usize);
+        assert!(
+            offset <= self.length - 4,
+            "SQ doorbell offset out of bounds"
+        );
+
+        let completion_queue_doorbell = self.address as usize + offset;
+        let submission_queue_doorbell = self.address as usize
+            + 0x1000
+            + ((4 << self.doorbell_stride) * (2 * queue_id.0) as usize);
+        let (submission_queue, completion_queue) = self.new_io_queues(
+            queue_id,
+            number_of_queue_entries as usize,
+            (submission_queue_doorbell, submission_queue_placement),
+            (completion_queue_doorbell, completion_queue_placement),
+        )?;
+        let io_queue_pair =
+            self.new_io_queue_pair(queue_id, namespace, submission_queue, completion_queue);
+        let completion_queue = &io_queue_pair.completion;
+        let result = self
+            .submit_and_complete_admin(|c_id, _| {
+                NvmeCommand::create_io_completion_queue(
+                    c_id,
+                    queue_id.0,
+                    completion_queue.get_addr(),
+                    (number_of_queue_entries - 1) as u16,
+                    interrupt_vector,
+                )
+            })
+            .map_err(|error| {
+                queue_creation_error(queue_id, number_of_queue_entries, interrupt_vector, error)
+            });
+
+        let submission_queue = &io_queue_pair.submission;
+        let result = result.and_then(|_| {
+            let created = self
+                .submit_and_complete_admin(|c_id, _| {
+                    NvmeCommand::create_io_submission_queue(
+                        c_id,
+                        queue_id.0,
+                        submission_queue.get_addr(),
+                        (number_of_queue_entries - 1) as u16,
+                        queue_id.0,
+                    )
+                })
+                .map_err(|error| {
+                    queue_creation_error(queue_id, number_of_queue_entries, interrupt_vector, error)
+                });
+            if created.is_err() {
+                // otherwise the completion queue keeps the ID, which the next attempt reuses
+                if let Err(error) = self.submit_and_complete_admin(|c_id, _| {
+                    NvmeCommand::delete_io_completion_queue(c_id, queue_id.0)
+                }) {
+                    warn!(
+                        "Deleting I/O completion queue {} failed: {error}",
+                        queue_id.0
+                    );
+                }
+            }
+            created
+        });
+        if let Err(error) = result {
+            if let Err(error) =
+                self.deallocate_io_queues(io_queue_pair.submission, io_queue_pair.completion)
+            {
+                warn!("Deallocating I/O queues {} failed: {error}", queue_id.0);
+            }
+            if let Some(controller_memory_buffer) = &mut self.controller_memory_buffer {
+                controller_memory_buffer.release(queue_id);
+            }
+            return Err(error);
+        }
+
+        self.io_queue_pair_ids.push(queue_id);
+        Ok(io_queue_pair)
+    }
+
+    /// Create a pair consisting of 1 submission and 1 completion queue, whose completion queue
+    /// interrupts on the MSI-X vector with the number of the queue pair ID.
+    /// The vector signals an eventfd registered with VFIO, which lets
+    /// [`IoQueuePair::wait_for_completion_interrupt`] sleep instead of spinning.
+    /// Requires a device opened with [`NvmeDevice::from_vfio`], fails with
+    /// [`Error::InterruptsNotSupported`] otherwise, and with [`Error::InvalidInterruptVector`]
+    /// if the MSI-X table of the device has no vector for the queue pair.
+    #[cfg(feature = "std")]
+    pub fn create_io_queue_pair_with_interrupt_event(
+        &mut self,
+        namespace_id: &NamespaceId,
+        number_of_queue_entries: u32,
+    ) -> Result<IoQueuePair<A>, Error> {
+        // vector 0 is the one of the admin queue, so every queue pair ID is a free vector
+        let queue_id = self.free_io_queue_pair_id()?;
+        let interrupt_vector = queue_id.0;
+        let msix_interrupts = self
+            .msix_interrupts
+            .as_mut()
+            .ok_or(Error::InterruptsNotSupported)?;
+        if interrupt_vector as u32 >= msix_interrupts.vector_count() {
+            return Err(Error::InvalidInterruptVector(queue_id, interrupt_vector));
+        }
+        // registered before the queue exists, so no interrupt is missed
+        let interrupt_event = msix_interrupts
+            .enable(interrupt_vector as u32)
+            .map_err(Error::InterruptError)?;
+        let io_queue_pair = self.create_io_queue_pair_with_interrupt_vector(
+            namespace_id,
+            number_of_queue_entries,
+            Some(interrupt_vector),
+        );
+        match io_queue_pair {
+            Ok(mut io_queue_pair) => {
+                io_queue_pair.interrupt_event = Some(interrupt_event);
+                Ok(io_queue_pair)
+            }
+            Err(error) => {
+                self.disable_interrupt_vector(interrupt_vector);
+                Err(error)
+            }
+        }
+    }
+
+    /// Stop the MSI-X `interrupt_vector` from signaling the eventfd of its queue pair.
+    /// Failures are only logged, as the vector is reassigned when it is used again.
+    #[cfg(feature = "std")]
+    fn disable_interrupt_vector(&mut self, interrupt_vector: u16) {
+        if let Some(msix_interrupts) = &mut self.msix_interrupts {
+            if let Err(error) = msix_interrupts.disable(interrupt_vector as u32) {
+                debug!("Disabling MSI-X vector {interrupt_vector} failed: {error}");
+            }
+        }
+    }
+
+    /// The lowest queue pair ID not in use.
+    /// Simple way to avoid collisions while reusing some previously deleted keys.
+    fn free_io_queue_pair_id(&self) -> Result<IoQueuePairId, Error> {
+        (1..=self.information.maximum_number_of_io_queue_pairs)
+            .map(IoQueuePairId)
+            .find(|queue_id| !self.io_queue_pair_ids.contains(queue_id))
+            .ok_or(Error::MaximumNumberOfQueuesReached)
+    }
+
+    /// Create the queues of the queue pair with `queue_id`, each with its doorbell and placement.
+    fn new_io_queues(
+        &mut self,
+        queue_id: IoQueuePairId,
+        number_of_queue_entries: usize,
+        (submission_queue_doorbell, submission_queue_placement): (usize, QueuePlacement),
+        (completion_queue_doorbell, completion_queue_placement): (usize, QueuePlacement),
+    ) -> Result<(SubmissionQueue, CompletionQueue), Error> {
+        let page_size = self.information.memory_page_size;
+        let in_buffer = |placement| placement == QueuePlacement::ControllerMemoryBuffer;
+        if in_buffer(submission_queue_placement) || in_buffer(completion_queue_placement) {
+            let buffer = &self
+                .controller_memory_buffer
+                .as_ref()
+                .ok_or(Error::ControllerMemoryBufferNotAvailable)?
+                .buffer;
+            if in_buffer(submission_queue_placement) && !buffer.submission_queue_support {
+                return Err(Error::ControllerMemoryBufferQueueNotSupported(true));
+            }
+            if in_buffer(completion_queue_placement) && !buffer.completion_queue_support {
+                return Err(Error::ControllerMemoryBufferQueueNotSupported(false));
+            }
+        }
+
+        let completion_queue = match completion_queue_placement {
+            QueuePlacement::Host => CompletionQueue::new(
+                number_of_queue_entries,
+                page_size,
+                completion_queue_doorbell,
+                self.allocator.as_ref(),
+            )?,
+            QueuePlacement::ControllerMemoryBuffer => {
+                let controller_memory_buffer = self.controller_memory_buffer.as_mut().unwrap();
+                let commands = controller_memory_buffer.allocate(
+                    queue_id,
+                    number_of_queue_entries,
+                    page_size,
+                )?;
+                CompletionQueue::with_memory(commands, completion_queue_doorbell)
+            }
+        };
+        let submission_queue = match submission_queue_placement {
+            QueuePlacement::Host => SubmissionQueue::new(
+                number_of_queue_entries,
+                page_size,
+                submission_queue_doorbell,
+                self.allocator.as_ref(),
+            ),
+            QueuePlacement::ControllerMemoryBuffer => {
+                let controller_memory_buffer = self.controller_memory_buffer.as_mut().unwrap();
+                controller_memory_buffer
+                    .allocate(queue_id, number_of_queue_entries, page_size)
+                    .map(|commands| {
+                        SubmissionQueue::with_memory(commands, submission_queue_doorbell)
+                    })
+            }
+        };
+        match submission_queue {
+            Ok(submission_queue) => Ok((submission_queue, completion_queue)),
+            Err(error) => {
+                if completion_queue_placement == QueuePlacement::Host {
+                    completion_queue.deallocate(self.allocator.as_ref())?;
+                }
+                if let Some(controller_memory_buffer) = &mut self.controller_memory_buffer {
+                    controller_memory_buffer.release(queue_id);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Deallocate the host memory of an I/O queue pair no longer known to the controller:
+    /// its PRP list pages, the PRP and SGL lists of commands that never completed and its queues.
+    fn release_io_queue_pair(&self, mut io_queue_pair: IoQueuePair<A>) -> Result<(), Error> {
+        let allocator = self.allocator.as_ref();
+        for (_, prp_container) in io_queue_pair.prp_containers.drain() {
+            prp::deallocate(prp_container, allocator, &mut io_queue_pair.prp_list_pool)?;
+        }
+        io_queue_pair.prp_list_pool.clear(allocator)?;
+        for (_, list) in io_queue_pair.sgl_lists.drain() {
+            list.deallocate(allocator)?;
+        }
+        self.deallocate_io_queues(io_queue_pair.submission, io_queue_pair.completion)
+    }
+
+    /// Deallocate the memory of I/O queues no longer known to the controller,
+    /// unless it lies in the controller memory buffer.
+    fn deallocate_io_queues(
+        &self,
+        submission_queue: SubmissionQueue,
+        completion_queue: CompletionQueue,
+    ) -> Result<(), Error> {
+        let in_buffer = |address: usize| {
+            self.controller_memory_buffer
+                .as_ref()
+                .is_some_and(|buffer| buffer.contains(address as u64))
+        };
+        if !in_buffer(submission_queue.get_addr()) {
+            submission_queue.deallocate(self.allocator.as_ref())?;
+        }
+        if !in_buffer(completion_queue.get_addr()) {
+            completion_queue.deallocate(self.allocator.as_ref())?;
+        }
+        Ok(())
+    }
+
+    fn new_io_queue_pair(
+        &self,
+        queue_id: IoQueuePairId,
+        namespace: Namespace,
+        submission_queue: SubmissionQueue,
+        completion_queue: CompletionQueue,
+    ) -> IoQueuePair<A> {
+        let command_ids = CommandIds::new(completion_queue.len());
+        IoQueuePair {
+            id: queue_id,
+            submission: submission_queue,
+            completion: completion_queue,
+            page_size: self.information.memory_page_size,
+            maximum_transfer_size: self.information.maximum_transfer_size,
+            allocator: self.allocator.clone(),
+            namespace,
+            device_address: self.address as usize,
+            device_length: self.length,
+            doorbell_stride: self.doorbell_stride,
+            prp_containers: HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0)),
+            prp_list_pool: prp::PrpListPool::new(PRP_LIST_POOL_CAPACITY),
+            sgl_support: self.information.sgl_support,
+            sgl_lists: HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0)),
+            outstanding_commands: 0,
+            stashed_completions: VecDeque::new(),
+            command_ids,
+            optional_nvm_command_support: self.information.optional_nvm_command_support,
+            wait_while_processing_paused: false,
+            #[cfg(feature = "std")]
+            char_device: None,
+            #[cfg(feature = "std")]
+            passthrough_completions: VecDeque::new(),
+            #[cfg(feature = "std")]
+            interrupt_event: None,
+            completion_callback: None,
+        }
+    }
+
+    pub fn delete_io_queue_pair(&mut self, queue_pair: IoQueuePair<A>) -> Result<(), Error> {
+        self.delete_io_queues(&queue_pair)?;
+        self.release_io_queue_pair(queue_pair)
+    }
+
+    /// Delete the queues of the `queue_pair` on the controller. Its ID is only freed once both
+    /// are deleted, so a queue pair whose deletion failed can be deleted again.
+    fn delete_io_queues(&mut self, queue_pair: &IoQueuePair<A>) -> Result<(), Error> {
+        debug!("Deleting I/O queue pair with ID {}", queue_pair.id.0);
+        let index = self
+            .io_queue_pair_ids
+            .iter()
+            .position(|id| id == &queue_pair.id)
+            .ok_or(Error::IoQueuePairDoesNotExist(queue_pair.id))?;
+        if matches!(self.admin, AdminTransport::Queue(_)) {
+            self.submit_and_complete_admin(|c_id, _| {
+                NvmeCommand::delete_io_submission_queue(c_id, queue_pair.id.0)
+            })?;
+            self.submit_and_complete_admin(|c_id, _| {
+                NvmeCommand::delete_io_completion_queue(c_id, queue_pair.id.0)
+            })?;
+        }
+        self.io_queue_pair_ids.remove(index);
+        if let Some(controller_memory_buffer) = &mut self.controller_memory_buffer {
+            controller_memory_buffer.release(queue_pair.id);
+        }
+        #[cfg(feature = "std")]
+        if queue_pair.interrupt_event.is_some() {
+            self.disable_interrupt_vector(queue_pair.id.0);
+        }
+        Ok(())
+    }
+
+    pub fn clear_namespace(&mut self, namespace_id: &NamespaceId) -> Result<(), Error> {
+        let lba_format_index = self.namespace(namespace_id)?.lba_format_index;
+        self.submit_and_complete_admin(|command_id, _| {
+            NvmeCommand::format_nvm(
+                command_id,
+                namespace_id.0,
+                lba_format_index,
+                SecureEraseSetting::UserDataErase,
+                MetadataSettings::default(),
+            )
+        })
+        .map(|_| ())
+    }
+
+    /// The I/O command set combinations supported by the controller.
+    /// Requires the controller to be enabled with CC.CSS set to "All Supported I/O Command Sets".
+    pub fn identify_io_command_sets(&mut self) -> Result<Vec<IoCommandSetCombination>, Error> {
+        let controller_id = self.information.controller_id;
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::identify_io_command_set(command_id, address, controller_id)
+        })?;
+        // the list ends at the first combination without any command set
+        Ok(self.buffer[..IO_COMMAND_SET_DATA_SIZE]
+            .chunks_exact(8)
+            .map(|entry| IoCommandSetCombination(u64::from_le_bytes(entry.try_into().unwrap())))
+            .take_while(|combination| combination.0 != 0)
+            .collect())
+    }
+
+    /// The zone size and the limits of open and active zones of the zoned namespace
+    /// with the `namespace_id`.
+    /// Requires the controller to be enabled with CC.CSS set to "All Supported I/O Command Sets".
+    pub fn zoned_namespace(&mut self, namespace_id: &NamespaceId) -> Result<ZonedNamespace, Error> {
+        let namespace = *self.namespace(namespace_id)?;
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::identify_namespace_command_set(
+                command_id,
                 address,
-                doorbell_stride,
+                namespace_id.0,
+                zns::ZONED_NAMESPACE_COMMAND_SET,
+            )
+        })?;
+        Ok(ZonedNamespace::from_bytes(namespace, &self.buffer[..4096]))
+    }
+
+    /// Read the Asymmetric Namespace Access log page with the ANA state of every ANA group,
+    /// which tells multipath users whether this controller is an optimized path to a namespace.
+    pub fn ana_log(&mut self) -> Result<AnaLog, Error> {
+        if !self.information.asymmetric_namespace_access_reporting {
+            return Err(Error::AnaReportingNotSupported);
+        }
+        let number_of_dwords = (self.buffer.size() / 4) as u32;
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::get_log_page(
+                command_id,
+                0,
+                number_of_dwords - 1,
+                address as u64,
+                0,
+                ANA_LOG_IDENTIFIER,
+                0,
+            )
+        })?;
+        Ok(AnaLog::from_bytes(&self.buffer[..]))
+    }
+
+    /// Read the SMART / Health Information log page of the namespace with the `namespace_id`,
+    /// or of the whole controller if `None`.
+    /// Per namespace information is only supported by some controllers.
+    pub fn smart_health_log(
+        &mut self,
+        namespace_id: Option<NamespaceId>,
+    ) -> Result<SmartHealthLog, Error> {
+        let log_namespace_id = match namespace_id {
+            Some(namespace_id) => self.namespace(&namespace_id)?.id.0,
+            None => 0xFFFF_FFFF,
+        };
+        let number_of_dwords = (SMART_HEALTH_LOG_SIZE / 4) as u32;
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::get_log_page(
+                command_id,
+                log_namespace_id,
+                number_of_dwords - 1,
+                address as u64,
+                0,
+                SMART_HEALTH_LOG_IDENTIFIER,
+                0,
+            )
+        })?;
+        Ok(SmartHealthLog::from_bytes(
+            &self.buffer[..SMART_HEALTH_LOG_SIZE],
+        ))
+    }
+
+    /// Post an Asynchronous Event Request on the admin queue, which the controller completes
+    /// once it reports an event, e.g. a temperature excursion or a media error.
+    /// At most [`ControllerInformation::asynchronous_event_request_limit`] requests may be
+    /// outstanding. Reported events are taken with [`Self::poll_async_events`].
+    pub fn submit_async_event_request(&mut self) -> Result<(), Error> {
+        let limit = self.information.asynchronous_event_request_limit;
+        let Some(admin_queue_pair) = self.admin.queue_pair_mut() else {
+            return Err(Error::AsyncEventRequestsNotSupported);
+        };
+        if admin_queue_pair.async_event_requests.len() >= limit as usize {
+            return Err(Error::AsyncEventRequestLimitExceeded(limit));
+        }
+        admin_queue_pair.submit_async_event_request(self.address, self.doorbell_stride)?;
+        Ok(())
+    }
+
+    /// The events reported by the controller since the last poll, without waiting for any.
+    /// The log page of each event is read, which clears the event so the controller reports
+    /// further events of its type, and a new Asynchronous Event Request is posted in place of
+    /// the completed one. Failed requests, e.g. aborted ones, are not posted again.
+    /// Every completion is handled even if reading a log page fails, which is reported in
+    /// the log of its event. If posting a new request fails, the first such failure is
+    /// returned after all completions are handled and the events are returned by the next poll.
+    pub fn poll_async_events(&mut self) -> Result<Vec<AsyncEvent>, Error> {
+        let Some(admin_queue_pair) = self.admin.queue_pair_mut() else {
+            return Err(Error::AsyncEventRequestsNotSupported);
+        };
+        let completions = admin_queue_pair.take_async_events(self.address, self.doorbell_stride);
+        let mut events = core::mem::take(&mut self.undelivered_async_events);
+        let mut result = Ok(());
+        for completion in completions {
+            let entry = match completion {
+                Ok(entry) => entry,
+                Err(error) => {
+                    debug!("Asynchronous Event Request failed: {error}");
+                    continue;
+                }
+            };
+            let (event_type, information, log_page_identifier) =
+                async_event::decode_dword_0(entry.command_specific);
+            let log = self.async_event_log(event_type, log_page_identifier);
+            events.push(AsyncEvent {
+                event_type,
+                information,
+                log_page_identifier,
+                log,
+            });
+            let submitted = self.submit_async_event_request();
+            if result.is_ok() {
+                result = submitted;
+            }
+        }
+        match result {
+            Ok(()) => Ok(events),
+            Err(error) => {
+                self.undelivered_async_events = events;
+                Err(error)
+            }
+        }
+    }
+
+    /// Read the log page with the details of an asynchronous event, which clears the event.
+    fn async_event_log(
+        &mut self,
+        event_type: AsyncEventType,
+        log_page_identifier: u8,
+    ) -> Result<AsyncEventLog, Error> {
+        match (event_type, log_page_identifier) {
+            (_, SMART_HEALTH_LOG_IDENTIFIER) => {
+                Ok(AsyncEventLog::SmartHealth(self.smart_health_log(None)?))
+            }
+            (_, ERROR_INFORMATION_LOG_IDENTIFIER) => {
+                let length = (self.information.error_log_page_entries as usize
+                    * ERROR_INFORMATION_ENTRY_SIZE)
+                    .min(self.buffer.size());
+                self.read_log_page_at_offset(ERROR_INFORMATION_LOG_IDENTIFIER, 0, 0, length)?;
+                Ok(AsyncEventLog::ErrorInformation(
+                    self.buffer[..length]
+                        .chunks_exact(ERROR_INFORMATION_ENTRY_SIZE)
+                        .map(ErrorInformationEntry::from_bytes)
+                        .filter(|entry| entry.error_count != 0)
+                        .collect(),
+                ))
+            }
+            _ => {
+                self.read_log_page_at_offset(log_page_identifier, 0, 0, OTHER_LOG_SIZE)?;
+                Ok(AsyncEventLog::Other(self.buffer[..OTHER_LOG_SIZE].to_vec()))
+            }
+        }
+    }
+
+    /// Create a new capture of the Telemetry Host-Initiated data and read it with data areas 1 to 3.
+    /// The log page may be several MiB big and is read in pieces of the admin buffer.
+    pub fn telemetry_host_initiated(&mut self) -> Result<Telemetry, Error> {
+        if !self.information.telemetry_supported {
+            return Err(Error::TelemetryNotSupported);
+        }
+        // reading the header with the "create" bit set captures the data
+        self.read_log_page_at_offset(
+            TELEMETRY_HOST_INITIATED_LOG_IDENTIFIER,
+            CREATE_TELEMETRY_HOST_INITIATED_DATA,
+            0,
+            TELEMETRY_BLOCK_SIZE,
+        )?;
+        let header = TelemetryHeader::from_bytes(&self.buffer[..TELEMETRY_BLOCK_SIZE]);
+        let size = header.size();
+        let mut data = Vec::with_capacity(size);
+        data.extend_from_slice(&self.buffer[..TELEMETRY_BLOCK_SIZE]);
+        let piece_size = self
+            .information
+            .maximum_transfer_size
+            .map_or(self.buffer.size(), |maximum_transfer_size| {
+                maximum_transfer_size.min(self.buffer.size())
+            });
+        while data.len() < size {
+            let length = piece_size.min(size - data.len());
+            self.read_log_page_at_offset(
+                TELEMETRY_HOST_INITIATED_LOG_IDENTIFIER,
+                0,
+                data.len() as u64,
+                length,
             )?;
+            data.extend_from_slice(&self.buffer[..length]);
+        }
+        Ok(Telemetry { header, data })
+    }
+
+    /// Read `length` bytes (a multiple of 4, at most the admin buffer size) of the log page
+    /// with the `log_identifier` at the byte `offset` into the admin buffer.
+    fn read_log_page_at_offset(
+        &mut self,
+        log_identifier: u8,
+        log_specific: u8,
+        offset: u64,
+        length: usize,
+    ) -> Result<(), Error> {
+        let number_of_dwords = (length / 4) as u32;
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::get_log_page_at_offset(
+                command_id,
+                0,
+                number_of_dwords - 1,
+                address as u64,
+                0,
+                log_identifier,
+                log_specific,
+                offset,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// The number of unrecovered data integrity errors of the whole controller
+    /// from the SMART / Health Information log page, saturated to `u64::MAX`.
+    pub fn media_and_data_integrity_errors(&mut self) -> Result<u64, Error> {
+        let log = self.smart_health_log(None)?;
+        Ok(u64::try_from(log.media_and_data_integrity_errors).unwrap_or(u64::MAX))
+    }
+
+    /// The critical warnings of the whole controller
+    /// from the SMART / Health Information log page.
+    pub fn critical_warnings(&mut self) -> Result<CriticalWarnings, Error> {
+        Ok(self.smart_health_log(None)?.critical_warnings())
+    }
+
+    /// The number of I/O queues currently allocated by the controller.
+    pub fn number_of_queues(&mut self) -> Result<NumberOfQueues, Error> {
+        let entry = self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::get_features(
+                command_id,
+                address,
+                FeatureIdentifier::NumberOfQueues,
+                Select::Current,
+            )
+        })?;
+        Ok(NumberOfQueues::from_u32(entry.command_specific))
+    }
+
+    /// Enable or disable the Deallocated or Unwritten Logical Block error (DULBE) of the
+    /// namespace with the `namespace_id`. Once enabled, reads of blocks never written or
+    /// deallocated fail with [`Error::DeallocatedOrUnwrittenBlock`] instead of returning
+    /// [`Namespace::deallocated_read_value`], see [`IoQueuePair::read_written`].
+    pub fn set_deallocated_or_unwritten_block_error(
+        &mut self,
+        namespace_id: &NamespaceId,
+        enable: bool,
+    ) -> Result<(), Error> {
+        if !self
+            .namespace(namespace_id)?
+            .deallocated_or_unwritten_block_error_supported
+        {
+            return Err(Error::DeallocatedOrUnwrittenBlockErrorNotSupported(
+                *namespace_id,
+            ));
+        }
+        let feature_id = FeatureIdentifier::ErrorRecovery;
+        // keep the Time Limited Error Recovery (TLER) in bits 15:0
+        let entry = self
+            .submit_and_complete_admin(|command_id, address| {
+                NvmeCommand::get_namespace_features(
+                    command_id,
+                    address,
+                    namespace_id.0,
+                    feature_id,
+                    Select::Current,
+                )
+            })
+            .map_err(|error| feature_error(feature_id, error))?;
+        let cdw11 = (entry.command_specific & 0xFFFF) | (enable as u32) << 16; // DULBE
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::set_features(command_id, address, namespace_id.0, feature_id, cdw11)
+        })
+        .map(|_| ())
+        .map_err(|error| feature_error(feature_id, error))
+    }
+
+    /// The read recovery level of the NVM set the namespace with the `namespace_id` belongs to.
+    pub fn read_recovery_level(&mut self, namespace_id: &NamespaceId) -> Result<u8, Error> {
+        self.namespace(namespace_id)?;
+        let entry = self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::get_namespace_features(
+                command_id,
+                address,
+                namespace_id.0,
+                FeatureIdentifier::ReadRecoveryLevelConfig,
+                Select::Current,
+            )
+        })?;
+        Ok((entry.command_specific & 0b1111) as u8)
+    }
+
+    /// Set the read recovery level (0 to 15) of the NVM set the namespace with the `namespace_id`
+    /// belongs to. Lower levels fail reads of marginal blocks faster,
+    /// higher levels try harder to recover the data.
+    /// The level must be one of [`ControllerInformation::read_recovery_levels_supported`].
+    pub fn set_read_recovery_level(
+        &mut self,
+        namespace_id: &NamespaceId,
+        level: u8,
+    ) -> Result<(), Error> {
+        self.namespace(namespace_id)?;
+        if level > 15 || (self.information.read_recovery_levels_supported >> level) & 0b1 == 0 {
+            return Err(Error::ReadRecoveryLevelNotSupported(level));
+        }
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::set_features(
+                command_id,
+                address,
+                namespace_id.0,
+                FeatureIdentifier::ReadRecoveryLevelConfig,
+                level as u32,
+            )
+        })
+        .map(|_| ())
+    }
+
+    /// The capabilities of every feature the controller supports.
+    /// Features rejected as an invalid field are not supported and skipped.
+    /// Requires support for the select field of Get Features
+    /// ([`OptionalNvmCommandSupport::save_field_in_features`]).
+    pub fn supported_features(&mut self) -> Result<Vec<(FeatureIdentifier, FeatureCaps)>, Error> {
+        let mut features = Vec::new();
+        for feature_id in FeatureIdentifier::ALL {
+            match self.feature_capabilities(feature_id) {
+                Ok(capabilities) => features.push((feature_id, capabilities)),
+                Err(Error::FeatureNotSupported(_)) => {
+                    debug!("Feature {feature_id:?} is not supported");
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(features)
+    }
+
+    /// The current value (dword 0 of the completion) of every feature the controller supports,
+    /// to inspect or snapshot its configuration. Unsupported features are skipped,
+    /// as are namespace specific features the controller requires a namespace for.
+    pub fn dump_features(&mut self) -> Result<Vec<(FeatureIdentifier, u32)>, Error> {
+        let mut features = Vec::new();
+        for feature_id in FeatureIdentifier::ALL {
+            match self.get_feature(feature_id) {
+                Ok(dword_0) => features.push((feature_id, dword_0)),
+                Err(Error::FeatureNotSupported(_)) => {
+                    debug!("Feature {feature_id:?} is not supported");
+                }
+                Err(Error::IoCompletionQueueFailure(_, _, status))
+                    if status.code == StatusCode::InvalidNamespaceOrFormat =>
+                {
+                    debug!("Feature {feature_id:?} is namespace specific");
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(features)
+    }
+
+    /// The capabilities of the feature with the `feature_id`,
+    /// [`Error::FeatureNotSupported`] if the controller rejects it as an invalid field.
+    fn feature_capabilities(
+        &mut self,
+        feature_id: FeatureIdentifier,
+    ) -> Result<FeatureCaps, Error> {
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::get_features(
+                command_id,
+                address,
+                feature_id,
+                Select::SupportedCapabilites,
+            )
+        })
+        .map(|entry| FeatureCaps::from_u32(entry.command_specific))
+        .map_err(|error| feature_error(feature_id, error))
+    }
+
+    /// Checks that the controller supports the feature with the `feature_id`,
+    /// if it supports querying the capabilities of features.
+    /// Otherwise an unsupported feature is only detected when getting or setting it.
+    fn check_feature_supported(&mut self, feature_id: FeatureIdentifier) -> Result<(), Error> {
+        if self
+            .information
+            .optional_nvm_command_support
+            .save_field_in_features
+        {
+            self.feature_capabilities(feature_id)?;
+        }
+        Ok(())
+    }
+
+    /// Gets the current value (dword 0 of the completion) of the feature with the `feature_id`.
+    fn get_feature(&mut self, feature_id: FeatureIdentifier) -> Result<u32, Error> {
+        self.check_feature_supported(feature_id)?;
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::get_features(command_id, address, feature_id, Select::Current)
+        })
+        .map(|entry| entry.command_specific)
+        .map_err(|error| feature_error(feature_id, error))
+    }
+
+    /// Sets the feature with the `feature_id` to the value in `cdw11`.
+    fn set_feature(&mut self, feature_id: FeatureIdentifier, cdw11: u32) -> Result<u32, Error> {
+        self.check_feature_supported(feature_id)?;
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::set_features(command_id, address, 0, feature_id, cdw11)
+        })
+        .map(|entry| entry.command_specific)
+        .map_err(|error| feature_error(feature_id, error))
+    }
+
+    /// Abort the command with `command_id` on the I/O queue pair with `queue_pair_id`, e.g.
+    /// after it timed out. Returns whether the controller aborted it; it may also have
+    /// completed already or be too far along. An aborted command is still completed on its
+    /// queue pair, with the status "Command Abort Requested", which frees its resources once
+    /// reaped, e.g. by [`IoQueuePair::poll_completions`].
+    /// Fails with [`Error::AbortCommandLimitExceeded`] if more aborts than the limit of the
+    /// controller ([`ControllerInformation::abort_command_limit`]) are outstanding.
+    pub fn abort(&mut self, queue_pair_id: IoQueuePairId, command_id: u16) -> Result<bool, Error> {
+        if !self.io_queue_pair_ids.contains(&queue_pair_id) {
+            return Err(Error::IoQueuePairDoesNotExist(queue_pair_id));
+        }
+        let limit = self.information.abort_command_limit;
+        let entry = self
+            .submit_and_complete_admin(|abort_id, _| {
+                NvmeCommand::abort(abort_id, queue_pair_id.0, command_id)
+            })
+            .map_err(|error| match error {
+                Error::IoCompletionQueueFailure(_, _, status)
+                    if status.code == StatusCode::AbortCommandLimitExceeded =>
+                {
+                    Error::AbortCommandLimitExceeded(limit)
+                }
+                error => error,
+            })?;
+        // bit 0 is cleared if the command was aborted
+        Ok(entry.command_specific & 0b1 == 0)
+    }
+
+    /// Set the Keep Alive Timeout (KATO) to `milliseconds`, rounded up by the controller to
+    /// [`ControllerInformation::keep_alive_granularity_milliseconds`]; 0 disables it.
+    /// Once set, [`Self::keep_alive`] must be called within the timeout, otherwise the
+    /// controller assumes the host is gone and may tear down its queues.
+    pub fn set_keep_alive_timeout(&mut self, milliseconds: u32) -> Result<(), Error> {
+        if self.information.keep_alive_granularity_milliseconds == 0 {
+            return Err(Error::KeepAliveNotSupported);
+        }
+        self.set_feature(FeatureIdentifier::KeepAliveTimer, milliseconds)
+            .map(|_| ())
+    }
+
+    /// Send a Keep Alive command, which restarts the keep alive timer of the controller.
+    /// Call it periodically, well within the timeout set with [`Self::set_keep_alive_timeout`].
+    pub fn keep_alive(&mut self) -> Result<(), Error> {
+        if self.information.keep_alive_granularity_milliseconds == 0 {
+            return Err(Error::KeepAliveNotSupported);
+        }
+        self.submit_and_complete_admin(|command_id, _| NvmeCommand::keep_alive(command_id))
+            .map(|_| ())
+    }
+
+    /// The temperature threshold in Kelvin of the `sensor` (0 for the composite temperature,
+    /// 1 to 8 for the temperature sensors of the SMART / Health Information log page).
+    pub fn temperature_threshold(&mut self, sensor: u8, kind: ThresholdKind) -> Result<u16, Error> {
+        let cdw11 = temperature_threshold_select(sensor, kind)?;
+        let feature_id = FeatureIdentifier::TemperatureThreshold;
+        self.check_feature_supported(feature_id)?;
+        self.submit_and_complete_admin(|command_id, address| {
+            let mut command =
+                NvmeCommand::get_features(command_id, address, feature_id, Select::Current);
+            command.cdw11 = cdw11;
+            command
+        })
+        .map(|entry| entry.command_specific as u16) // TMPTH
+        .map_err(|error| feature_error(feature_id, error))
+    }
+
+    /// Set the temperature threshold of the `sensor` (see [`Self::temperature_threshold`])
+    /// to `kelvin`. Crossing it sets the temperature warning of the SMART / Health Information
+    /// log page and reports an asynchronous event, if enabled.
+    pub fn set_temperature_threshold(
+        &mut self,
+        sensor: u8,
+        kind: ThresholdKind,
+        kelvin: u16,
+    ) -> Result<(), Error> {
+        let cdw11 = temperature_threshold_select(sensor, kind)? | kelvin as u32;
+        self.set_feature(FeatureIdentifier::TemperatureThreshold, cdw11)
+            .map(|_| ())
+    }
+
+    /// Whether the volatile write cache is enabled, so completed writes may still be lost
+    /// on power loss until they are flushed.
+    pub fn write_cache_enabled(&mut self) -> Result<bool, Error> {
+        let dword_0 = self.get_feature(FeatureIdentifier::VolatileWriteCache)?;
+        Ok(dword_0 & 0b1 == 1) // WCE
+    }
+
+    /// Enable or disable the volatile write cache. With the cache disabled, writes are
+    /// durable once completed, without a flush. Fails with [`Error::FeatureNotSupported`]
+    /// if the controller has no volatile write cache and with [`Error::FeatureNotChangeable`]
+    /// if it may not be changed.
+    pub fn set_write_cache_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.set_feature(FeatureIdentifier::VolatileWriteCache, enabled as u32)
+            .map(|_| ())
+    }
+
+    /// Whether spinup control is enabled, which staggers the spinup of rotational media
+    /// to limit the inrush current in enclosures with many drives.
+    pub fn spinup_control(&mut self) -> Result<bool, Error> {
+        let dword_0 = self.get_feature(FeatureIdentifier::SpinupControl)?;
+        Ok(dword_0 & 0b1 == 1) // SUPC
+    }
+
+    /// Enable or disable spinup control, see [`Self::spinup_control`].
+    pub fn set_spinup_control(&mut self, enable: bool) -> Result<(), Error> {
+        self.set_feature(FeatureIdentifier::SpinupControl, enable as u32)
+            .map(|_| ())
+    }
+
+    /// The power loss signaling mode (PLSM), which selects how the controller reacts to
+    /// the power loss signal, e.g. by flushing with emergency power; 0 if disabled.
+    pub fn power_loss_signaling_mode(&mut self) -> Result<u8, Error> {
+        let dword_0 = self.get_feature(FeatureIdentifier::PowerLossSignalingConfig)?;
+        Ok((dword_0 & 0b111) as u8) // PLSM
+    }
+
+    /// Set the power loss signaling mode (PLSM), only bits 2:0 of `mode` are used.
+    pub fn set_power_loss_signaling_mode(&mut self, mode: u8) -> Result<(), Error> {
+        self.set_feature(
+            FeatureIdentifier::PowerLossSignalingConfig,
+            (mode & 0b111) as u32,
+        )
+        .map(|_| ())
+    }
+
+    /// The behaviors the host has enabled with [`Self::set_host_behavior`].
+    pub fn host_behavior(&mut self) -> Result<HostBehavior, Error> {
+        self.get_feature(FeatureIdentifier::HostBehaviorSupport)?;
+        Ok(HostBehavior::from_bytes(&self.buffer[..HOST_BEHAVIOR_SIZE]))
+    }
+
+    /// Tell the controller which optional behaviors the host supports (Host Behavior Support).
+    /// With [`HostBehavior::advanced_command_retry_enable`], failed commands the controller
+    /// expects to succeed later report a Command Retry Delay instead of Do Not Retry,
+    /// so retrying commands whose status has Do Not Retry cleared becomes worthwhile.
+    pub fn set_host_behavior(&mut self, host_behavior: HostBehavior) -> Result<(), Error> {
+        host_behavior.to_bytes(&mut self.buffer[..HOST_BEHAVIOR_SIZE]);
+        self.set_feature(FeatureIdentifier::HostBehaviorSupport, 0)
+            .map(|_| ())
+    }
 
-            let namespace_data: IdentifyNamespace =
-                unsafe { (*(buffer.virtual_address() as *const IdentifyNamespace)).clone() };
+    /// Send a vendor specific admin command (opcodes 0xC0 to 0xFF) for the namespace
+    /// `namespace_id` (0 if it applies to the controller) with the command dwords
+    /// 10 to 15 set to `cdws` and the data transferred to or from `buffer`.
+    /// Returns the dwords 0 to 3 of the completion queue entry.
+    pub fn vendor_admin(
+        &mut self,
+        opcode: u8,
+        namespace_id: u32,
+        cdws: [u32; 6],
+        buffer: Option<&Dma<u8>>,
+    ) -> Result<[u32; 4], Error> {
+        if !VENDOR_SPECIFIC_ADMIN_OPCODES.contains(&opcode) {
+            return Err(Error::OpcodeNotVendorSpecific(opcode));
+        }
+        let prp_container = match (&mut self.admin, buffer) {
+            (AdminTransport::Queue(admin_queue_pair), Some(buffer)) => Some(prp::allocate(
+                buffer,
+                self.information.memory_page_size,
+                self.allocator.as_ref(),
+                &mut admin_queue_pair.prp_list_pool,
+            )?),
+            (AdminTransport::Queue(_), None) => None,
+            #[cfg(feature = "std")]
+            (AdminTransport::CharDevice(char_device), buffer) => {
+                let command = NvmeCommand::vendor_specific(0, opcode, namespace_id, 0, 0, cdws);
+                let (data, data_length) = buffer.map_or((core::ptr::null_mut(), 0), |buffer| {
+                    (buffer.virtual_address(), buffer.size())
+                });
+                return Ok([char_device.admin(&command, data, data_length)?, 0, 0, 0]);
+            }
+        };
+        let (prp_1, prp_2) = prp_container.as_ref().map_or((0, 0), |prp_container| {
+            (
+                prp_container.prp_1() as usize,
+                prp_container.prp_2().unwrap_or(core::ptr::null_mut()) as usize,
+            )
+        });
+        let result = self.submit_and_complete_admin(|command_id, _| {
+            NvmeCommand::vendor_specific(command_id, opcode, namespace_id, prp_1, prp_2, cdws)
+        });
+        if let (Some(prp_container), Some(admin_queue_pair)) =
+            (prp_container, self.admin.queue_pair_mut())
+        {
+            prp::deallocate(
+                prp_container,
+                self.allocator.as_ref(),
+                &mut admin_queue_pair.prp_list_pool,
+            )?;
+        }
+        let entry = result?;
+        Ok([
+            entry.command_specific,
+            entry._reserved,
+            ((entry.sq_id as u32) << 16) | entry.sq_head as u32,
+            ((entry.status as u32) << 16) | entry.command_id as u32,
+        ])
+    }
 
-            // figure out block size
-            let flba_index = (namespace_data.formatted_lba_size & 0xF) as usize;
-            let flba_data = (namespace_data.lba_formats_list[flba_index] >> 16) & 0xFF;
-            let block_size = if !(9..32).contains(&flba_data) {
-                0
-            } else {
-                1 << flba_data
-            };
+    /// Format the namespace with the LBA format at index `lba_format` without metadata
+    /// settings and wait until the namespace is ready again and reports the new format.
+    /// Returns the updated namespace, which also replaces the cached one.
+    /// I/O queue pairs created for the namespace before keep the old block size
+    /// and must be recreated.
+    pub fn reformat_namespace(
+        &mut self,
+        namespace_id: NamespaceId,
+        lba_format: u8,
+        secure_erase: SecureEraseSetting,
+    ) -> Result<Namespace, Error> {
+        self.format_namespace(
+            namespace_id,
+            lba_format,
+            secure_erase,
+            MetadataSettings::default(),
+        )?;
+        self.namespace(&namespace_id).copied()
+    }
 
-            // TODO: check metadata?
-            let namespace = Namespace {
-                id: namespace_id,
-                blocks: namespace_data.namespace_capacity,
-                block_size,
-            };
-            debug!("{namespace:?}");
-            namespaces.insert(namespace_id, namespace);
+    /// Format the namespace with the LBA format at index `lba_format` (see
+    /// [`Namespace::lba_formats`]) and the `metadata` settings, and wait until the namespace
+    /// is ready again and reports the new format, which then replaces the cached namespace.
+    /// I/O queue pairs created for the namespace before keep the old block size
+    /// and must be recreated.
+    /// Fails with [`Error::InvalidProtectionInformationType`] if the protection information
+    /// type of the `metadata` is not 0 to 3, and with [`Error::FormatTimeout`] if the namespace
+    /// does not report the new format within the controller timeout (CAP.TO).
+    pub fn format_namespace(
+        &mut self,
+        namespace_id: NamespaceId,
+        lba_format: u8,
+        secure_erase: SecureEraseSetting,
+        metadata: MetadataSettings,
+    ) -> Result<(), Error> {
+        self.namespace(&namespace_id)?;
+        if metadata.protection_information > 3 {
+            return Err(Error::InvalidProtectionInformationType(
+                metadata.protection_information,
+            ));
+        }
+        let namespace_data = self.identify_namespace_data(namespace_id)?;
+        if lba_format > namespace_data.number_of_lba_formats || lba_format >= 64 {
+            return Err(Error::InvalidLbaFormat(lba_format));
         }
+        let lba_data_size = (namespace_data.lba_formats_list[lba_format as usize] >> 16) & 0xFF; // LBADS
+        let expected_block_size = if (9..32).contains(&lba_data_size) {
+            1 << lba_data_size
+        } else {
+            return Err(Error::InvalidLbaFormat(lba_format));
+        };
 
-        Ok(Self {
-            allocator: Arc::new(allocator),
-            address,
-            doorbell_stride,
-            length,
-            admin_queue_pair,
-            io_queue_pair_ids: Vec::new(),
-            buffer,
-            information,
-            namespaces,
-        })
+        debug!(
+            "Format namespace {} with LBA format {lba_format}",
+            namespace_id.0
+        );
+        self.submit_and_complete_admin(|command_id, _| {
+            NvmeCommand::format_nvm(
+                command_id,
+                namespace_id.0,
+                lba_format,
+                secure_erase,
+                metadata,
+            )
+        })?;
+
+        // The namespace may be reported as not ready until the format has been applied.
+        let mut deadline =
+            Deadline::after_milliseconds(self.information.timeout_milliseconds as u64);
+        let namespace = loop {
+            match self.identify_namespace(namespace_id) {
+                Ok(namespace) if namespace.block_size == expected_block_size => break namespace,
+                Ok(_) | Err(Error::FormatInProgress) => {}
+                Err(Error::IoCompletionQueueFailure(_, _, status))
+                    if status.code == StatusCode::NamespaceNotReady => {}
+                Err(error) => return Err(error),
+            }
+            if deadline.expired() {
+                return Err(Error::FormatTimeout(namespace_id));
+            }
+            spin_loop();
+        };
+        debug!("{namespace:?}");
+        self.namespaces.insert(namespace_id, namespace);
+        Ok(())
     }
 
-    pub fn controller_information(&self) -> &ControllerInformation {
-        &self.information
+    /// Wait until a format of the namespace has completed, e.g. after commands to it failed
+    /// with [`Error::FormatInProgress`], and return the namespace, which also replaces the
+    /// cached one.
+    /// Polls the Format Progress Indicator (FPI) if the namespace reports it,
+    /// otherwise until identifying the namespace no longer fails because of the format.
+    /// Formats, especially with a secure erase, may take far longer than the controller timeout,
+    /// so the caller sets the `timeout_milliseconds` after which this fails with
+    /// [`Error::FormatTimeout`].
+    pub fn wait_for_format(
+        &mut self,
+        namespace_id: NamespaceId,
+        timeout_milliseconds: u64,
+    ) -> Result<Namespace, Error> {
+        self.namespace(&namespace_id)?;
+        let mut deadline = Deadline::after_milliseconds(timeout_milliseconds);
+        let namespace_data = loop {
+            match self.identify_namespace_data(namespace_id) {
+                Ok(namespace_data) => {
+                    let format_progress_indicator = namespace_data.format_progress_indicator;
+                    let supported = (format_progress_indicator >> 7) & 0b1 == 1;
+                    let percentage_remaining = format_progress_indicator & 0b0111_1111;
+                    if !supported || percentage_remaining == 0 {
+                        break namespace_data;
+                    }
+                    debug!(
+                        "Format of namespace {}: {percentage_remaining}% remaining",
+                        namespace_id.0
+                    );
+                }
+                Err(Error::FormatInProgress) => {}
+                Err(Error::IoCompletionQueueFailure(_, _, status))
+                    if status.code == StatusCode::NamespaceNotReady => {}
+                Err(error) => return Err(error),
+            }
+            if deadline.expired() {
+                return Err(Error::FormatTimeout(namespace_id));
+            }
+            spin_loop();
+        };
+        let namespace = Namespace::from_identify_namespace(namespace_id, &namespace_data);
+        self.namespaces.insert(namespace_id, namespace);
+        Ok(namespace)
     }
 
-    pub fn namespace_ids(&self) -> Vec<NamespaceId> {
-        self.namespaces.keys().copied().collect()
+    fn identify_namespace_data(
+        &mut self,
+        namespace_id: NamespaceId,
+    ) -> Result<IdentifyNamespace, Error> {
+        self.submit_and_complete_admin(|command_id, address| {
+            NvmeCommand::identify_namespace(command_id, address, namespace_id.0)
+        })?;
+        Ok(unsafe { (*(self.buffer.virtual_address() as *const IdentifyNamespace)).clone() })
     }
 
-    pub fn namespace(&self, namespace_id: &NamespaceId) -> Result<&Namespace, Error> {
-        self.namespaces
-            .get(namespace_id)
-            .ok_or(Error::NamespaceDoesNotExist(*namespace_id))
+    fn identify_namespace(&mut self, namespace_id: NamespaceId) -> Result<Namespace, Error> {
+        let namespace_data = self.identify_namespace_data(namespace_id)?;
+        Ok(Namespace::from_identify_namespace(
+            namespace_id,
+            &namespace_data,
+        ))
     }
 
-    /// Create a pair consisting of 1 submission and 1 completion queue.
-    pub fn create_io_queue_pair(
-        &mut self,
-        namespace_id: &NamespaceId,
-        number_of_queue_entries: u32,
-    ) -> Result<IoQueuePair<A>, Error> {
-        if number_of_queue_entries < 2 {
-            return Err(Error::NumberOfQueueEntriesLessThanTwo(
-                number_of_queue_entries,
+    /// Read the beginning of the boot partition with the ID `partition` (0 or 1) into the `buffer`.
+    /// The size of the `buffer` must be a non-zero multiple of 4 KiB
+    /// and must not exceed the size of the boot partition. The controller writes the data
+    /// starting at a single address, so the `buffer` must be physically contiguous.
+    /// Fails with [`Error::BootPartitionReadTimeout`] if the read does not complete within the
+    /// controller timeout (CAP.TO).
+    pub fn read_boot_partition(&mut self, partition: u8, buffer: &Dma<u8>) -> Result<(), Error> {
+        if !self.information.boot_partition_support {
+            return Err(Error::BootPartitionsNotSupported);
+        }
+        if partition > 1 {
+            return Err(Error::BootPartitionIdInvalid(partition));
+        }
+        const BOOT_PARTITION_READ_UNIT: usize = 4096;
+        let bpinfo = get_register_32(NvmeRegs32::BPINFO, self.address, self.length)?;
+        let boot_partition_size = (bpinfo & 0x7FFF) as usize * 128 * 1024; // BPSZ (converted)
+        let read_size = buffer.size() / BOOT_PARTITION_READ_UNIT;
+        if buffer.size() == 0
+            || !buffer.size().is_multiple_of(BOOT_PARTITION_READ_UNIT)
+            || buffer.size() > boot_partition_size
+            || read_size > 0x3FF
+        {
+            return Err(Error::BootPartitionReadSizeInvalid(
+                buffer.size(),
+                boot_partition_size,
             ));
         }
-        if number_of_queue_entries > self.information.maximum_queue_entries_supported {
-            return Err(Error::NumberOfQueueEntriesMoreThanMaximum(
-                number_of_queue_entries,
-                self.information.maximum_queue_entries_supported,
+        if !(buffer.physical_address() as usize).is_multiple_of(BOOT_PARTITION_READ_UNIT) {
+            return Err(Error::PhysicalAddressIsNotPageAligned(
+                buffer.physical_address() as usize,
+            ));
+        }
+        // the controller writes the whole read from the single address in BPMBL
+        if !buffer
+            .is_physically_contiguous(self.information.memory_page_size, self.allocator.as_ref())?
+        {
+            return Err(Error::BootPartitionBufferNotPhysicallyContiguous(
+                buffer.size(),
             ));
         }
-        let namespace = *self.namespace(namespace_id)?;
 
-        // Simple way to avoid collisions while reusing some previously deleted keys.
-        let mut index_option = None;
-        for i in 1..=self.information.maximum_number_of_io_queue_pairs {
-            if !self.io_queue_pair_ids.contains(&IoQueuePairId(i)) {
-                index_option = Some(IoQueuePairId(i));
-                break;
+        debug!("Read boot partition {partition}");
+        set_register_64(
+            NvmeRegs64::BPMBL,
+            buffer.physical_address() as u64,
+            self.address,
+            self.length,
+        )?;
+        // Boot Partition Read Offset (BPROF) of 0
+        let bprsel = ((partition as u32) << 31) | read_size as u32; // BPID | BPRSZ
+        set_register_32(NvmeRegs32::BPRSEL, bprsel, self.address, self.length)?;
+
+        // Wait for the Boot Read Status (BRS) to signal completion
+        let mut deadline =
+            Deadline::after_milliseconds(self.information.timeout_milliseconds as u64);
+        loop {
+            let bpinfo = get_register_32(NvmeRegs32::BPINFO, self.address, self.length)?;
+            match (bpinfo >> 24) & 0b11 {
+                0b10 => break,
+                0b11 => return Err(Error::BootPartitionReadFailed(partition)),
+                _ if deadline.expired() => return Err(Error::BootPartitionReadTimeout(partition)),
+                _ => spin_loop(),
             }
         }
-        let queue_id = index_option.ok_or(Error::MaximumNumberOfQueuesReached)?;
+        Ok(())
+    }
 
-        debug!("Requesting I/O queue pair with ID {}", queue_id.0);
+    /// This initiates a normal Memory-based Controller Shutdown (PCIe).
+    /// Devices opened by PCI address are bound to their previous kernel driver afterwards.
+    /// `all_io_queue_pairs` must contain every queue pair not deleted yet,
+    /// as the queue pairs access the device; fails with [`Error::IoQueuePairsRemaining`] otherwise.
+    /// On failure the device is handed back with the queue pairs not deleted yet and the error,
+    /// e.g. to shut it down again with them; steps completed before are not repeated.
+    pub fn shutdown(
+        mut self,
+        mut all_io_queue_pairs: Vec<IoQueuePair<A>>,
+    ) -> Result<(), ShutdownError<A>> {
+        match self.shutdown_steps(&mut all_io_queue_pairs) {
+            Ok(()) => Ok(()),
+            Err(error) => Err((Box::new(self), all_io_queue_pairs, error)),
+        }
+    }
 
-        let offset = 0x1000 + ((4 << self.doorbell_stride) * (2 * queue_id.0 + 1) as usize);
-        assert!(
-            offset <= self.length - 4,
-            "SQ doorbell offset out of bounds"
-        );
+    /// Queue pairs are only removed from `all_io_queue_pairs` once they are deleted.
+    fn shutdown_steps(
+        &mut self,
+        all_io_queue_pairs: &mut Vec<IoQueuePair<A>>,
+    ) -> Result<(), Error> {
+        while let Some(io_queue_pair) = all_io_queue_pairs.last() {
+            self.delete_io_queues(io_queue_pair)?;
+            let io_queue_pair = all_io_queue_pairs.pop().unwrap();
+            self.release_io_queue_pair(io_queue_pair)?;
+        }
+        if !self.io_queue_pair_ids.is_empty() {
+            return Err(Error::IoQueuePairsRemaining(self.io_queue_pair_ids.len()));
+        }
+        // `Drop` still runs afterwards, but neither deallocates the buffer nor shuts down again.
+        // The buffer is null once deallocated by a previous attempt.
+        if !self.buffer.virtual_address().is_null() {
+            let buffer = core::mem::replace(&mut self.buffer, unsafe { Dma::new_uninitialized() });
+            buffer.deallocate(self.allocator.as_ref())?;
+        }
+        if let Some(admin_queue_pair) = self.admin.queue_pair_mut() {
+            admin_queue_pair
+                .prp_list_pool
+                .clear(self.allocator.as_ref())?;
+        }
 
-        let dbl = self.address as usize + offset;
-        let completion_queue = CompletionQueue::new(
-            number_of_queue_entries as usize,
-            self.information.memory_page_size,
-            dbl,
-            self.allocator.as_ref(),
-        )?;
-        self.submit_and_complete_admin(|c_id, _| {
-            NvmeCommand::create_io_completion_queue(
-                c_id,
-                queue_id.0,
-                completion_queue.get_addr(),
-                (number_of_queue_entries - 1) as u16,
-            )
-        })?;
+        // The kernel driver is responsible for controllers accessed through its character device.
+        if self.admin.queue_pair_mut().is_some() {
+            self.notify_shutdown(false)?;
+        }
 
-        let dbl = self.address as usize
-            + 0x1000
-            + ((4 << self.doorbell_stride) * (2 * queue_id.0) as usize);
-        let submission_queue = SubmissionQueue::new(
-            number_of_queue_entries as usize,
-            self.information.memory_page_size,
-            dbl,
-            self.allocator.as_ref(),
-        )?;
-        self.submit_and_complete_admin(|c_id, _| {
-            NvmeCommand::create_io_submission_queue(
-                c_id,
-                queue_id.0,
-                submission_queue.get_addr(),
-                (number_of_queue_entries - 1) as u16,
-                queue_id.0,
-            )
-        })?;
+        // The BAR was mapped by `from_pci_address` or `from_vfio`,
+        // mappings given to `new` belong to the caller.
+        #[cfg(feature = "std")]
+        if let Some(bar) = self.bar.take() {
+            debug!("Unmap BAR");
+            bar.unmap().map_err(Error::UnixPciError)?;
+        }
 
-        let io_queue_pair = IoQueuePair {
-            id: queue_id,
-            submission: submission_queue,
-            completion: completion_queue,
-            page_size: self.information.memory_page_size,
-            maximum_transfer_size: self.information.maximum_transfer_size,
-            allocator: self.allocator.clone(),
-            namespace,
-            device_address: self.address as usize,
-            doorbell_stride: self.doorbell_stride,
-            prp_containers: HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0)),
-        };
-        self.io_queue_pair_ids.push(queue_id);
-        Ok(io_queue_pair)
+        // Return the device to the kernel driver it was taken from.
+        #[cfg(feature = "std")]
+        if let (Some(pci_address), Some(driver)) = (&self.pci_address, &self.kernel_driver) {
+            debug!("Bind kernel driver {driver}");
+            pci::bind_driver(pci_address, driver).map_err(Error::UnixPciError)?;
+        }
+        Ok(())
     }
 
-    pub fn delete_io_queue_pair(&mut self, queue_pair: IoQueuePair<A>) -> Result<(), Error> {
-        debug!("Deleting I/O queue pair with ID {}", queue_pair.id.0);
-        let index = self
-            .io_queue_pair_ids
-            .iter()
-            .position(|id| id == &queue_pair.id)
-            .ok_or(Error::IoQueuePairDoesNotExist(queue_pair.id))?;
-        self.io_queue_pair_ids.remove(index);
-        self.submit_and_complete_admin(|c_id, _| {
-            NvmeCommand::delete_io_submission_queue(c_id, queue_pair.id.0)
-        })?;
-        self.submit_and_complete_admin(|c_id, _| {
-            NvmeCommand::delete_io_completion_queue(c_id, queue_pair.id.0)
-        })?;
+    /// Disable the controller (CC.EN = 0) and wait until it is no longer ready.
+    /// All queues of the controller, including the admin queue, are deleted by the controller;
+    /// I/O queue pairs must be recreated after enabling it again.
+    pub fn disable(&mut self) -> Result<(), Error> {
+        debug!("Disable controller");
+        disable_controller(
+            self.address,
+            self.length,
+            self.information.timeout_milliseconds,
+        )?;
+        // The controller starts again at the first entry of the admin queues once enabled.
+        if let Some(admin_queue_pair) = self.admin.queue_pair_mut() {
+            admin_queue_pair.submission.reset();
+            admin_queue_pair.completion.reset();
+            admin_queue_pair.outstanding_commands = 0;
+            admin_queue_pair.completed.clear();
+            admin_queue_pair.async_event_requests.clear();
+        }
         Ok(())
     }
 
-    pub fn clear_namespace(&mut self, namespace_id: &NamespaceId) -> Result<(), Error> {
-        self.admin_queue_pair
-            .submit_and_complete(
-                |command_id, _| NvmeCommand::format_nvm(command_id, namespace_id.0),
-                &self.buffer,
-                self.address,
-                self.doorbell_stride,
-            )
-            .map(|_| ())
+    /// Enable the controller (CC.EN = 1) with the current controller configuration
+    /// and admin queue registers and wait until it is ready.
+    /// Fails with [`Error::ControllerFatalStatus`] if the controller reports a fatal error
+    /// and with [`Error::ControllerReadyTimeout`] if it is not ready within its timeout (CAP.TO).
+    pub fn enable(&mut self) -> Result<(), Error> {
+        debug!("Enable controller");
+        // clear a shutdown notification (SHN) of a previous shutdown
+        let cc = get_register_32(NvmeRegs32::CC, self.address, self.length)? & !(0b11 << 14);
+        set_register_32(NvmeRegs32::CC, cc | 0b1, self.address, self.length)?;
+        wait_for_ready(
+            true,
+            self.address,
+            self.length,
+            self.information.timeout_milliseconds,
+        )?;
+        self.shut_down = false;
+        Ok(())
     }
 
-    /// This initiates a normal Memory-based Controller Shutdown (PCIe).
-    pub fn shutdown(mut self, all_io_queue_pairs: Vec<IoQueuePair<A>>) -> Result<(), Error> {
-        for io_queue_pair in all_io_queue_pairs {
-            self.delete_io_queue_pair(io_queue_pair)?;
+    /// Make the Controller Memory Buffer usable for queues with
+    /// [`Self::create_io_queue_pair_with_placement`]. `bar_physical_address` is the address the
+    /// BAR is mapped at on the PCI bus, e.g. read from the BAR register in the configuration space.
+    /// Devices opened with [`Self::from_pci_address`] enable the buffer themselves.
+    /// Only buffers in the BAR mapped by this crate (BAR 0) are supported.
+    pub fn enable_controller_memory_buffer(
+        &mut self,
+        bar_physical_address: u64,
+    ) -> Result<(), Error> {
+        let buffer = self
+            .information
+            .controller_memory_buffer
+            .ok_or(Error::ControllerMemoryBufferNotAvailable)?;
+        if buffer.bar != 0 || buffer.offset + buffer.size > self.length as u64 {
+            return Err(Error::ControllerMemoryBufferNotAvailable);
         }
-        self.buffer.deallocate(self.allocator.as_ref())?;
+        let cap = get_register_64(NvmeRegs64::CAP, self.address, self.length)?;
+        if (cap >> 57) & 0b1 == 1 {
+            // CBA | CMSE | CRE, so the controller accepts accesses to the buffer
+            let cmbmsc = (bar_physical_address & !0xFFF) | 0b11;
+            set_register_64(NvmeRegs64::CMBMSC, cmbmsc, self.address, self.length)?;
+        }
+        debug!("Enable controller memory buffer {buffer:?}");
+        self.controller_memory_buffer = Some(MappedControllerMemoryBuffer::new(
+            buffer,
+            self.address as usize + buffer.offset as usize,
+            bar_physical_address + buffer.offset,
+        ));
+        Ok(())
+    }
 
-        debug!("Send shutdown signal");
-        let mut cc = get_register_32(NvmeRegs32::CC, self.address, self.length)?;
-        // Set Shutdown (SHN) to 0b01
-        cc &= 0b1111_1111_1111_1111_0111_1111_1111_1111;
-        cc |= 0b0000_0000_0000_0000_0100_0000_0000_0000;
-        set_register_32(NvmeRegs32::CC, cc, self.address, self.length)?;
+    /// Reset the controller to recover it, e.g. from a fatal status (CSTS.CFS):
+    /// disable it, program the admin queue registers again, enable it and identify the
+    /// controller and its namespaces again.
+    /// The controller deletes all I/O queues, so `all_io_queue_pairs` must contain every
+    /// queue pair not deleted yet; they have to be recreated. Their host memory is deallocated:
+    /// the queues not placed in the controller memory buffer, the PRP list pages and the PRP and
+    /// SGL lists of commands in flight. PRP lists registered with
+    /// [`IoQueuePair::build_prp_list`] are owned by the caller and not released.
+    pub fn reset(&mut self, all_io_queue_pairs: Vec<IoQueuePair<A>>) -> Result<(), Error> {
+        let csts = get_register_32(NvmeRegs32::CSTS, self.address, self.length)?;
+        if (csts >> 1) & 0b1 == 1 {
+            warn!("Reset controller with fatal status (CSTS.CFS)");
+        } else {
+            debug!("Reset controller");
+        }
+        for io_queue_pair in &all_io_queue_pairs {
+            self.io_queue_pair_ids.retain(|id| id != &io_queue_pair.id);
+        }
+        if !self.io_queue_pair_ids.is_empty() {
+            warn!(
+                "{} I/O queue pairs were not returned for the reset and are invalid",
+                self.io_queue_pair_ids.len()
+            );
+            self.io_queue_pair_ids.clear();
+        }
+        if let Some(controller_memory_buffer) = &mut self.controller_memory_buffer {
+            controller_memory_buffer.release_all();
+        }
 
-        // Wait for "shutdown" signal
-        loop {
-            let csts = get_register_32(NvmeRegs32::CSTS, self.address, self.length)?;
-            let shutdown_status = (csts >> 2) & 0b11; // SHST
-            let shutdown_type = csts >> 7; // ST
-            if shutdown_status == 0b10 && shutdown_type == 0 {
-                break;
-            } else {
-                spin_loop();
-            }
+        self.disable()?;
+        // only now the controller no longer accesses the memory of the queues
+        for io_queue_pair in all_io_queue_pairs {
+            self.release_io_queue_pair(io_queue_pair)?;
         }
-        debug!("Controller shutdown successful");
-        Ok(())
+        if let Some(admin_queue_pair) = self.admin.queue_pair_mut() {
+            let submission_queue_address = admin_queue_pair.submission.get_addr() as u64;
+            let completion_queue_address = admin_queue_pair.completion.get_addr() as u64;
+            let entries = admin_queue_pair.completion.len() as u32;
+            set_register_64(
+                NvmeRegs64::ASQ,
+                submission_queue_address,
+                self.address,
+                self.length,
+            )?;
+            set_register_64(
+                NvmeRegs64::ACQ,
+                completion_queue_address,
+                self.address,
+                self.length,
+            )?;
+            let aqa = (entries - 1) << 16 | (entries - 1);
+            set_register_32(NvmeRegs32::AQA, aqa, self.address, self.length)?;
+        }
+        self.enable()?;
+        self.identify()
     }
 
     fn submit_and_complete_admin<F: FnOnce(u16, usize) -> NvmeCommand>(
         &mut self,
         cmd_init: F,
     ) -> Result<CompletionQueueEntry, Error> {
-        self.admin_queue_pair.submit_and_complete(
-            cmd_init,
-            &self.buffer,
-            self.address,
-            self.doorbell_stride,
-        )
+        match &mut self.admin {
+            AdminTransport::Queue(admin_queue_pair) => admin_queue_pair.submit_and_complete(
+                cmd_init,
+                &self.buffer,
+                self.address,
+                self.doorbell_stride,
+            ),
+            #[cfg(feature = "std")]
+            AdminTransport::CharDevice(char_device) => {
+                let command = cmd_init(0, self.buffer.physical_address() as usize);
+                let command_specific = char_device.admin(
+                    &command,
+                    self.buffer.virtual_address(),
+                    self.buffer.size(),
+                )?;
+                Ok(CompletionQueueEntry {
+                    command_specific,
+                    ..Default::default()
+                })
+            }
+        }
+    }
+}
+
+/// Threshold Temperature Select (TMPSEL) and Threshold Type Select (THSEL) in dword 11 of
+/// the Temperature Threshold feature, [`Error::TemperatureSensorInvalid`] if the `sensor`
+/// is neither the composite temperature (0) nor one of the 8 temperature sensors.
+fn temperature_threshold_select(sensor: u8, kind: ThresholdKind) -> Result<u32, Error> {
+    if sensor > 8 {
+        return Err(Error::TemperatureSensorInvalid(sensor));
+    }
+    Ok((kind as u32) << 20 | (sensor as u32) << 16)
+}
+
+/// Allocate the admin submission and completion queues with `maximum_queue_entries_supported`
+/// entries, at most the 4096 the admin queue attributes (AQA) can describe.
+/// The controller accesses the queues by their base address, so they must be physically
+/// contiguous. If the allocator cannot provide that for the full size, the queues are capped
+/// to one page each, which is contiguous with any allocator.
+fn allocate_admin_queues<A: Allocator>(
+    maximum_queue_entries_supported: u32,
+    page_size: usize,
+    allocator: &A,
+) -> Result<(SubmissionQueue, CompletionQueue), Error> {
+    let entries = (maximum_queue_entries_supported as usize).min(ADMIN_QUEUE_MAXIMUM_ENTRIES);
+    let entries_per_page = page_size / core::mem::size_of::<NvmeCommand>();
+    match allocate_contiguous_admin_queues(entries, page_size, allocator) {
+        Err(error) if entries > entries_per_page => {
+            warn!("Capping the admin queues to {entries_per_page} entries: {error}");
+            allocate_contiguous_admin_queues(entries_per_page, page_size, allocator)
+        }
+        result => result,
+    }
+}
+
+/// Allocate the admin queues with `entries` each, [`Error::QueueNotPhysicallyContiguous`]
+/// if either is not physically contiguous.
+fn allocate_contiguous_admin_queues<A: Allocator>(
+    entries: usize,
+    page_size: usize,
+    allocator: &A,
+) -> Result<(SubmissionQueue, CompletionQueue), Error> {
+    let submission_queue = SubmissionQueue::new(entries, page_size, 0, allocator)?;
+    match CompletionQueue::new(entries, page_size, 0, allocator) {
+        Ok(completion_queue) => Ok((submission_queue, completion_queue)),
+        Err(error) => {
+            submission_queue.deallocate(allocator)?;
+            Err(error)
+        }
+    }
+}
+
+/// Maps an "Invalid Field in Command" failure of a command for the feature with the
+/// `feature_id` to [`Error::FeatureNotSupported`] and a "Feature Not Changeable" failure
+/// to [`Error::FeatureNotChangeable`].
+fn feature_error(feature_id: FeatureIdentifier, error: Error) -> Error {
+    match error {
+        Error::IoCompletionQueueFailure(_, _, status)
+            if status.code == StatusCode::InvalidField =>
+        {
+            Error::FeatureNotSupported(feature_id)
+        }
+        Error::IoCompletionQueueFailure(_, _, status)
+            if status.code == StatusCode::FeatureNotChangeable =>
+        {
+            Error::FeatureNotChangeable(feature_id)
+        }
+        error => error,
+    }
+}
+
+/// Maps the command specific failures of Create I/O Completion Queue and
+/// Create I/O Submission Queue for the queue pair with `queue_id` to descriptive errors.
+fn queue_creation_error(
+    queue_id: IoQueuePairId,
+    number_of_queue_entries: u32,
+    interrupt_vector: Option<u16>,
+    error: Error,
+) -> Error {
+    match error {
+        Error::IoCompletionQueueFailure(_, _, status) => match status.code {
+            StatusCode::CompletionQueueInvalid => Error::CompletionQueueInvalid(queue_id),
+            StatusCode::InvalidQueueIdentifier => Error::InvalidQueueIdentifier(queue_id),
+            StatusCode::InvalidQueueSize => {
+                Error::InvalidQueueSize(queue_id, number_of_queue_entries)
+            }
+            StatusCode::InvalidInterruptVector => {
+                Error::InvalidInterruptVector(queue_id, interrupt_vector.unwrap_or(0))
+            }
+            _ => error,
+        },
+        error => error,
+    }
+}
+
+/// Set Enable (EN) to 0 to disable the controller and wait for the "not ready" signal.
+fn disable_controller(
+    address: *mut u8,
+    length: usize,
+    timeout_milliseconds: u32,
+) -> Result<(), Error> {
+    let cc = get_register_32(NvmeRegs32::CC, address, length)?;
+    set_register_32(NvmeRegs32::CC, cc & !0b1, address, length)?;
+    wait_for_ready(false, address, length, timeout_milliseconds)
+}
+
+/// Wait until CSTS.RDY equals `ready`, at most for `timeout_milliseconds` (CAP.TO).
+/// While waiting for the controller to become ready, a fatal status (CSTS.CFS) is an error.
+fn wait_for_ready(
+    ready: bool,
+    address: *mut u8,
+    length: usize,
+    timeout_milliseconds: u32,
+) -> Result<(), Error> {
+    let mut deadline = Deadline::after_milliseconds(timeout_milliseconds as u64);
+    loop {
+        let csts = get_register_32(NvmeRegs32::CSTS, address, length)?;
+        if (csts & 0b1 == 1) == ready {
+            return Ok(());
+        }
+        if ready && (csts >> 1) & 0b1 == 1 {
+            return Err(Error::ControllerFatalStatus);
+        }
+        if deadline.expired() {
+            return Err(Error::ControllerReadyTimeout(ready));
+        }
+        spin_loop();
     }
 }
 
 /// Gets the value of the register at `address` + `register`.
 /// Returns an error if `address` + `register` does not belong to mapped memory.
-fn get_register_32(register: NvmeRegs32, address: *mut u8, length: usize) -> Result<u32, Error> {
-    if register as usize > length - 4 {
+pub(crate) fn get_register_32(
+    register: NvmeRegs32,
+    address: *mut u8,
+    length: usize,
+) -> Result<u32, Error> {
+    if register as usize + 4 > length {
         return Err(Error::MemoryAccessOutOfBounds);
     }
     let value =
@@ -573,7 +3123,7 @@ fn get_register_32(register: NvmeRegs32, address: *mut u8, length: usize) -> Res
 /// Gets the value of the register at `address` + `register`.
 /// Returns an error if `address` + `register` does not belong to mapped memory.
 fn get_register_64(register: NvmeRegs64, address: *mut u8, length: usize) -> Result<u64, Error> {
-    if register as usize > length - 8 {
+    if register as usize + 8 > length {
         return Err(Error::MemoryAccessOutOfBounds);
     }
     let value =
@@ -589,7 +3139,7 @@ fn set_register_32(
     address: *mut u8,
     length: usize,
 ) -> Result<(), Error> {
-    if register as usize > length - 4 {
+    if register as usize + 4 > length {
         return Err(Error::MemoryAccessOutOfBounds);
     }
     unsafe {
@@ -606,7 +3156,7 @@ fn set_register_64(
     address: *mut u8,
     length: usize,
 ) -> Result<(), Error> {
-    if register as usize > length - 8 {
+    if register as usize + 8 > length {
         return Err(Error::MemoryAccessOutOfBounds);
     }
     unsafe {
@@ -630,7 +3180,6 @@ pub(crate) enum NvmeRegs32 {
     CMBSZ = 0x3C,    // Controller Memory Buffer Size
     BPINFO = 0x40,   // Boot Partition Info
     BPRSEL = 0x44,   // Boot Partition Read Select
-    BPMBL = 0x48,    // Bood Partition Memory Location
     CMBSTS = 0x58,   // Controller Memory Buffer Status
     PMRCAP = 0xE00,  // PMem Capabilities
     PMRCTL = 0xE04,  // PMem Region Control
@@ -645,6 +3194,192 @@ pub(crate) enum NvmeRegs64 {
     CAP = 0x0,      // Controller Capabilities
     ASQ = 0x28,     // Admin Submission Queue Base Address
     ACQ = 0x30,     // Admin Completion Queue Base Address
+    BPMBL = 0x48,   // Boot Partition Memory Buffer Location
     CMBMSC = 0x50,  // Controller Memory Buffer Space Control
     PMRMSC = 0xE14, // Persistent Memory Buffer Space Control
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: u64 = 512;
+
+    fn namespace(blocks: u64) -> Namespace {
+        Namespace {
+            id: NamespaceId(1),
+            blocks,
+            block_size: BLOCK_SIZE,
+            namespace_globally_unique_identifier: [0; 16],
+            ieee_extended_unique_identifier: 0,
+            deallocate_in_write_zeroes_supported: false,
+            deallocated_read_value: None,
+            deallocated_or_unwritten_block_error_supported: false,
+            extended_lba: false,
+            metadata_size: 0,
+            preferred_write_granularity: 0,
+            preferred_deallocate_granularity: 0,
+            preferred_deallocate_alignment: 0,
+            lba_format_index: 0,
+            ana_group_id: 0,
+            maximum_single_source_range_length: 0,
+            maximum_copy_length: 0,
+            maximum_source_range_count: 1,
+            lba_formats_list: [0; 64],
+            number_of_lba_formats: 1,
+        }
+    }
+
+    /// The bits of the CC register defined by `ControllerConfiguration`.
+    const CONTROLLER_CONFIGURATION_MASK: u32 = 0x01FF_FFF1;
+
+    #[test]
+    fn controller_configuration_round_trips() {
+        // enabled, NVM command set, 4 KiB pages, round robin, 64 / 16 byte queue entries
+        let cc = 0x0046_0001;
+        let configuration = ControllerConfiguration::from_u32(cc);
+        assert_eq!(
+            configuration,
+            ControllerConfiguration {
+                enable: true,
+                io_command_set_selected: 0,
+                memory_page_size: 0,
+                arbitration_mechanism_selected: 0,
+                shutdown_notification: 0,
+                io_submission_queue_entry_size: 6,
+                io_completion_queue_entry_size: 4,
+                controller_ready_independent_of_media_enable: false,
+            }
+        );
+        assert_eq!(configuration.to_u32(), cc);
+
+        for cc in [
+            0,
+            CONTROLLER_CONFIGURATION_MASK,
+            0xFFFF_FFFF,
+            0xA5A5_A5A5,
+            0x5A5A_5A5A,
+        ] {
+            assert_eq!(
+                ControllerConfiguration::from_u32(cc).to_u32(),
+                cc & CONTROLLER_CONFIGURATION_MASK
+            );
+        }
+    }
+
+    #[test]
+    fn controller_configuration_fields_stay_within_their_bits() {
+        let field = |configure: fn(&mut ControllerConfiguration)| {
+            let mut configuration = ControllerConfiguration::default();
+            configure(&mut configuration);
+            configuration.to_u32()
+        };
+        assert_eq!(field(|cc| cc.io_command_set_selected = 0xFF), 0b111 << 4);
+        assert_eq!(field(|cc| cc.memory_page_size = 0xFF), 0b1111 << 7);
+        assert_eq!(
+            field(|cc| cc.arbitration_mechanism_selected = 0xFF),
+            0b111 << 11
+        );
+        assert_eq!(field(|cc| cc.shutdown_notification = 0xFF), 0b11 << 14);
+        assert_eq!(
+            field(|cc| cc.io_submission_queue_entry_size = 0xFF),
+            0b1111 << 16
+        );
+        assert_eq!(
+            field(|cc| cc.io_completion_queue_entry_size = 0xFF),
+            0b1111 << 20
+        );
+        assert_eq!(
+            field(|cc| cc.controller_ready_independent_of_media_enable = true),
+            1 << 24
+        );
+        assert_eq!(field(|cc| cc.enable = true), 1);
+    }
+
+    #[test]
+    fn validate_transfer_allows_65536_blocks_per_command() {
+        let namespace = namespace(1 << 32);
+        let length = 65536 * BLOCK_SIZE as usize;
+        assert_eq!(namespace.validate_transfer(length, 0, None).unwrap(), 65536);
+        assert!(matches!(
+            namespace.validate_transfer(length + BLOCK_SIZE as usize, 0, None),
+            Err(Error::NumberOfBlocksMoreThanMaximum(65537, 65536))
+        ));
+    }
+
+    #[test]
+    fn validate_transfer_reports_the_real_number_of_blocks() {
+        let namespace = namespace(u64::MAX);
+        let blocks = (u32::MAX as u64 + 2) as usize;
+        assert!(matches!(
+            namespace.validate_transfer(blocks * BLOCK_SIZE as usize, 0, None),
+            Err(Error::NumberOfBlocksMoreThanMaximum(reported, 65536)) if reported == blocks as u64
+        ));
+    }
+
+    #[test]
+    fn validate_transfer_enforces_the_maximum_transfer_size() {
+        let namespace = namespace(1 << 20);
+        let maximum_transfer_size = Some(128 * 1024);
+        assert_eq!(
+            namespace
+                .validate_transfer(128 * 1024, 0, maximum_transfer_size)
+                .unwrap(),
+            256
+        );
+        assert!(matches!(
+            namespace.validate_transfer(128 * 1024 + 512, 0, maximum_transfer_size),
+            Err(Error::BufferLengthBiggerThanMaximumTransferSize(
+                131584, 131072
+            ))
+        ));
+    }
+
+    #[test]
+    fn validate_transfer_rejects_empty_unaligned_and_out_of_bounds_transfers() {
+        let namespace = namespace(16);
+        assert!(matches!(
+            namespace.validate_transfer(0, 0, None),
+            Err(Error::NumberOfBlocksIsZero)
+        ));
+        assert!(matches!(
+            namespace.validate_transfer(100, 0, None),
+            Err(Error::BufferLengthNotAMultipleOfNamespaceBlockSize(
+                100, 512
+            ))
+        ));
+        assert!(matches!(
+            namespace.validate_transfer(2 * 512, 15, None),
+            Err(Error::LbaRangeOutOfBounds(_, 16))
+        ));
+    }
+
+    #[test]
+    fn align_deallocate_range_shrinks_to_whole_granules() {
+        let mut namespace = namespace(1 << 20);
+        namespace.preferred_deallocate_alignment = 8;
+        namespace.preferred_deallocate_granularity = 16;
+        assert_eq!(
+            namespace.align_deallocate_range(&LbaRange::new(3, 40)),
+            Some(LbaRange::new(8, 32))
+        );
+        assert_eq!(
+            namespace.align_deallocate_range(&LbaRange::new(3, 20)),
+            None
+        );
+    }
+
+    #[test]
+    fn align_deallocate_range_rejects_ranges_past_the_last_address() {
+        let mut namespace = namespace(u64::MAX);
+        namespace.preferred_deallocate_alignment = 8;
+        assert_eq!(
+            namespace.align_deallocate_range(&LbaRange::new(u64::MAX - 1, 4)),
+            None
+        );
+        assert_eq!(
+            namespace.align_deallocate_range(&LbaRange::new(u64::MAX - 3, 1)),
+            None
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::dma::Allocator;
+use crate::huge_pages::{self, HugePageAllocator};
+use std::boxed::Box;
+use std::error::Error;
+use std::ptr;
+
+/// Allocates locked memory in pages of the system page size (usually 4 KiB),
+/// for systems without huge pages.
+/// Pages are only physically contiguous by chance. Data buffers are described page by page,
+/// but queues spanning several pages need to be physically contiguous and fail to be created
+/// if they are not. Keep queues within a single page (64 submission queue entries)
+/// when using this allocator.
+pub struct PageAllocator;
+
+impl Allocator for PageAllocator {
+    fn allocate<T>(&self, layout: core::alloc::Layout) -> Result<*mut [T], Box<dyn Error>> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size = layout.size().next_multiple_of(page_size);
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err("failed to mmap pages".into());
+        }
+        if unsafe { libc::mlock(ptr, size) } != 0 {
+            unsafe { libc::munmap(ptr, size) };
+            return Err("failed to memory lock pages".into());
+        }
+        let slice = core::ptr::slice_from_raw_parts_mut(ptr, size);
+        Ok(slice as *mut [T])
+    }
+
+    fn deallocate<T>(&self, slice: *mut [T]) -> Result<(), Box<dyn Error>> {
+        let size = slice.len() * core::mem::size_of::<T>();
+        if unsafe { libc::munmap(slice as *mut libc::c_void, size) } != 0 {
+            return Err("failed to munmap pages".into());
+        }
+        Ok(())
+    }
+
+    fn translate_virtual_to_physical<T>(
+        &self,
+        virtual_address: *const T,
+    ) -> Result<*const T, Box<dyn Error>> {
+        huge_pages::translate_virtual_to_physical(virtual_address)
+    }
+}
+
+/// Allocates unlocked memory without translating it to physical addresses,
+/// so it needs neither huge pages nor root privileges for the page map.
+/// Only usable where the controller is not given physical addresses,
+/// with the character device transport, where the kernel maps every transfer.
+/// The "physical address" of its memory is the virtual address.
+/// It cannot be constructed outside the crate, so the only device using it is the one opened by
+/// [`crate::NvmeDevice::from_char_device_with_std_allocator`].
+pub struct StdAllocator {
+    _private: (),
+}
+
+impl StdAllocator {
+    pub(crate) fn new() -> Self {
+        Self { _private: () }
+    }
+}
+
+impl Allocator for StdAllocator {
+    fn allocate<T>(&self, layout: core::alloc::Layout) -> Result<*mut [T], Box<dyn Error>> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size = layout.size().next_multiple_of(page_size);
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err("failed to mmap pages".into());
+        }
+        let slice = core::ptr::slice_from_raw_parts_mut(ptr, size);
+        Ok(slice as *mut [T])
+    }
+
+    fn deallocate<T>(&self, slice: *mut [T]) -> Result<(), Box<dyn Error>> {
+        PageAllocator.deallocate(slice)
+    }
+
+    fn translate_virtual_to_physical<T>(
+        &self,
+        virtual_address: *const T,
+    ) -> Result<*const T, Box<dyn Error>> {
+        Ok(virtual_address)
+    }
+}
+
+/// The allocator chosen by [`crate::open`]:
+/// huge pages if they are available, pages of the system page size otherwise.
+pub enum DefaultAllocator {
+    HugePages(HugePageAllocator),
+    Pages(PageAllocator),
+}
+
+impl DefaultAllocator {
+    /// Uses huge pages if one can be mapped.
+    pub fn new() -> Self {
+        if huge_pages::huge_pages_available() {
+            DefaultAllocator::HugePages(HugePageAllocator)
+        } else {
+            DefaultAllocator::Pages(PageAllocator)
+        }
+    }
+}
+
+impl Default for DefaultAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Allocator for DefaultAllocator {
+    fn allocate<T>(&self, layout: core::alloc::Layout) -> Result<*mut [T], Box<dyn Error>> {
+        match self {
+            DefaultAllocator::HugePages(allocator) => allocator.allocate(layout),
+            DefaultAllocator::Pages(allocator) => allocator.allocate(layout),
+        }
+    }
+
+    fn deallocate<T>(&self, slice: *mut [T]) -> Result<(), Box<dyn Error>> {
+        match self {
+            DefaultAllocator::HugePages(allocator) => allocator.deallocate(slice),
+            DefaultAllocator::Pages(allocator) => allocator.deallocate(slice),
+        }
+    }
+
+    fn translate_virtual_to_physical<T>(
+        &self,
+        virtual_address: *const T,
+    ) -> Result<*const T, Box<dyn Error>> {
+        match self {
+            DefaultAllocator::HugePages(allocator) => {
+                allocator.translate_virtual_to_physical(virtual_address)
+            }
+            DefaultAllocator::Pages(allocator) => {
+                allocator.translate_virtual_to_physical(virtual_address)
+            }
+        }
+    }
+}
@@ -6,6 +6,7 @@ use std::os::unix::prelude::AsRawFd;
 use std::format;
 use std::string::String;
 use std::boxed::Box;
+use std::vec::Vec;
 
 use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
 
@@ -15,6 +16,40 @@ pub(crate) const COMMAND_REGISTER_OFFSET: u64 = 4;
 pub(crate) const BUS_MASTER_ENABLE_BIT: u64 = 2;
 // bit 10: "interrupt disable"
 pub(crate) const INTERRUPT_DISABLE: u64 = 10;
+// base address register 0 in the PCIe config space
+const BAR_0_OFFSET: u64 = 0x10;
+
+// 0x01 -> mass storage device class id
+// 0x08 -> nvme subclass
+pub(crate) const NVME_CLASS_ID: u64 = 0x0108;
+
+/// Returns the PCI addresses of all devices with the `class_id` (class and subclass code),
+/// sorted in ascending order.
+pub(crate) fn list_devices(class_id: u64) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut pci_addresses = Vec::new();
+    for entry in fs::read_dir("/sys/bus/pci/devices")? {
+        let pci_address = entry?.file_name().to_string_lossy().into_owned();
+        let mut class_file = open_resource_readonly(&pci_address, "class")?;
+        // the class file also contains the programming interface in the lowest byte
+        if read_hex(&mut class_file)? >> 8 == class_id {
+            pci_addresses.push(pci_address);
+        }
+    }
+    pci_addresses.sort();
+    Ok(pci_addresses)
+}
+
+/// Returns the name of the driver bound to the device at `pci_address`, if any.
+pub(crate) fn bound_driver(pci_address: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let path = format!("/sys/bus/pci/devices/{pci_address}/driver");
+    match fs::read_link(path) {
+        Ok(driver) => Ok(driver
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
 
 /// Unbinds the driver from the device at `pci_address`.
 pub(crate) fn unbind_driver(pci_address: &str) -> Result<(), Box<dyn Error>> {
@@ -30,6 +65,14 @@ pub(crate) fn unbind_driver(pci_address: &str) -> Result<(), Box<dyn Error>> {
     }
 }
 
+/// Binds the `driver` to the device at `pci_address`.
+pub(crate) fn bind_driver(pci_address: &str, driver: &str) -> Result<(), Box<dyn Error>> {
+    let path = format!("/sys/bus/pci/drivers/{driver}/bind");
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    write!(file, "{pci_address}")?;
+    Ok(())
+}
+
 /// Enables direct memory access for the device at `pci_address`.
 pub(crate) fn enable_dma(pci_address: &str) -> Result<(), Box<dyn Error>> {
     let path = format!("/sys/bus/pci/devices/{pci_address}/config");
@@ -54,8 +97,9 @@ pub(crate) fn disable_interrupts(pci_address: &str) -> Result<(), Box<dyn Error>
     Ok(())
 }
 
-/// Mmaps a pci resource and returns a pointer to the mapped memory.
-pub(crate) fn mmap_resource(pci_address: &str) -> Result<(*mut u8, usize), Box<dyn Error>> {
+/// Mmaps a pci resource and returns a pointer to the mapped memory and the resource file.
+/// Keep the file open as long as the mapping, which stays valid until [`unmap_resource`].
+pub(crate) fn mmap_resource(pci_address: &str) -> Result<MappedBar, Box<dyn Error>> {
     let path = format!("/sys/bus/pci/devices/{pci_address}/resource0");
 
     unbind_driver(pci_address)?;
@@ -64,6 +108,9 @@ pub(crate) fn mmap_resource(pci_address: &str) -> Result<(*mut u8, usize), Box<d
 
     let file = fs::OpenOptions::new().read(true).write(true).open(&path)?;
     let len = fs::metadata(&path)?.len() as usize;
+    if len == 0 {
+        return Err(format!("pci mapping failed: {path} is empty").into());
+    }
 
     let ptr = unsafe {
         libc::mmap(
@@ -73,13 +120,73 @@ pub(crate) fn mmap_resource(pci_address: &str) -> Result<(*mut u8, usize), Box<d
             libc::MAP_SHARED,
             file.as_raw_fd(),
             0,
-        ) as *mut u8
+        )
     };
 
-    if ptr.is_null() || len == 0 {
-        Err("pci mapping failed".into())
+    if ptr == libc::MAP_FAILED {
+        let error = io::Error::last_os_error();
+        Err(format!("pci mapping of {path} failed: {error}").into())
     } else {
-        Ok((ptr, len))
+        Ok(MappedBar::new(ptr as *mut u8, len, file))
+    }
+}
+
+/// Unmaps a pci resource mapped by [`mmap_resource`].
+pub(crate) fn unmap_resource(address: *mut u8, len: usize) -> Result<(), Box<dyn Error>> {
+    if unsafe { libc::munmap(address as *mut libc::c_void, len) } != 0 {
+        let error = io::Error::last_os_error();
+        return Err(format!("pci unmapping failed: {error}").into());
+    }
+    Ok(())
+}
+
+/// A BAR mapped into the address space, unmapped when dropped.
+/// The file it is mapped from stays open as long as the mapping.
+#[derive(Debug)]
+pub(crate) struct MappedBar {
+    address: *mut u8,
+    /// 0 once unmapped.
+    length: usize,
+    file: File,
+}
+
+impl MappedBar {
+    /// Takes ownership of the mapping of `length` bytes at `address` of the `file`.
+    pub(crate) fn new(address: *mut u8, length: usize, file: File) -> Self {
+        Self {
+            address,
+            length,
+            file,
+        }
+    }
+
+    pub(crate) fn address(&self) -> *mut u8 {
+        self.address
+    }
+
+    pub(crate) fn length(&self) -> usize {
+        self.length
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Unmaps the BAR; unlike dropping it, a failure is returned.
+    pub(crate) fn unmap(mut self) -> Result<(), Box<dyn Error>> {
+        let length = core::mem::take(&mut self.length);
+        unmap_resource(self.address, length)
+    }
+}
+
+impl Drop for MappedBar {
+    fn drop(&mut self) {
+        if self.length == 0 {
+            return;
+        }
+        if let Err(error) = unmap_resource(self.address, self.length) {
+            log::warn!("Unmapping the BAR failed: {error}");
+        }
     }
 }
 
@@ -115,6 +222,18 @@ pub(crate) fn read_io32(file: &mut File, offset: u64) -> Result<u32, io::Error>
     file.read_u32::<NativeEndian>()
 }
 
+/// Reads the address BAR 0 is mapped at on the PCI bus from the configuration space `file`.
+pub(crate) fn bar_0_address(config_file: &mut File) -> Result<u64, io::Error> {
+    let lower = read_io32(config_file, BAR_0_OFFSET)?;
+    // bits 2:1 "type", 0b10 for a 64 bit BAR continued in the next register
+    let upper = if (lower >> 1) & 0b11 == 0b10 {
+        read_io32(config_file, BAR_0_OFFSET + 4)?
+    } else {
+        0
+    };
+    Ok(((upper as u64) << 32) | (lower & !0xF) as u64)
+}
+
 #[allow(dead_code)]
 /// Writes an u64 at `offset` in `file`.
 pub(crate) fn read_io64(file: &mut File, offset: u64) -> Result<u64, io::Error> {
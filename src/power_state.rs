@@ -0,0 +1,65 @@
+/// Offset of the first power state descriptor in the Identify Controller data structure.
+pub(crate) const POWER_STATE_DESCRIPTORS_OFFSET: usize = 2048;
+
+/// Size of a power state descriptor in bytes.
+pub(crate) const POWER_STATE_DESCRIPTOR_SIZE: usize = 32;
+
+/// NVMe base specification 5.17.2.1 Power State Descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerStateDescriptor {
+    /// Maximum Power (MP) in microwatts, converted with the Max Power Scale (MXPS).
+    pub max_power_microwatts: u32,
+    /// Non-Operational State (NOPS): no I/O commands are processed in this state.
+    pub non_operational: bool,
+    /// Entry Latency (ENLAT) in microseconds; 0 if not reported.
+    pub entry_latency_microseconds: u32,
+    /// Exit Latency (EXLAT) in microseconds; 0 if not reported.
+    pub exit_latency_microseconds: u32,
+    pub relative_read_throughput: u8,
+    pub relative_read_latency: u8,
+    pub relative_write_throughput: u8,
+    pub relative_write_latency: u8,
+    /// Idle Power (IDLP) in microwatts, converted with the Idle Power Scale (IPS);
+    /// `None` if not reported.
+    pub idle_power_microwatts: Option<u32>,
+    /// Active Power (ACTP) in microwatts, converted with the Active Power Scale (APS);
+    /// `None` if not reported.
+    pub active_power_microwatts: Option<u32>,
+}
+
+impl PowerStateDescriptor {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let read_u16 = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        // MXPS: 0.01 W or 0.0001 W units
+        let max_power_scale = if bytes[3] & 0b1 == 1 { 100 } else { 10_000 };
+        Self {
+            max_power_microwatts: read_u16(0) as u32 * max_power_scale,
+            non_operational: (bytes[3] >> 1) & 0b1 == 1,
+            entry_latency_microseconds: read_u32(4),
+            exit_latency_microseconds: read_u32(8),
+            relative_read_throughput: bytes[12] & 0b1_1111,
+            relative_read_latency: bytes[13] & 0b1_1111,
+            relative_write_throughput: bytes[14] & 0b1_1111,
+            relative_write_latency: bytes[15] & 0b1_1111,
+            idle_power_microwatts: scaled_power(read_u16(16), bytes[18] >> 6),
+            active_power_microwatts: scaled_power(read_u16(20), bytes[22] >> 6),
+        }
+    }
+}
+
+/// Converts a power with a 2 bit scale (IPS, APS) to microwatts, `None` if not reported.
+fn scaled_power(power: u16, scale: u8) -> Option<u32> {
+    match scale & 0b11 {
+        0b01 => Some(power as u32 * 100),
+        0b10 => Some(power as u32 * 10_000),
+        _ => None,
+    }
+}
@@ -15,7 +15,7 @@ use alloc::vec::Vec;
 #[derive(Debug)]
 pub(crate) enum PrpContainer {
     One(usize),                     // Address of PRP1
-    Two(usize, usize),           // Address of PRP1 and PRP2
+    Two(usize, usize),              // Address of PRP1 and PRP2
     Multiple(usize, Vec<Dma<u64>>), // Address of PRP1 and PRP list
 }
 
@@ -37,74 +37,320 @@ impl PrpContainer {
     }
 }
 
+/// PRPs built once for a registered buffer, which can be reused for every transfer
+/// of that buffer instead of building them on each I/O.
+/// Must be released with [`crate::IoQueuePair::release_prp_list`].
+#[derive(Debug)]
+pub struct PrpList {
+    pub(crate) container: PrpContainer,
+    buffer_physical_address: usize,
+    buffer_size: usize,
+}
+
+impl PrpList {
+    pub(crate) fn new<A: Allocator, T>(
+        buffer: &Dma<T>,
+        page_size: usize,
+        allocator: &A,
+        pool: &mut PrpListPool,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            container: allocate(buffer, page_size, allocator, pool)?,
+            buffer_physical_address: buffer.physical_address() as usize,
+            buffer_size: buffer.size(),
+        })
+    }
+
+    /// Whether the PRPs were built for the `buffer`.
+    pub fn describes<T>(&self, buffer: &Dma<T>) -> bool {
+        self.buffer_physical_address == buffer.physical_address() as usize
+            && self.buffer_size == buffer.size()
+    }
+}
+
+/// Free PRP list pages of a queue pair, which are reused by later transfers
+/// instead of allocating and deallocating DMA memory for every transfer.
+#[derive(Debug)]
+pub(crate) struct PrpListPool {
+    free: Vec<Dma<u64>>,
+    capacity: usize,
+}
+
+impl PrpListPool {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            free: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Take a PRP list page from the pool or allocate one if the pool is empty.
+    fn take<A: Allocator>(&mut self, page_size: usize, allocator: &A) -> Result<Dma<u64>, Error> {
+        match self.free.pop() {
+            Some(prp_list) => Ok(prp_list),
+            None => Dma::allocate(
+                page_size / core::mem::size_of::<u64>(),
+                page_size,
+                allocator,
+            ),
+        }
+    }
+
+    /// Return a PRP list page to the pool or deallocate it if the pool is full.
+    fn give<A: Allocator>(&mut self, prp_list: Dma<u64>, allocator: &A) -> Result<(), Error> {
+        if self.free.len() < self.capacity {
+            self.free.push(prp_list);
+            Ok(())
+        } else {
+            prp_list.deallocate(allocator)
+        }
+    }
+
+    /// Deallocate all PRP list pages in the pool.
+    pub(crate) fn clear<A: Allocator>(&mut self, allocator: &A) -> Result<(), Error> {
+        for prp_list in self.free.drain(..) {
+            prp_list.deallocate(allocator)?;
+        }
+        Ok(())
+    }
+}
+
+/// The number of PRP list pages needed to describe the pages after the first one.
+/// All lists except the last one use their last entry as a pointer to the next list.
+pub(crate) fn number_of_prp_lists(needed_number_of_pages: usize, page_size: usize) -> usize {
+    // PRP1 and PRP2 suffice for up to 2 pages
+    if needed_number_of_pages <= 2 {
+        return 0;
+    }
+    let prp_entries_per_page = page_size / core::mem::size_of::<u64>();
+    // subtracting 1 from the needed number of pages, because PRP1 points to the first needed page
+    // the last list can hold one entry more, because it does not point to another list
+    (needed_number_of_pages - 2).div_ceil(prp_entries_per_page - 1)
+}
+
 pub(crate) fn allocate<A: Allocator, T>(
     buffer: &Dma<T>,
     page_size: usize,
     allocator: &A,
+    pool: &mut PrpListPool,
 ) -> Result<PrpContainer, Error> {
-    if (buffer.virtual_address() as usize & 0b0111) != 0 {
-        return Err(Error::VirtualAddressIsNotDwordAligned(
-            buffer.virtual_address() as usize,
-        ));
-    }
-    let prp_1 = buffer.physical_address() as *mut u64;
-    let needed_number_of_pages =
-        ((buffer.virtual_address() as usize & (page_size - 1)) + buffer.size()).div_ceil(page_size);
-    if needed_number_of_pages == 1 {
-        return Ok(PrpContainer::One(prp_1 as usize));
-    }
-    if (buffer.virtual_address() as usize & (page_size - 1)) != 0 {
-        return Err(Error::VirtualAddressIsNotPageAligned(
-            buffer.virtual_address() as usize,
-        ));
+    let virtual_address = buffer.virtual_address() as usize;
+    if (virtual_address & 0b0111) != 0 {
+        return Err(Error::VirtualAddressIsNotDwordAligned(virtual_address));
     }
-    // add one page size to the virtual address of PRP1 to get the virtual address of PRP2
-    let prp_2 = allocator
-        .translate_virtual_to_physical(unsafe { buffer.virtual_address().add(page_size) })
-        .map_err(Error::TranslateVirtualToPhysical)? as *mut u64;
-    if needed_number_of_pages == 2 {
-        return Ok(PrpContainer::Two(prp_1 as usize, prp_2 as usize));
+    // PRP1 may point into the middle of a page, all further entries point to the start of a page.
+    let offset_in_first_page = virtual_address & (page_size - 1);
+    let first_page = virtual_address - offset_in_first_page;
+    let prp_1 = buffer.physical_address() as usize;
+    let needed_number_of_pages = (offset_in_first_page + buffer.size()).div_ceil(page_size);
+    if needed_number_of_pages == 1 {
+        return Ok(PrpContainer::One(prp_1));
     }
 
-    // FIXME: multiple PRPs do not seem to work
-    if needed_number_of_pages > 2 {
-        return Err(Error::PrpMultipleNotSupported);
+    // Every page is translated on its own, as the buffer is not required to be physically contiguous.
+    let translate_page = |page_index: usize| -> Result<u64, Error> {
+        let page = (first_page + page_index * page_size) as *const u8;
+        allocator
+            .translate_virtual_to_physical(page)
+            .map(|physical_address| physical_address as u64)
+            .map_err(Error::TranslateVirtualToPhysical)
+    };
+    if needed_number_of_pages == 2 {
+        return Ok(PrpContainer::Two(prp_1, translate_page(1)? as usize));
     }
 
-    let prp_entries_per_page = page_size / core::mem::size_of::<u64>();
-    // subtracting 1 from the needed number of pages, because PRP1 points to the first needed page
-    // subtracting 1 from the PRP entries per page, because one entry is needed as a pointer to the next PRP list
-    let needed_number_of_prp_lists =
-        (needed_number_of_pages - 1).div_ceil(prp_entries_per_page - 1);
-
+    let needed_number_of_prp_lists = number_of_prp_lists(needed_number_of_pages, page_size);
     let mut prp_lists: Vec<Dma<u64>> = Vec::with_capacity(needed_number_of_prp_lists);
+    let mut result = Ok(());
     for _ in 0..needed_number_of_prp_lists {
-        prp_lists.push(Dma::allocate(prp_entries_per_page, page_size, allocator)?);
+        match pool.take(page_size, allocator) {
+            Ok(prp_list) => prp_lists.push(prp_list),
+            Err(error) => {
+                result = Err(error);
+                break;
+            }
+        }
+    }
+    if result.is_ok() {
+        result = fill_prp_lists(
+            &mut prp_lists,
+            needed_number_of_pages,
+            page_size,
+            translate_page,
+        );
+    }
+    if let Err(error) = result {
+        // the allocation failure is what the caller needs to see, not a failure to free
+        let _ = give_all(prp_lists, allocator, pool);
+        return Err(error);
     }
 
-    for i in 0..needed_number_of_prp_lists {
-        // last entry is needed as a pointer to the next PRP list
-        for j in 0..prp_entries_per_page - 1 {
-            let offset = (i * (prp_entries_per_page - 1) + j) * page_size;
-            prp_lists[i][j] = unsafe { prp_2.add(offset) } as u64;
-        }
-        // last list should not point to another list
-        if i < needed_number_of_prp_lists - 1 {
-            prp_lists[i][prp_entries_per_page - 1] = prp_lists[i + 1].physical_address() as u64;
+    Ok(PrpContainer::Multiple(prp_1, prp_lists))
+}
+
+fn fill_prp_lists<F: Fn(usize) -> Result<u64, Error>>(
+    prp_lists: &mut [Dma<u64>],
+    needed_number_of_pages: usize,
+    page_size: usize,
+    translate_page: F,
+) -> Result<(), Error> {
+    let prp_entries_per_page = page_size / core::mem::size_of::<u64>();
+    // page 0 is covered by PRP1
+    let mut page_index = 1;
+    for i in 0..prp_lists.len() {
+        let next_prp_list = prp_lists
+            .get(i + 1)
+            .map(|prp_list| prp_list.physical_address());
+        let prp_list = &mut prp_lists[i];
+        match next_prp_list {
+            Some(next_prp_list) => {
+                // last entry is needed as a pointer to the next PRP list
+                for j in 0..prp_entries_per_page - 1 {
+                    prp_list[j] = translate_page(page_index)?;
+                    page_index += 1;
+                }
+                prp_list[prp_entries_per_page - 1] = next_prp_list as u64;
+            }
+            None => {
+                // last list should not point to another list
+                for j in 0..needed_number_of_pages - page_index {
+                    prp_list[j] = translate_page(page_index + j)?;
+                }
+            }
         }
     }
-
-    Ok(PrpContainer::Multiple(prp_1 as usize, prp_lists))
+    Ok(())
 }
 
 pub(crate) fn deallocate<A: Allocator>(
     prp_container: PrpContainer,
     allocator: &A,
+    pool: &mut PrpListPool,
 ) -> Result<(), Error> {
     if let PrpContainer::Multiple(_, prp_lists) = prp_container {
-        for prp_list in prp_lists {
-            prp_list.deallocate(allocator)?;
-        }
+        give_all(prp_lists, allocator, pool)?;
     }
     Ok(())
 }
+
+/// Return every PRP list page to the pool, even if deallocating one of them fails.
+/// The first failure is returned.
+fn give_all<A: Allocator>(
+    prp_lists: Vec<Dma<u64>>,
+    allocator: &A,
+    pool: &mut PrpListPool,
+) -> Result<(), Error> {
+    let mut result = Ok(());
+    for prp_list in prp_lists {
+        let given = pool.give(prp_list, allocator);
+        if result.is_ok() {
+            result = given;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dma::tests::TestAllocator;
+
+    const PAGE_SIZE: usize = 4096;
+
+    /// The physical address of the page `page_index` pages after the start of the `buffer`.
+    fn physical_page(buffer: &Dma<u8>, page_index: usize) -> u64 {
+        (buffer.physical_address() as usize + page_index * PAGE_SIZE) as u64
+    }
+
+    #[test]
+    fn prp_list_of_a_1_mib_buffer() {
+        let allocator = TestAllocator::new();
+        let mut pool = PrpListPool::new(4);
+        let buffer: Dma<u8> = Dma::allocate(1024 * 1024, PAGE_SIZE, &allocator).unwrap();
+
+        let prp_list = PrpList::new(&buffer, PAGE_SIZE, &allocator, &mut pool).unwrap();
+        assert!(prp_list.describes(&buffer));
+        let PrpContainer::Multiple(prp_1, prp_lists) = &prp_list.container else {
+            panic!("256 pages need a PRP list");
+        };
+        assert_eq!(*prp_1, buffer.physical_address() as usize);
+        // PRP1 covers the first page, a single list the other 255
+        assert_eq!(prp_lists.len(), 1);
+        assert_eq!(
+            prp_list.container.prp_2(),
+            Some(prp_lists[0].physical_address())
+        );
+        for page_index in 1..256 {
+            assert_eq!(
+                prp_lists[0][page_index - 1],
+                physical_page(&buffer, page_index)
+            );
+        }
+        assert!((255..512).all(|index| prp_lists[0][index] == 0));
+
+        deallocate(prp_list.container, &allocator, &mut pool).unwrap();
+        pool.clear(&allocator).unwrap();
+        buffer.deallocate(&allocator).unwrap();
+        assert_eq!(allocator.live_allocations.get(), 0);
+    }
+
+    #[test]
+    fn prp_lists_are_chained_when_one_page_of_entries_is_not_enough() {
+        let allocator = TestAllocator::new();
+        let mut pool = PrpListPool::new(0);
+        // 1 + 511 + 100 pages: PRP1, a full list pointing to a second list
+        let buffer: Dma<u8> = Dma::allocate(612 * PAGE_SIZE, PAGE_SIZE, &allocator).unwrap();
+
+        let prp_container = allocate(&buffer, PAGE_SIZE, &allocator, &mut pool).unwrap();
+        let PrpContainer::Multiple(_, prp_lists) = &prp_container else {
+            panic!("612 pages need PRP lists");
+        };
+        assert_eq!(prp_lists.len(), 2);
+        assert_eq!(prp_lists[0][0], physical_page(&buffer, 1));
+        assert_eq!(prp_lists[0][510], physical_page(&buffer, 511));
+        assert_eq!(prp_lists[0][511], prp_lists[1].physical_address() as u64);
+        assert_eq!(prp_lists[1][0], physical_page(&buffer, 512));
+        assert_eq!(prp_lists[1][99], physical_page(&buffer, 611));
+
+        deallocate(prp_container, &allocator, &mut pool).unwrap();
+        buffer.deallocate(&allocator).unwrap();
+        assert_eq!(allocator.live_allocations.get(), 0);
+    }
+
+    #[test]
+    fn failed_prp_list_allocation_is_returned_without_leaking() {
+        let allocator = TestAllocator::new();
+        // 1000 pages need 2 PRP lists, allocating the second one fails
+        let buffer: Dma<u8> = Dma::allocate(1000 * PAGE_SIZE, PAGE_SIZE, &allocator).unwrap();
+        let mut pool = PrpListPool::new(0);
+        allocator.allocations_left.set(Some(1));
+
+        let result = allocate(&buffer, PAGE_SIZE, &allocator, &mut pool);
+        assert!(matches!(result, Err(Error::Allocate(_))));
+        // only the buffer is still allocated
+        assert_eq!(allocator.live_allocations.get(), 1);
+
+        buffer.deallocate(&allocator).unwrap();
+    }
+
+    #[test]
+    fn failed_prp_list_allocation_returns_the_lists_to_the_pool() {
+        let allocator = TestAllocator::new();
+        let buffer: Dma<u8> = Dma::allocate(1000 * PAGE_SIZE, PAGE_SIZE, &allocator).unwrap();
+        let mut pool = PrpListPool::new(4);
+        allocator.allocations_left.set(Some(1));
+
+        let result = allocate(&buffer, PAGE_SIZE, &allocator, &mut pool);
+        assert!(matches!(result, Err(Error::Allocate(_))));
+        assert_eq!(pool.free.len(), 1);
+
+        // the pooled list is reused once allocations succeed again
+        allocator.allocations_left.set(None);
+        let prp_container = allocate(&buffer, PAGE_SIZE, &allocator, &mut pool).unwrap();
+        assert_eq!(allocator.live_allocations.get(), 3);
+        deallocate(prp_container, &allocator, &mut pool).unwrap();
+        pool.clear(&allocator).unwrap();
+        buffer.deallocate(&allocator).unwrap();
+        assert_eq!(allocator.live_allocations.get(), 0);
+    }
+}
@@ -1,41 +1,241 @@
-use crate::cmd::NvmeCommand;
+#[cfg(feature = "std")]
+use crate::char_device::CharDevice;
+use crate::cmd::{IoOptions, NvmeCommand, ZoneSendAction};
 use crate::dma::{Allocator, Dma};
 use crate::error::Error;
-use crate::nvme::Namespace;
-use crate::prp;
+use crate::lba::LbaRange;
+use crate::nvme::{self, Namespace, NvmeRegs32, OptionalNvmCommandSupport};
+use crate::prp::{self, PrpList};
 use crate::queues::*;
+use crate::sgl::{self, Sgl, SglDescriptor, SglSupport};
+use crate::status::StatusCode;
+use crate::timeout::Deadline;
+use crate::zns::{self, ZoneDescriptor};
 use ahash::RandomState;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
 use hashbrown::HashMap;
 use log::debug;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::Read;
 
 #[derive(Debug)]
 pub(crate) struct AdminQueuePair {
     pub(crate) submission: SubmissionQueue,
     pub(crate) completion: CompletionQueue,
+    /// Number of submitted commands whose completion has not been reaped yet.
+    pub(crate) outstanding_commands: usize,
+    /// Reaped completions of commands not waited for yet.
+    pub(crate) completed: HashMap<u16, Result<CompletionQueueEntry, Error>, RandomState>,
+    /// Length of the mapped controller registers, to check the controller status.
+    device_length: usize,
+    /// Command IDs of the Asynchronous Event Requests whose event has not been taken yet.
+    /// They stay outstanding until the controller reports an event, possibly forever.
+    pub(crate) async_event_requests: Vec<u16>,
+    /// PRP list pages of admin commands transferring user buffers, e.g. vendor specific ones.
+    pub(crate) prp_list_pool: prp::PrpListPool,
 }
 
+/// Admin commands with user buffers are rare, so few PRP list pages are kept for reuse.
+const ADMIN_PRP_LIST_POOL_CAPACITY: usize = 2;
+
+/// Set in the command ID of Asynchronous Event Requests, which may be outstanding for longer
+/// than the submission queue takes to wrap around, so they cannot use the tail as their ID.
+const ASYNC_EVENT_REQUEST_COMMAND_ID: u16 = 0x8000;
+
 impl AdminQueuePair {
-    pub(crate) fn submit_and_complete<F: FnOnce(u16, usize) -> NvmeCommand>(
+    pub(crate) fn new(
+        submission: SubmissionQueue,
+        completion: CompletionQueue,
+        device_length: usize,
+    ) -> Self {
+        Self {
+            submission,
+            completion,
+            device_length,
+            outstanding_commands: 0,
+            completed: HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0)),
+            async_event_requests: Vec::new(),
+            prp_list_pool: prp::PrpListPool::new(ADMIN_PRP_LIST_POOL_CAPACITY),
+        }
+    }
+
+    /// Submits the command created by `cmd_init`, which receives the command ID and
+    /// the physical address of the `buffer`, and rings the submission queue tail doorbell.
+    /// Several commands may be in flight, each is waited for with [`Self::complete`].
+    pub(crate) fn submit<F: FnOnce(u16, usize) -> NvmeCommand>(
         &mut self,
         cmd_init: F,
         buffer: &Dma<u8>,
         address: *mut u8,
         doorbell_stride: u16,
-    ) -> Result<CompletionQueueEntry, Error> {
-        let cid = self.submission.tail;
+    ) -> Result<u16, Error> {
+        // see `IoQueuePair::check_completion_queue_overflow`
+        if self.outstanding_commands >= self.completion.len() - 1 {
+            return Err(Error::CompletionQueueOverflow(self.completion.len()));
+        }
+        let command_id = self.submission.tail as u16;
+        let tail = self
+            .submission
+            .submit(cmd_init(command_id, buffer.physical_address() as usize));
+        self.outstanding_commands += 1;
+        set_submission_queue_tail_doorbell(0, tail as u32, address, doorbell_stride);
+        Ok(command_id)
+    }
+
+    /// Submits an Asynchronous Event Request, which completes once the controller
+    /// reports an event. Its completion is taken with [`Self::take_async_events`].
+    pub(crate) fn submit_async_event_request(
+        &mut self,
+        address: *mut u8,
+        doorbell_stride: u16,
+    ) -> Result<u16, Error> {
+        if self.outstanding_commands >= self.completion.len() - 1 {
+            return Err(Error::CompletionQueueOverflow(self.completion.len()));
+        }
+        let command_id = (0..)
+            .map(|index| ASYNC_EVENT_REQUEST_COMMAND_ID | index)
+            .find(|command_id| !self.async_event_requests.contains(command_id))
+            .unwrap();
         let tail = self
             .submission
-            .submit(cmd_init(cid as u16, buffer.physical_address() as usize));
+            .submit(NvmeCommand::async_event_req(command_id));
+        self.outstanding_commands += 1;
+        self.async_event_requests.push(command_id);
         set_submission_queue_tail_doorbell(0, tail as u32, address, doorbell_stride);
+        Ok(command_id)
+    }
+
+    /// The number of Asynchronous Event Requests the controller has not completed yet.
+    pub(crate) fn pending_async_event_requests(&self) -> usize {
+        self.async_event_requests
+            .iter()
+            .filter(|&&command_id| !self.completed.contains_key(&command_id))
+            .count()
+    }
 
-        let (head, entry, _) = self.completion.complete_spin();
+    /// Reaps the available completions without spinning and takes those of
+    /// Asynchronous Event Requests, in the order they were submitted.
+    pub(crate) fn take_async_events(
+        &mut self,
+        address: *mut u8,
+        doorbell_stride: u16,
+    ) -> Vec<Result<CompletionQueueEntry, Error>> {
+        while let Some((command_id, result)) = self.complete_next(address, doorbell_stride) {
+            self.completed.insert(command_id, result);
+        }
+        let mut events = Vec::new();
+        let completed = &mut self.completed;
+        self.async_event_requests
+            .retain(|command_id| match completed.remove(command_id) {
+                Some(result) => {
+                    events.push(result);
+                    false
+                }
+                None => true,
+            });
+        events
+    }
+
+    /// The next completion if one is available, see [`Self::complete_next_spin`].
+    fn complete_next(
+        &mut self,
+        address: *mut u8,
+        doorbell_stride: u16,
+    ) -> Option<(u16, Result<CompletionQueueEntry, Error>)> {
+        let (head, entry, _) = self.completion.complete().ok()?;
         set_completion_queue_head_doorbell(0, head as u32, address, doorbell_stride);
+        self.outstanding_commands = self.outstanding_commands.saturating_sub(1);
+        self.submission.head = entry.sq_head as usize;
         let status = entry.status >> 1;
         if status != 0 {
-            return Err(Error::IoCompletionQueueFailure(status));
+            let error = Error::from_status(IoQueuePairId(0), entry.command_id, status);
+            return Some((entry.command_id, Err(error)));
+        }
+        Some((entry.command_id, Ok(entry)))
+    }
+
+    /// Spins until the next completion is available, in the order the controller completes
+    /// the commands, and returns its command ID and entry or error status.
+    /// While spinning, the controller status is checked periodically and
+    /// [`Error::ControllerFatalStatus`] is returned if the controller failed.
+    pub(crate) fn complete_next_spin(
+        &mut self,
+        address: *mut u8,
+        doorbell_stride: u16,
+    ) -> Result<(u16, Result<CompletionQueueEntry, Error>), Error> {
+        let mut spins: u32 = 0;
+        loop {
+            if let Some(completion) = self.complete_next(address, doorbell_stride) {
+                return Ok(completion);
+            }
+            spins = spins.wrapping_add(1);
+            if spins.is_multiple_of(CONTROLLER_STATUS_POLL_INTERVAL) {
+                let csts = nvme::get_register_32(NvmeRegs32::CSTS, address, self.device_length)?;
+                if (csts >> 1) & 0b1 == 1 {
+                    return Err(Error::ControllerFatalStatus);
+                }
+            }
+            spin_loop();
+        }
+    }
+
+    /// Spins until the command with `command_id` is completed.
+    /// Completions of other commands reaped meanwhile are kept until they are waited for.
+    pub(crate) fn complete(
+        &mut self,
+        command_id: u16,
+        address: *mut u8,
+        doorbell_stride: u16,
+    ) -> Result<CompletionQueueEntry, Error> {
+        if let Some(result) = self.completed.remove(&command_id) {
+            return result;
+        }
+        loop {
+            let (completed_command_id, result) =
+                self.complete_next_spin(address, doorbell_stride)?;
+            if completed_command_id == command_id {
+                return result;
+            }
+            self.completed.insert(completed_command_id, result);
         }
-        Ok(entry)
+    }
+
+    /// Spins until every outstanding command except pending Asynchronous Event Requests
+    /// is completed. The completions are kept until they are waited for.
+    /// Fails with [`Error::DrainTimeout`] once the `deadline` has passed.
+    pub(crate) fn drain(
+        &mut self,
+        address: *mut u8,
+        doorbell_stride: u16,
+        deadline: &mut Deadline,
+    ) -> Result<(), Error> {
+        while self.outstanding_commands > self.pending_async_event_requests() {
+            match self.complete_next(address, doorbell_stride) {
+                Some((command_id, result)) => {
+                    self.completed.insert(command_id, result);
+                }
+                None if deadline.expired() => return Err(Error::DrainTimeout(IoQueuePairId(0))),
+                None => spin_loop(),
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn submit_and_complete<F: FnOnce(u16, usize) -> NvmeCommand>(
+        &mut self,
+        cmd_init: F,
+        buffer: &Dma<u8>,
+        address: *mut u8,
+        doorbell_stride: u16,
+    ) -> Result<CompletionQueueEntry, Error> {
+        let command_id = self.submit(cmd_init, buffer, address, doorbell_stride)?;
+        self.complete(command_id, address, doorbell_stride)
     }
 }
 
@@ -43,25 +243,152 @@ impl AdminQueuePair {
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct IoQueuePairId(pub u16);
 
+/// A pair of I/O queues created by [`crate::NvmeDevice::create_io_queue_pair`].
+/// It writes to the doorbells of the device, so it must not outlive the device:
+/// return it with [`crate::NvmeDevice::delete_io_queue_pair`] or
+/// [`crate::NvmeDevice::shutdown`], which fails while pairs are outstanding.
+/// Dropping it instead leaves its queues on the controller.
 #[derive(Debug)]
 pub struct IoQueuePair<A: Allocator> {
     pub(crate) id: IoQueuePairId,
     pub(crate) submission: SubmissionQueue,
     pub(crate) completion: CompletionQueue,
     pub(crate) page_size: usize,
-    pub(crate) maximum_transfer_size: usize,
+    /// `None` if the controller has no limit.
+    pub(crate) maximum_transfer_size: Option<usize>,
     pub(crate) allocator: Arc<A>,
     pub(crate) namespace: Namespace,
     pub(crate) device_address: usize,
+    pub(crate) device_length: usize,
     pub(crate) doorbell_stride: u16,
     pub(crate) prp_containers: HashMap<u16, prp::PrpContainer, RandomState>,
+    pub(crate) prp_list_pool: prp::PrpListPool,
+    pub(crate) sgl_support: SglSupport,
+    /// SGL descriptor lists of commands in flight, see `submit_with_sgl`.
+    pub(crate) sgl_lists: HashMap<u16, Dma<SglDescriptor>, RandomState>,
+    /// Number of submitted commands whose completion has not been reaped yet.
+    pub(crate) outstanding_commands: usize,
+    /// Completions reaped while waiting for the completion of another command,
+    /// handed out first by the next reap, see `complete_command`.
+    pub(crate) stashed_completions: VecDeque<Completion>,
+    /// The command IDs of the submitted commands, see `next_command_id`.
+    pub(crate) command_ids: CommandIds,
+    pub(crate) optional_nvm_command_support: OptionalNvmCommandSupport,
+    pub(crate) wait_while_processing_paused: bool,
+    /// Set if the commands are passed through the character device of the kernel driver.
+    #[cfg(feature = "std")]
+    pub(crate) char_device: Option<Arc<CharDevice>>,
+    /// Command IDs and statuses of passed through commands, which are not completed yet.
+    #[cfg(feature = "std")]
+    pub(crate) passthrough_completions: VecDeque<(u16, Result<(), Error>)>,
+    /// The eventfd signaled by the MSI-X interrupt of the completion queue.
+    #[cfg(feature = "std")]
+    pub(crate) interrupt_event: Option<File>,
+    pub(crate) completion_callback: Option<CompletionCallback>,
+}
+
+/// The callback set with [`IoQueuePair::set_completion_callback`].
+pub(crate) struct CompletionCallback(Box<CompletionCallbackFn>);
+
+type CompletionCallbackFn = dyn FnMut(u16, &Result<(), Error>) + Send;
+
+impl core::fmt::Debug for CompletionCallback {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("CompletionCallback")
+    }
+}
+
+/// Maximum number of ranges of one Dataset Management command.
+const MAXIMUM_DATASET_RANGES: usize = 256;
+
+/// Size of a Dataset Management range descriptor in bytes.
+const DATASET_RANGE_SIZE: usize = 16;
+
+/// Size in bytes of the writes of [`IoQueuePair::write_pattern`],
+/// if the maximum transfer size allows it.
+const PATTERN_WRITE_SIZE: usize = 128 * 1024;
+
+/// Size of a Copy source range entry (descriptor format 0) in bytes.
+const COPY_SOURCE_RANGE_SIZE: usize = 32;
+
+/// The command ID and status of a completed I/O command.
+type Completion = (u16, Result<(), Error>);
+
+/// The command IDs of a queue pair in use, one bit per ID.
+/// An ID is reserved from the submission of its command until its completion is handed out,
+/// so a command that timed out keeps its ID until the controller completes it.
+#[derive(Debug)]
+pub(crate) struct CommandIds {
+    outstanding: Vec<u64>,
+    number_of_ids: usize,
+    /// The ID tried first by `next`.
+    counter: u16,
 }
 
+impl CommandIds {
+    /// Command IDs 0 to `number_of_ids - 1`, none of them in use.
+    pub(crate) fn new(number_of_ids: usize) -> Self {
+        Self {
+            outstanding: alloc::vec![0; number_of_ids.div_ceil(64)],
+            number_of_ids,
+            counter: 0,
+        }
+    }
+
+    /// Reserve the next free ID of the counter wrapping at the number of IDs,
+    /// or `None` if all are in use.
+    pub(crate) fn next(&mut self) -> Option<u16> {
+        for _ in 0..self.number_of_ids {
+            let command_id = self.counter;
+            self.counter = ((command_id as usize + 1) % self.number_of_ids) as u16;
+            if !self.is_outstanding(command_id) {
+                self.reserve(command_id);
+                return Some(command_id);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn reserve(&mut self, command_id: u16) {
+        self.outstanding[command_id as usize / 64] |= 1 << (command_id % 64);
+    }
+
+    pub(crate) fn release(&mut self, command_id: u16) {
+        if let Some(word) = self.outstanding.get_mut(command_id as usize / 64) {
+            *word &= !(1 << (command_id % 64));
+        }
+    }
+
+    fn is_outstanding(&self, command_id: u16) -> bool {
+        self.outstanding[command_id as usize / 64] & (1 << (command_id % 64)) != 0
+    }
+}
+
+/// Number of unsuccessful completion polls after which the controller status is checked.
+const CONTROLLER_STATUS_POLL_INTERVAL: u32 = 1 << 10;
+
 impl<A: Allocator> IoQueuePair<A> {
     pub fn id(&self) -> IoQueuePairId {
         self.id
     }
 
+    /// The physical address of the submission queue, as programmed into the controller.
+    pub fn submission_queue_physical_address(&self) -> usize {
+        self.submission.get_addr()
+    }
+
+    /// The physical address of the completion queue, as programmed into the controller.
+    pub fn completion_queue_physical_address(&self) -> usize {
+        self.completion.get_addr()
+    }
+
+    /// Whether blocking completions keep waiting while the controller reports that it paused
+    /// processing commands (CSTS.PP), instead of failing with
+    /// [`Error::ControllerProcessingPaused`]. Defaults to `false`.
+    pub fn set_wait_while_processing_paused(&mut self, wait: bool) {
+        self.wait_while_processing_paused = wait;
+    }
+
     pub fn allocate_buffer<T>(&self, number_of_elements: usize) -> Result<Dma<T>, Error> {
         if number_of_elements == 0 {
             return Err(Error::NumberOfElementsIsZero);
@@ -82,33 +409,149 @@ impl<A: Allocator> IoQueuePair<A> {
     }
 
     /// Write the content of the provided `buffer` to the device at the `logical_block_address`.
-    /// The `buffer` needs to be page aligned,
+    /// The `buffer` needs to be dword aligned,
     /// its size must be a multiple of the name space block size and not exceed the maximum transfer size.
     pub fn write<T>(&mut self, buffer: &Dma<T>, logical_block_address: u64) -> Result<(), Error> {
-        self.submit_write(buffer, logical_block_address)?;
-        loop {
-            if let Ok(()) = self.complete_io() {
-                break;
-            }
-        }
-        Ok(())
+        self.write_with_options(buffer, logical_block_address, IoOptions::default())
+    }
+
+    /// Like [`Self::write`], with the Limited Retry, Force Unit Access and access latency
+    /// `options` set in the command.
+    pub fn write_with_options<T>(
+        &mut self,
+        buffer: &Dma<T>,
+        logical_block_address: u64,
+        options: IoOptions,
+    ) -> Result<(), Error> {
+        let command_id = self.submit_write_command(buffer, logical_block_address, options)?;
+        self.complete_io_spin(command_id)
     }
 
     /// Fill the provided `buffer` with data read from the device at the `logical_block_address`.
-    /// The `buffer` needs to be page aligned,
+    /// The `buffer` needs to be dword aligned,
     /// its size must be a multiple of the name space block size and not exceed the maximum transfer size.
+    /// The data is transferred directly into the `buffer` with a single command,
+    /// buffers spanning many pages are described by a PRP list.
     pub fn read<T>(
         &mut self,
         buffer: &mut Dma<T>,
         logical_block_address: u64,
     ) -> Result<(), Error> {
-        self.submit_read(buffer, logical_block_address)?;
-        loop {
-            if let Ok(()) = self.complete_io() {
-                break;
-            }
+        self.read_with_options(buffer, logical_block_address, IoOptions::default())
+    }
+
+    /// Like [`Self::read`], with the Limited Retry, Force Unit Access and access latency
+    /// `options` set in the command.
+    pub fn read_with_options<T>(
+        &mut self,
+        buffer: &mut Dma<T>,
+        logical_block_address: u64,
+        options: IoOptions,
+    ) -> Result<(), Error> {
+        let command_id = self.submit_read_command(buffer, logical_block_address, options)?;
+        self.complete_io_spin(command_id)
+    }
+
+    /// Like [`Self::read`], but returns `false` instead of failing with
+    /// [`Error::DeallocatedOrUnwrittenBlock`] if the range contains blocks that were never
+    /// written or are deallocated, whose content is undefined then.
+    /// Requires the error to be enabled with
+    /// [`crate::NvmeDevice::set_deallocated_or_unwritten_block_error`], otherwise
+    /// such blocks read as [`Namespace::deallocated_read_value`] and `true` is returned.
+    pub fn read_written<T>(
+        &mut self,
+        buffer: &mut Dma<T>,
+        logical_block_address: u64,
+    ) -> Result<bool, Error> {
+        match self.read(buffer, logical_block_address) {
+            Ok(()) => Ok(true),
+            Err(Error::DeallocatedOrUnwrittenBlock(_, _)) => Ok(false),
+            Err(error) => Err(error),
         }
-        Ok(())
+    }
+
+    /// Read the blocks starting at the `logical_block_address` into the `length` bytes at
+    /// `buffer_offset` into the `buffer`, e.g. one big buffer registered up front, without
+    /// copying. The data pointer points into the middle of a page at the offset, which must be
+    /// qword aligned for PRPs and dword aligned for SGLs, which are used if the controller
+    /// supports them; the `length` must be a multiple of the block size.
+    pub fn read_at_offset(
+        &mut self,
+        buffer: &mut Dma<u8>,
+        buffer_offset: usize,
+        logical_block_address: u64,
+        length: usize,
+    ) -> Result<(), Error> {
+        let virtual_address = buffer.virtual_address() as usize + buffer_offset;
+        if virtual_address & 0b11 != 0 {
+            return Err(Error::VirtualAddressIsNotDwordAligned(virtual_address));
+        }
+        // the kernel maps the buffers of passed through commands itself
+        if virtual_address & 0b111 != 0
+            && self.sgl_support == SglSupport::NotSupported
+            && !self.is_passed_through()
+        {
+            return Err(Error::VirtualAddressIsNotQwordAligned(virtual_address));
+        }
+        // SAFETY: the region is only used for this command and the `buffer` is borrowed
+        // mutably until it is completed.
+        let mut region = unsafe {
+            buffer.region(
+                buffer_offset,
+                length,
+                self.page_size,
+                self.allocator.as_ref(),
+            )?
+        };
+        self.read(&mut region, logical_block_address)
+    }
+
+    /// Fill `destination` with the bytes of the namespace starting at `byte_offset`,
+    /// which need not be block aligned. The blocks covering the range are read into
+    /// a temporary buffer and only the requested bytes are copied out.
+    pub fn read_bytes(&mut self, destination: &mut [u8], byte_offset: u64) -> Result<(), Error> {
+        if destination.is_empty() {
+            return Ok(());
+        }
+        let block_size = self.namespace.block_size;
+        let logical_block_address = byte_offset / block_size;
+        let offset = (byte_offset % block_size) as usize;
+        let mut buffer = self.allocate_buffer::<u8>(offset + destination.len())?;
+        let result = self.read(&mut buffer, logical_block_address);
+        if result.is_ok() {
+            destination.copy_from_slice(&buffer[offset..offset + destination.len()]);
+        }
+        self.deallocate_buffer(buffer)?;
+        result
+    }
+
+    /// Like [`Self::write`], but gives up waiting for the completion after `max_spins` polls
+    /// with [`Error::CompletionTimeout`]. The command stays outstanding then,
+    /// so the `buffer` must be kept until it is completed, e.g. by [`Self::poll_completions`].
+    pub fn write_timeout<T>(
+        &mut self,
+        buffer: &Dma<T>,
+        logical_block_address: u64,
+        max_spins: u64,
+    ) -> Result<(), Error> {
+        let command_id =
+            self.submit_write_command(buffer, logical_block_address, IoOptions::default())?;
+        self.complete_command(command_id, max_spins)
+    }
+
+    /// Like [`Self::read`], but gives up waiting for the completion after `max_spins` polls
+    /// with [`Error::CompletionTimeout`]. The command stays outstanding then and may still
+    /// transfer data into the `buffer`, which must be kept until it is completed,
+    /// e.g. by [`Self::poll_completions`].
+    pub fn read_timeout<T>(
+        &mut self,
+        buffer: &mut Dma<T>,
+        logical_block_address: u64,
+        max_spins: u64,
+    ) -> Result<(), Error> {
+        let command_id =
+            self.submit_read_command(buffer, logical_block_address, IoOptions::default())?;
+        self.complete_command(command_id, max_spins)
     }
 
     pub fn submit_read<T>(
@@ -116,107 +559,1251 @@ impl<A: Allocator> IoQueuePair<A> {
         buffer: &mut Dma<T>,
         logical_block_address: u64,
     ) -> Result<(), Error> {
-        if buffer.size() > self.maximum_transfer_size {
-            return Err(Error::BufferLengthBiggerThanMaximumTransferSize(
-                buffer.size(),
-                self.maximum_transfer_size,
-            ));
+        self.submit_read_with_options(buffer, logical_block_address, IoOptions::default())
+    }
+
+    /// Like [`Self::submit_read`], with the `options` set in the command.
+    pub fn submit_read_with_options<T>(
+        &mut self,
+        buffer: &mut Dma<T>,
+        logical_block_address: u64,
+        options: IoOptions,
+    ) -> Result<(), Error> {
+        self.submit_read_command(buffer, logical_block_address, options)?;
+        Ok(())
+    }
+
+    fn submit_read_command<T>(
+        &mut self,
+        buffer: &mut Dma<T>,
+        logical_block_address: u64,
+        options: IoOptions,
+    ) -> Result<u16, Error> {
+        let range = self.transfer_range(buffer.size(), logical_block_address)?;
+        let number_of_blocks = range.number_of_blocks_zero_based()?;
+        let namespace_id = self.namespace.id.0;
+        self.submit_with_buffer(buffer, |command_id, prp_1, prp_2| {
+            NvmeCommand::io_read(
+                command_id,
+                namespace_id,
+                range.start,
+                number_of_blocks,
+                prp_1,
+                prp_2,
+                options,
+            )
+        })
+    }
+
+    pub fn submit_write<T>(
+        &mut self,
+        buffer: &Dma<T>,
+        logical_block_address: u64,
+    ) -> Result<(), Error> {
+        self.submit_write_with_options(buffer, logical_block_address, IoOptions::default())
+    }
+
+    /// Like [`Self::submit_write`], with the `options` set in the command.
+    pub fn submit_write_with_options<T>(
+        &mut self,
+        buffer: &Dma<T>,
+        logical_block_address: u64,
+        options: IoOptions,
+    ) -> Result<(), Error> {
+        self.submit_write_command(buffer, logical_block_address, options)?;
+        Ok(())
+    }
+
+    fn submit_write_command<T>(
+        &mut self,
+        buffer: &Dma<T>,
+        logical_block_address: u64,
+        options: IoOptions,
+    ) -> Result<u16, Error> {
+        let range = self.transfer_range(buffer.size(), logical_block_address)?;
+        let number_of_blocks = range.number_of_blocks_zero_based()?;
+        let namespace_id = self.namespace.id.0;
+        self.submit_with_buffer(buffer, |command_id, prp_1, prp_2| {
+            NvmeCommand::io_write(
+                command_id,
+                namespace_id,
+                range.start,
+                number_of_blocks,
+                prp_1,
+                prp_2,
+                options,
+            )
+        })
+    }
+
+    /// Write the content of every buffer to the device at its logical block address.
+    /// As many writes as fit into the queues are kept in flight;
+    /// completions are reaped to make room for the remaining writes.
+    /// With `fail_fast`, no further writes are submitted after the first failure,
+    /// the writes in flight are completed and the failure is returned.
+    /// Otherwise all writes are attempted and the result of every write is returned
+    /// in the order of the `items`.
+    /// Completions of commands submitted before are kept for later reaps.
+    /// If the controller fails, its error is returned with writes possibly still in flight,
+    /// so the buffers must not be reused until the queue pair is deleted.
+    pub fn write_all(
+        &mut self,
+        items: &[(Dma<u8>, u64)],
+        fail_fast: bool,
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        let mut results: Vec<Result<(), Error>> = items.iter().map(|_| Ok(())).collect();
+        let mut in_flight: HashMap<u16, usize, RandomState> =
+            HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0));
+        let mut first_failure = None;
+        let mut next = 0;
+        while (next < items.len() && first_failure.is_none()) || !in_flight.is_empty() {
+            while next < items.len()
+                && first_failure.is_none()
+                && self.outstanding_commands < self.completion.len() - 1
+            {
+                let (buffer, logical_block_address) = &items[next];
+                match self.submit_write_command(
+                    buffer,
+                    *logical_block_address,
+                    IoOptions::default(),
+                ) {
+                    Ok(command_id) => {
+                        in_flight.insert(command_id, next);
+                    }
+                    Err(error) if fail_fast => first_failure = Some(error),
+                    Err(error) => results[next] = Err(error),
+                }
+                next += 1;
+            }
+            if in_flight.is_empty() {
+                if next == items.len() || first_failure.is_some() {
+                    break;
+                }
+                // the queues are full of commands submitted before, make room for the writes
+                self.stash_next()?;
+                continue;
+            }
+            let (command_id, result) = self.complete_one_of(&in_flight)?;
+            if let (Some(index), Err(error)) = (in_flight.remove(&command_id), result) {
+                if fail_fast {
+                    first_failure.get_or_insert(error);
+                } else {
+                    results[index] = Err(error);
+                }
+            }
         }
-        if buffer.size() as u64 % self.namespace.block_size != 0 {
-            return Err(Error::BufferLengthNotAMultipleOfNamespaceBlockSize(
-                buffer.size(),
-                self.namespace.block_size,
-            ));
+        match first_failure {
+            Some(error) => Err(error),
+            None => Ok(results),
         }
-        let prp_container = prp::allocate(buffer, self.page_size, self.allocator.as_ref())?;
-        let prp_1 = prp_container.prp_1() as u64;
-        let prp_2 = prp_container.prp_2().map(|prp_2| prp_2 as u64).unwrap_or(0);
-        let blocks = buffer.size() as u64 / self.namespace.block_size;
+    }
 
-        let command_id = self.submission.tail as u16;
-        self.prp_containers
-            .try_insert(command_id, prp_container)
-            .map_err(|_| Error::PrpContainerAlreadyExists(command_id))?;
+    /// Fill the `number_of_blocks` blocks starting at the `logical_block_address` with the
+    /// `pattern`, repeated from the start of every block, e.g. for validation or burn-in.
+    /// One buffer holding the pattern is written in chunks, with as many writes in flight as
+    /// fit into the queues. After the first failed write no further writes are submitted,
+    /// the writes in flight are completed and the failure is returned.
+    /// Completions of commands submitted before are kept for later reaps.
+    /// Returns the number of blocks written.
+    pub fn write_pattern(
+        &mut self,
+        pattern: &[u8],
+        logical_block_address: u64,
+        number_of_blocks: u64,
+    ) -> Result<u64, Error> {
+        if pattern.is_empty() {
+            return Err(Error::PatternIsEmpty);
+        }
+        if number_of_blocks == 0 {
+            return Ok(0);
+        }
+        if logical_block_address
+            .checked_add(number_of_blocks)
+            .is_none_or(|end| end > self.namespace.blocks)
+        {
+            let range = LbaRange::new(
+                logical_block_address,
+                number_of_blocks.min(u32::MAX as u64) as u32,
+            );
+            return Err(Error::LbaRangeOutOfBounds(range, self.namespace.blocks));
+        }
+        let block_size = self.namespace.block_size as usize;
+        let write_size = self
+            .maximum_transfer_size
+            .map_or(PATTERN_WRITE_SIZE, |maximum| {
+                PATTERN_WRITE_SIZE.min(maximum)
+            });
+        let blocks_per_write = ((write_size / block_size).max(1) as u64).min(number_of_blocks);
+        let mut buffer = self.allocate_buffer::<u8>(blocks_per_write as usize * block_size)?;
+        for block in buffer[..].chunks_exact_mut(block_size) {
+            for (byte, &value) in block.iter_mut().zip(pattern.iter().cycle()) {
+                *byte = value;
+            }
+        }
 
-        let command = NvmeCommand::io_read(
-            command_id,
-            self.namespace.id.0,
-            logical_block_address,
-            blocks as u16 - 1,
-            prp_1,
-            prp_2,
-        );
+        let mut in_flight: HashMap<u16, u64, RandomState> =
+            HashMap::with_hasher(RandomState::with_seeds(0, 0, 0, 0));
+        let mut first_failure = None;
+        let mut next = logical_block_address;
+        let end = logical_block_address + number_of_blocks;
+        let mut blocks_written = 0;
+        while (next < end && first_failure.is_none()) || !in_flight.is_empty() {
+            while next < end
+                && first_failure.is_none()
+                && self.outstanding_commands < self.completion.len() - 1
+            {
+                let blocks = blocks_per_write.min(end - next);
+                // SAFETY: the buffer is only read by the writes until all are completed.
+                let view = unsafe { buffer.prefix(blocks as usize * block_size) };
+                match self.submit_write_command(&view, next, IoOptions::default()) {
+                    Ok(command_id) => {
+                        in_flight.insert(command_id, blocks);
+                    }
+                    Err(error) => first_failure = Some(error),
+                }
+                next += blocks;
+            }
+            if in_flight.is_empty() {
+                if next == end || first_failure.is_some() {
+                    break;
+                }
+                // the queues are full of commands submitted before, make room for the writes
+                if let Err(error) = self.stash_next() {
+                    first_failure = Some(error);
+                    break;
+                }
+                continue;
+            }
+            let (command_id, result) = match self.complete_one_of(&in_flight) {
+                Ok(completion) => completion,
+                // The controller failed. Writes only read the buffer, so it can be freed
+                // even though they may never complete.
+                Err(error) => {
+                    first_failure.get_or_insert(error);
+                    break;
+                }
+            };
+            let blocks = in_flight.remove(&command_id).unwrap_or(0);
+            match result {
+                Ok(()) => blocks_written += blocks,
+                Err(error) => {
+                    first_failure.get_or_insert(error);
+                }
+            }
+        }
+        let deallocated = self.deallocate_buffer(buffer);
+        match first_failure {
+            Some(error) => Err(error),
+            None => deallocated.map(|()| blocks_written),
+        }
+    }
 
-        let tail = self.submission.submit(command);
-        set_submission_queue_tail_doorbell(
-            self.id.0,
-            tail as u32,
-            self.device_address as *mut u8,
-            self.doorbell_stride,
-        );
+    /// The number of PRP list pages a transfer of `transfer_bytes` from a page aligned buffer
+    /// needs, i.e. taken from the PRP list pool of this queue pair while it is in flight.
+    pub fn prp_overhead(&self, transfer_bytes: usize) -> usize {
+        let needed_number_of_pages = transfer_bytes.div_ceil(self.page_size);
+        prp::number_of_prp_lists(needed_number_of_pages, self.page_size)
+    }
+
+    /// Build the PRPs for the registered `buffer` once,
+    /// so they can be reused by [`Self::submit_read_with_prp_list`] and
+    /// [`Self::submit_write_with_prp_list`] without allocating on every I/O.
+    pub fn build_prp_list<T>(&mut self, buffer: &Dma<T>) -> Result<PrpList, Error> {
+        PrpList::new(
+            buffer,
+            self.page_size,
+            self.allocator.as_ref(),
+            &mut self.prp_list_pool,
+        )
+    }
+
+    /// Release the PRPs built by [`Self::build_prp_list`].
+    /// The PRP list must not be used by any outstanding command.
+    pub fn release_prp_list(&mut self, prp_list: PrpList) -> Result<(), Error> {
+        prp::deallocate(
+            prp_list.container,
+            self.allocator.as_ref(),
+            &mut self.prp_list_pool,
+        )
+    }
+
+    /// Like [`Self::submit_read`], but uses the `prp_list` built for the `buffer` beforehand.
+    /// The `prp_list` must be kept until the command is completed.
+    pub fn submit_read_with_prp_list<T>(
+        &mut self,
+        buffer: &mut Dma<T>,
+        prp_list: &PrpList,
+        logical_block_address: u64,
+    ) -> Result<(), Error> {
+        let range = self.transfer_range(buffer.size(), logical_block_address)?;
+        let number_of_blocks = range.number_of_blocks_zero_based()?;
+        let namespace_id = self.namespace.id.0;
+        self.submit_with_prp_list(buffer, prp_list, |command_id, prp_1, prp_2| {
+            NvmeCommand::io_read(
+                command_id,
+                namespace_id,
+                range.start,
+                number_of_blocks,
+                prp_1,
+                prp_2,
+                IoOptions::default(),
+            )
+        })?;
         Ok(())
     }
 
-    pub fn submit_write<T>(
+    /// Like [`Self::submit_write`], but uses the `prp_list` built for the `buffer` beforehand.
+    /// The `prp_list` must be kept until the command is completed.
+    pub fn submit_write_with_prp_list<T>(
         &mut self,
         buffer: &Dma<T>,
+        prp_list: &PrpList,
         logical_block_address: u64,
     ) -> Result<(), Error> {
-        if buffer.size() > self.maximum_transfer_size {
-            return Err(Error::BufferLengthBiggerThanMaximumTransferSize(
-                buffer.size(),
-                self.maximum_transfer_size,
+        let range = self.transfer_range(buffer.size(), logical_block_address)?;
+        let number_of_blocks = range.number_of_blocks_zero_based()?;
+        let namespace_id = self.namespace.id.0;
+        self.submit_with_prp_list(buffer, prp_list, |command_id, prp_1, prp_2| {
+            NvmeCommand::io_write(
+                command_id,
+                namespace_id,
+                range.start,
+                number_of_blocks,
+                prp_1,
+                prp_2,
+                IoOptions::default(),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Report all zones of the zoned namespace of this queue pair.
+    pub fn report_zones(&mut self) -> Result<Vec<ZoneDescriptor>, Error> {
+        let size = self
+            .maximum_transfer_size
+            .map_or(self.page_size, |maximum| self.page_size.min(maximum));
+        let buffer: Dma<u8> = Dma::allocate(size, self.page_size, self.allocator.as_ref())?;
+        let result = self.report_zones_into(&buffer);
+        buffer.deallocate(self.allocator.as_ref())?;
+        result
+    }
+
+    fn report_zones_into(&mut self, buffer: &Dma<u8>) -> Result<Vec<ZoneDescriptor>, Error> {
+        let maximum_zones_per_report = buffer.size() / zns::ZONE_REPORT_ENTRY_SIZE - 1;
+        let namespace_id = self.namespace.id.0;
+        let mut zones: Vec<ZoneDescriptor> = Vec::new();
+        let mut zone_start_logical_block_address = 0;
+        loop {
+            let command_id = self.submit_with_buffer(buffer, |command_id, prp_1, prp_2| {
+                NvmeCommand::zone_management_receive(
+                    command_id,
+                    namespace_id,
+                    zone_start_logical_block_address,
+                    (buffer.size() / 4) as u32,
+                    true,
+                    prp_1,
+                    prp_2,
+                )
+            })?;
+            self.complete_io_spin(command_id)?;
+
+            // With a partial report, the header contains the number of zones in this report.
+            let mut number_of_zones = [0u8; 8];
+            number_of_zones.copy_from_slice(&buffer[0..8]);
+            let number_of_zones =
+                (u64::from_le_bytes(number_of_zones) as usize).min(maximum_zones_per_report);
+            let mut new_zones = 0;
+            for i in 1..=number_of_zones {
+                let offset = i * zns::ZONE_REPORT_ENTRY_SIZE;
+                let zone = ZoneDescriptor::from_bytes(
+                    &buffer[offset..offset + zns::ZONE_REPORT_ENTRY_SIZE],
+                );
+                // The report starts with the zone containing the start address,
+                // which is the last zone of the previous report.
+                if zones.last().is_some_and(|last| {
+                    zone.start_logical_block_address <= last.start_logical_block_address
+                }) {
+                    continue;
+                }
+                zones.push(zone);
+                new_zones += 1;
+            }
+            match zones.last() {
+                Some(last) if new_zones > 0 && number_of_zones == maximum_zones_per_report => {
+                    zone_start_logical_block_address = last.start_logical_block_address + 1;
+                }
+                _ => break,
+            }
+        }
+        Ok(zones)
+    }
+
+    /// Report the zones of the zoned namespace of this queue pair,
+    /// which are implicitly or explicitly opened.
+    /// The number of open zones is limited by the controller.
+    pub fn open_zones(&mut self) -> Result<Vec<ZoneDescriptor>, Error> {
+        let mut zones = self.report_zones()?;
+        zones.retain(|zone| zone.state.is_open());
+        Ok(zones)
+    }
+
+    /// Like [`Self::deallocate`], but shrinks each range to the preferred deallocate alignment
+    /// and granularity of the namespace (see [`Namespace::align_deallocate_range`]),
+    /// as many controllers only reclaim whole aligned granules.
+    /// The blocks cut off at the ends of the ranges are not deallocated, so reading them
+    /// may still return the old data.
+    pub fn deallocate_aligned(&mut self, ranges: &[LbaRange]) -> Result<(), Error> {
+        let aligned_ranges: Vec<LbaRange> = ranges
+            .iter()
+            .filter_map(|range| self.namespace.align_deallocate_range(range))
+            .collect();
+        self.deallocate(&aligned_ranges)
+    }
+
+    /// Deallocate (trim) the `ranges` of logical blocks with one Dataset Management command.
+    /// Reading deallocated blocks returns the value reported in DLFEAT.
+    pub fn deallocate(&mut self, ranges: &[LbaRange]) -> Result<(), Error> {
+        if !self.optional_nvm_command_support.dataset_management {
+            return Err(Error::DatasetManagementNotSupported);
+        }
+        if ranges.len() > MAXIMUM_DATASET_RANGES {
+            return Err(Error::TooManyDatasetRanges(ranges.len()));
+        }
+        if ranges.is_empty() {
+            return Ok(());
+        }
+        for &range in ranges {
+            if range.is_empty() {
+                return Err(Error::NumberOfBlocksIsZero);
+            }
+            if !range.is_within(&self.namespace) {
+                return Err(Error::LbaRangeOutOfBounds(range, self.namespace.blocks));
+            }
+        }
+        let mut buffer: Dma<u8> = Dma::allocate(
+            ranges.len() * DATASET_RANGE_SIZE,
+            self.page_size,
+            self.allocator.as_ref(),
+        )?;
+        for (descriptor, range) in buffer[..ranges.len() * DATASET_RANGE_SIZE]
+            .chunks_exact_mut(DATASET_RANGE_SIZE)
+            .zip(ranges)
+        {
+            // context attributes stay 0, the number of blocks is not 0-based here
+            descriptor[0..4].fill(0);
+            descriptor[4..8].copy_from_slice(&range.count.to_le_bytes());
+            descriptor[8..16].copy_from_slice(&range.start.to_le_bytes());
+        }
+        let namespace_id = self.namespace.id.0;
+        let number_of_ranges = ranges.len() as u16;
+        let result = self
+            .submit_with_buffer(&buffer, |command_id, prp_1, prp_2| {
+                NvmeCommand::dataset_management(
+                    command_id,
+                    namespace_id,
+                    number_of_ranges,
+                    true,
+                    prp_1,
+                    prp_2,
+                )
+            })
+            .and_then(|command_id| self.complete_io_spin(command_id));
+        buffer.deallocate(self.allocator.as_ref())?;
+        result
+    }
+
+    /// Copy the blocks of the `source_ranges` in order to the blocks starting at the
+    /// `destination_lba` within the controller, without transferring the data to the host.
+    /// At least one range is required, and the ranges must not exceed the limits of the
+    /// namespace (MSRC, MSSRL and MCL).
+    /// Copy is optional; fails with [`Error::CopyNotSupported`] if the controller does not
+    /// support it.
+    pub fn copy(&mut self, source_ranges: &[LbaRange], destination_lba: u64) -> Result<(), Error> {
+        if !self.optional_nvm_command_support.copy {
+            return Err(Error::CopyNotSupported);
+        }
+        if source_ranges.is_empty() {
+            return Err(Error::NoCopySourceRanges);
+        }
+        let maximum_source_range_count = self.namespace.maximum_source_range_count;
+        if source_ranges.len() > maximum_source_range_count as usize {
+            return Err(Error::TooManyCopySourceRanges(
+                source_ranges.len(),
+                maximum_source_range_count,
             ));
         }
-        if buffer.size() as u64 % self.namespace.block_size != 0 {
-            return Err(Error::BufferLengthNotAMultipleOfNamespaceBlockSize(
-                buffer.size(),
-                self.namespace.block_size,
+        let maximum_single_source_range_length = self.namespace.maximum_single_source_range_length;
+        let mut number_of_blocks: u64 = 0;
+        let mut source_range_lengths = Vec::with_capacity(source_ranges.len());
+        for range in source_ranges {
+            let number_of_blocks_zero_based = range.number_of_blocks_zero_based()?;
+            source_range_lengths.push(number_of_blocks_zero_based);
+            let count = number_of_blocks_zero_based as u32 + 1;
+            if !range.is_within(&self.namespace) {
+                return Err(Error::LbaRangeOutOfBounds(*range, self.namespace.blocks));
+            }
+            if maximum_single_source_range_length != 0
+                && count > maximum_single_source_range_length as u32
+            {
+                return Err(Error::CopySourceRangeTooLong(
+                    count,
+                    maximum_single_source_range_length,
+                ));
+            }
+            number_of_blocks += count as u64;
+        }
+        let maximum_copy_length = self.namespace.maximum_copy_length;
+        if maximum_copy_length != 0 && number_of_blocks > maximum_copy_length as u64 {
+            return Err(Error::CopyLengthTooLong(
+                number_of_blocks,
+                maximum_copy_length,
+            ));
+        }
+        let destination = LbaRange::new(
+            destination_lba,
+            number_of_blocks.min(u32::MAX as u64) as u32,
+        );
+        if number_of_blocks > u32::MAX as u64 || !destination.is_within(&self.namespace) {
+            return Err(Error::LbaRangeOutOfBounds(
+                destination,
+                self.namespace.blocks,
             ));
         }
-        let prp_container = prp::allocate(buffer, self.page_size, self.allocator.as_ref())?;
+
+        let mut buffer: Dma<u8> = Dma::allocate(
+            source_ranges.len() * COPY_SOURCE_RANGE_SIZE,
+            self.page_size,
+            self.allocator.as_ref(),
+        )?;
+        for ((entry, range), number_of_blocks) in buffer
+            [..source_ranges.len() * COPY_SOURCE_RANGE_SIZE]
+            .chunks_exact_mut(COPY_SOURCE_RANGE_SIZE)
+            .zip(source_ranges)
+            .zip(source_range_lengths)
+        {
+            entry.fill(0);
+            entry[8..16].copy_from_slice(&range.start.to_le_bytes()); // SLBA
+            entry[16..18].copy_from_slice(&number_of_blocks.to_le_bytes()); // NLB
+        }
+        let namespace_id = self.namespace.id.0;
+        // 1 to 256 ranges as checked above, so the 0-based number fits in a byte
+        let number_of_ranges_zero_based = (source_ranges.len() - 1) as u8;
+        let result = self
+            .submit_with_buffer(&buffer, |command_id, prp_1, prp_2| {
+                NvmeCommand::copy(
+                    command_id,
+                    namespace_id,
+                    destination_lba,
+                    number_of_ranges_zero_based,
+                    prp_1,
+                    prp_2,
+                )
+            })
+            .and_then(|command_id| self.complete_io_spin(command_id));
+        buffer.deallocate(self.allocator.as_ref())?;
+        result
+    }
+
+    /// Compare the content of the `buffer` with the blocks at the `logical_block_address`
+    /// on the device without reading them into host memory.
+    /// Returns whether they match; a mismatch (Compare Failure) is not an error.
+    /// The `buffer` has the same requirements as for [`Self::read`].
+    /// Compare is optional; fails with [`Error::CompareNotSupported`] if the controller
+    /// does not support it.
+    pub fn compare<T>(
+        &mut self,
+        buffer: &Dma<T>,
+        logical_block_address: u64,
+    ) -> Result<bool, Error> {
+        if !self.optional_nvm_command_support.compare {
+            return Err(Error::CompareNotSupported);
+        }
+        let range = self.transfer_range(buffer.size(), logical_block_address)?;
+        let number_of_blocks = range.number_of_blocks_zero_based()?;
+        let namespace_id = self.namespace.id.0;
+        let command_id = self.submit_with_buffer(buffer, |command_id, prp_1, prp_2| {
+            NvmeCommand::compare(
+                command_id,
+                namespace_id,
+                range.start,
+                number_of_blocks,
+                prp_1,
+                prp_2,
+            )
+        })?;
+        match self.complete_io_spin(command_id) {
+            Ok(()) => Ok(true),
+            Err(Error::IoCompletionQueueFailure(_, _, status))
+                if status.code == StatusCode::CompareFailure =>
+            {
+                Ok(false)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Set the blocks of the `range`, at most 65536, to zero without transferring any data.
+    /// With `deallocate`, the controller may deallocate the blocks instead of writing them.
+    /// Write Zeroes is optional and not supported by all controllers (e.g. Samsung);
+    /// fails with [`Error::WriteZeroesNotSupported`] on those.
+    pub fn write_zeroes(&mut self, range: LbaRange, deallocate: bool) -> Result<(), Error> {
+        if !self.optional_nvm_command_support.write_zeroes {
+            return Err(Error::WriteZeroesNotSupported);
+        }
+        self.check_write_zeroes_deallocate(deallocate)?;
+        let number_of_blocks = range.number_of_blocks_zero_based()?;
+        if !range.is_within(&self.namespace) {
+            return Err(Error::LbaRangeOutOfBounds(range, self.namespace.blocks));
+        }
+        let namespace_id = self.namespace.id.0;
+        let command_id = self.submit(
+            |command_id| {
+                NvmeCommand::write_zeroes(
+                    command_id,
+                    namespace_id,
+                    range.start,
+                    number_of_blocks,
+                    deallocate,
+                )
+            },
+            None,
+        )?;
+        self.complete_io_spin(command_id)
+            .map_err(|error| match error {
+                Error::IoCompletionQueueFailure(_, _, status)
+                    if status.code == StatusCode::InvalidOpcode =>
+                {
+                    Error::WriteZeroesNotSupported
+                }
+                error => error,
+            })
+    }
+
+    /// Let the controller check that the blocks of the `range`, at most 65536, are readable,
+    /// including their protection information, without transferring any data,
+    /// e.g. to scrub the media.
+    /// Unreadable blocks fail with [`Error::IoCompletionQueueFailure`] and a media and data
+    /// integrity status, e.g. [`StatusCode::UnrecoveredReadError`].
+    /// Verify is optional; fails with [`Error::VerifyNotSupported`] if the controller does not
+    /// support it.
+    pub fn verify(&mut self, range: LbaRange) -> Result<(), Error> {
+        if !self.optional_nvm_command_support.verify {
+            return Err(Error::VerifyNotSupported);
+        }
+        let number_of_blocks = range.number_of_blocks_zero_based()?;
+        if !range.is_within(&self.namespace) {
+            return Err(Error::LbaRangeOutOfBounds(range, self.namespace.blocks));
+        }
+        let namespace_id = self.namespace.id.0;
+        let command_id = self.submit(
+            |command_id| {
+                NvmeCommand::verify(command_id, namespace_id, range.start, number_of_blocks)
+            },
+            None,
+        )?;
+        self.complete_io_spin(command_id)
+    }
+
+    /// Commit the data and metadata in the volatile write cache of the controller
+    /// to non-volatile media, so all completed writes to the namespace survive a power loss.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let namespace_id = self.namespace.id.0;
+        let command_id = self.submit(
+            |command_id| NvmeCommand::flush(command_id, namespace_id),
+            None,
+        )?;
+        self.complete_io_spin(command_id)
+    }
+
+    /// Reset all zones of the zoned namespace of this queue pair.
+    pub fn reset_all_zones(&mut self) -> Result<(), Error> {
+        let namespace_id = self.namespace.id.0;
+        let command_id = self.submit(
+            |command_id| {
+                NvmeCommand::zone_management_send(
+                    command_id,
+                    namespace_id,
+                    0,
+                    true,
+                    ZoneSendAction::Reset,
+                )
+            },
+            None,
+        )?;
+        self.complete_io_spin(command_id)
+    }
+
+    pub fn complete_io(&mut self) -> Result<(), Error> {
+        self.complete_next()?.1
+    }
+
+    /// Complete the next command and return its command ID and status,
+    /// the stashed completions first.
+    /// Fails with [`Error::CompletionQueueCompletionFailure`] if no completion is available.
+    fn complete_next(&mut self) -> Result<(u16, Result<(), Error>), Error> {
+        match self.unstash(0) {
+            Some(completion) => Ok(completion),
+            None => self.reap_next(),
+        }
+    }
+
+    /// Reap the next completion from the completion queue, ignoring the stashed completions.
+    fn reap_next(&mut self) -> Result<(u16, Result<(), Error>), Error> {
+        #[cfg(feature = "std")]
+        if self.char_device.is_some() {
+            let completion = self
+                .passthrough_completions
+                .pop_front()
+                .ok_or(Error::CompletionQueueCompletionFailure)?;
+            self.outstanding_commands = self.outstanding_commands.saturating_sub(1);
+            self.command_ids.release(completion.0);
+            self.notify(completion.0, &completion.1);
+            return Ok(completion);
+        }
+        let (tail, completion_queue_entry, _) = self.completion.complete()?;
+        unsafe {
+            core::ptr::write_volatile(self.completion.doorbell as *mut u32, tail as u32);
+        }
+        self.finish(completion_queue_entry)
+    }
+
+    /// Block until a completion is available, sleeping on the eventfd signaled by the MSI-X
+    /// interrupt of the completion queue instead of spinning.
+    /// The completion is not reaped, use e.g. [`Self::poll_completions`] afterwards.
+    /// Requires a queue pair created by
+    /// [`crate::NvmeDevice::create_io_queue_pair_with_interrupt_event`].
+    #[cfg(feature = "std")]
+    pub fn wait_for_completion_interrupt(&mut self) -> Result<(), Error> {
+        if self.is_passed_through() {
+            // passed through commands complete on submission
+            return Ok(());
+        }
+        let interrupt_event = self
+            .interrupt_event
+            .as_ref()
+            .ok_or(Error::InterruptsNotEnabled(self.id))?;
+        // the interrupt may have fired before the completion was checked, so check first
+        while !self.completion.is_completion_available() {
+            let mut counter = [0u8; 8];
+            let mut interrupt_event = interrupt_event;
+            interrupt_event
+                .read_exact(&mut counter)
+                .map_err(|error| Error::InterruptError(Box::new(error)))?;
+        }
+        Ok(())
+    }
+
+    /// Reap up to `max` available completions without waiting and return how many were reaped.
+    /// The completion queue head doorbell is only written once for the whole batch.
+    /// If commands failed, the completions are reaped nevertheless
+    /// and the failure of the first one is returned.
+    pub fn poll_completions(&mut self, max: usize) -> Result<usize, Error> {
+        let mut reaped = 0;
+        let mut first_failure = None;
+        while reaped < max {
+            let Some((_, result)) = self.unstash(0) else {
+                break;
+            };
+            reaped += 1;
+            if let Err(error) = result {
+                first_failure.get_or_insert(error);
+            }
+        }
+        #[cfg(feature = "std")]
+        if self.char_device.is_some() {
+            while reaped < max {
+                let Some((command_id, result)) = self.passthrough_completions.pop_front() else {
+                    break;
+                };
+                self.outstanding_commands = self.outstanding_commands.saturating_sub(1);
+                self.command_ids.release(command_id);
+                self.notify(command_id, &result);
+                reaped += 1;
+                if let Err(error) = result {
+                    first_failure.get_or_insert(error);
+                }
+            }
+            return first_failure.map_or(Ok(reaped), Err);
+        }
+        let mut head = None;
+        while reaped < max {
+            let Ok((tail, completion_queue_entry, _)) = self.completion.complete() else {
+                break;
+            };
+            head = Some(tail);
+            reaped += 1;
+            match self.finish(completion_queue_entry) {
+                Ok((_, Ok(()))) => {}
+                Ok((_, Err(error))) | Err(error) => {
+                    first_failure.get_or_insert(error);
+                }
+            }
+        }
+        if let Some(head) = head {
+            unsafe {
+                core::ptr::write_volatile(self.completion.doorbell as *mut u32, head as u32);
+            }
+        }
+        first_failure.map_or(Ok(reaped), Err)
+    }
+
+    /// Wait for `count` completions, at most the number of outstanding commands,
+    /// and return the command ID and status of each in the order they completed.
+    /// The PRP lists of every command are released, whether it failed or not.
+    /// Only failures of the queue pair itself, e.g. a fatal controller status, are returned
+    /// as an error; completions reaped before are lost then.
+    pub fn complete_batch(&mut self, count: usize) -> Result<Vec<Completion>, Error> {
+        let count = count.min(self.outstanding_commands + self.stashed_completions.len());
+        let mut completions = Vec::with_capacity(count);
+        for _ in 0..count {
+            completions.push(self.complete_next_spin()?);
+        }
+        Ok(completions)
+    }
+
+    /// Bookkeeping of a reaped completion: returns its command ID and status.
+    fn finish(
+        &mut self,
+        completion_queue_entry: CompletionQueueEntry,
+    ) -> Result<(u16, Result<(), Error>), Error> {
+        self.outstanding_commands = self.outstanding_commands.saturating_sub(1);
+        self.submission.head = completion_queue_entry.sq_head as usize;
+        // The PRP container is released regardless of the status to not leak it on failures.
+        // Failing to release it is reported as the status of the command,
+        // so batches can still account for every other command.
+        let command_id = completion_queue_entry.command_id;
+        self.command_ids.release(command_id);
+        let prp_container = self.prp_containers.remove(&command_id);
+        let released = match prp_container {
+            Some(prp_container) => prp::deallocate(
+                prp_container,
+                self.allocator.as_ref(),
+                &mut self.prp_list_pool,
+            ),
+            None => Ok(()),
+        };
+        let released = match self.sgl_lists.remove(&command_id) {
+            Some(list) => released.and(list.deallocate(self.allocator.as_ref())),
+            None => released,
+        };
+        let status = completion_queue_entry.status >> 1;
+        let result = released.and_then(|_| match status {
+            0 => Ok(()),
+            status => Err(Error::from_status(self.id, command_id, status)),
+        });
+        self.notify(command_id, &result);
+        Ok((command_id, result))
+    }
+
+    /// Invoke the completion callback, if any, for the reaped completion.
+    fn notify(&mut self, command_id: u16, result: &Result<(), Error>) {
+        if let Some(CompletionCallback(callback)) = &mut self.completion_callback {
+            callback(command_id, result);
+        }
+    }
+
+    /// Set the `callback` invoked with the command ID and status of every completion reaped
+    /// by this queue pair, e.g. by [`Self::poll_completions`] or [`Self::complete_batch`],
+    /// before it is returned. It runs on the thread reaping the completion,
+    /// e.g. to wake a future or signal a condition variable.
+    pub fn set_completion_callback(
+        &mut self,
+        callback: impl FnMut(u16, &Result<(), Error>) + Send + 'static,
+    ) {
+        self.completion_callback = Some(CompletionCallback(Box::new(callback)));
+    }
+
+    /// Remove the callback set with [`Self::set_completion_callback`].
+    pub fn clear_completion_callback(&mut self) {
+        self.completion_callback = None;
+    }
+
+    /// Spin until the next completion is available and complete it.
+    /// While spinning, the controller status is checked periodically.
+    fn complete_next_spin(&mut self) -> Result<(u16, Result<(), Error>), Error> {
+        self.complete_next_within(u64::MAX)?
+            .ok_or(Error::CompletionQueueCompletionFailure)
+    }
+
+    /// Spin at most `max_spins` times until the next completion is available and complete it,
+    /// the stashed completions first. Returns `None` if none became available.
+    fn complete_next_within(&mut self, max_spins: u64) -> Result<Option<Completion>, Error> {
+        match self.unstash(0) {
+            Some(completion) => Ok(Some(completion)),
+            None => self.reap_within(max_spins),
+        }
+    }
+
+    /// Spin at most `max_spins` times until a completion is available in the completion queue
+    /// and reap it. Returns `None` if none became available.
+    fn reap_within(&mut self, max_spins: u64) -> Result<Option<Completion>, Error> {
+        let mut spins: u64 = 0;
+        loop {
+            match self.reap_next() {
+                // passed through commands complete on submission, nothing else will arrive
+                Err(Error::CompletionQueueCompletionFailure) if !self.is_passed_through() => {
+                    spins += 1;
+                    if spins >= max_spins {
+                        return Ok(None);
+                    }
+                    if spins.is_multiple_of(CONTROLLER_STATUS_POLL_INTERVAL as u64) {
+                        self.check_controller_status()?;
+                    }
+                    spin_loop();
+                }
+                result => return result.map(Some),
+            }
+        }
+    }
+
+    /// Wait for the completion of the command with `command_id` and return its status,
+    /// or fail with [`Error::CompletionTimeout`] after `max_spins` polls without a completion.
+    /// Completions of other commands reaped meanwhile are stashed for the next reap,
+    /// e.g. by [`Self::poll_completions`]. A timed out command keeps its command ID,
+    /// PRP list and SGL list until its own completion is reaped.
+    fn complete_command(&mut self, command_id: u16, max_spins: u64) -> Result<(), Error> {
+        let stashed = self
+            .stashed_completions
+            .iter()
+            .position(|(stashed_id, _)| *stashed_id == command_id);
+        if let Some(completion) = stashed.and_then(|index| self.unstash(index)) {
+            return completion.1;
+        }
+        loop {
+            let (completed_id, result) = self
+                .reap_within(max_spins)?
+                .ok_or(Error::CompletionTimeout(command_id))?;
+            if completed_id == command_id {
+                return result;
+            }
+            self.stash((completed_id, result));
+        }
+    }
+
+    /// Wait for the completion of any command in `in_flight`, the stashed completions first.
+    /// Completions of other commands reaped meanwhile are stashed for the next reap.
+    fn complete_one_of<T>(
+        &mut self,
+        in_flight: &HashMap<u16, T, RandomState>,
+    ) -> Result<Completion, Error> {
+        let stashed = self
+            .stashed_completions
+            .iter()
+            .position(|(stashed_id, _)| in_flight.contains_key(stashed_id));
+        if let Some(completion) = stashed.and_then(|index| self.unstash(index)) {
+            return Ok(completion);
+        }
+        loop {
+            let completion = self
+                .reap_within(u64::MAX)?
+                .ok_or(Error::CompletionQueueCompletionFailure)?;
+            if in_flight.contains_key(&completion.0) {
+                return Ok(completion);
+            }
+            self.stash(completion);
+        }
+    }
+
+    /// Wait for the next completion in the completion queue and stash it for the next reap,
+    /// which frees its entries in the queues.
+    fn stash_next(&mut self) -> Result<(), Error> {
+        let completion = self
+            .reap_within(u64::MAX)?
+            .ok_or(Error::CompletionQueueCompletionFailure)?;
+        self.stash(completion);
+        Ok(())
+    }
+
+    fn stash(&mut self, completion: Completion) {
+        // the ID stays reserved until the completion is handed out
+        self.command_ids.reserve(completion.0);
+        self.stashed_completions.push_back(completion);
+    }
+
+    /// Hand out the stashed completion at `index` and release its command ID.
+    fn unstash(&mut self, index: usize) -> Option<Completion> {
+        let completion = self.stashed_completions.remove(index)?;
+        self.command_ids.release(completion.0);
+        Some(completion)
+    }
+
+    fn check_controller_status(&self) -> Result<(), Error> {
+        let csts = nvme::get_register_32(
+            NvmeRegs32::CSTS,
+            self.device_address as *mut u8,
+            self.device_length,
+        )?;
+        if (csts >> 1) & 0b1 == 1 {
+            return Err(Error::ControllerFatalStatus); // CFS
+        }
+        let processing_paused = (csts >> 5) & 0b1 == 1; // PP
+        if processing_paused && !self.wait_while_processing_paused {
+            return Err(Error::ControllerProcessingPaused);
+        }
+        Ok(())
+    }
+
+    /// Returns the range of blocks a transfer of `buffer_size` bytes at the `logical_block_address` covers.
+    fn transfer_range(
+        &self,
+        buffer_size: usize,
+        logical_block_address: u64,
+    ) -> Result<LbaRange, Error> {
+        let blocks = self.namespace.validate_transfer(
+            buffer_size,
+            logical_block_address,
+            self.maximum_transfer_size,
+        )?;
+        Ok(LbaRange::new(logical_block_address, blocks))
+    }
+
+    /// Builds the PRPs describing the `buffer` and submits the command created by `command_init`,
+    /// which receives the command ID, PRP1 and PRP2.
+    fn submit_with_buffer<T, F: FnOnce(u16, u64, u64) -> NvmeCommand>(
+        &mut self,
+        buffer: &Dma<T>,
+        command_init: F,
+    ) -> Result<u16, Error> {
+        #[cfg(feature = "std")]
+        if self.is_passed_through() {
+            return self.submit_passthrough(
+                |command_id| command_init(command_id, 0, 0),
+                buffer.virtual_address() as *mut u8,
+                buffer.size(),
+            );
+        }
+        self.check_completion_queue_overflow()?;
+        if self.prefers_sgl(buffer) {
+            if let Some(sgl) = sgl::build(buffer, self.page_size, self.allocator.as_ref())? {
+                return self.submit_with_sgl(sgl, command_init);
+            }
+        }
+        let prp_container = prp::allocate(
+            buffer,
+            self.page_size,
+            self.allocator.as_ref(),
+            &mut self.prp_list_pool,
+        )?;
         let prp_1 = prp_container.prp_1() as u64;
         let prp_2 = prp_container.prp_2().map(|prp_2| prp_2 as u64).unwrap_or(0);
-        let blocks = buffer.size() as u64 / self.namespace.block_size;
+        self.submit(
+            |command_id| command_init(command_id, prp_1, prp_2),
+            Some(prp_container),
+        )
+    }
 
-        let command_id = self.submission.tail as u16;
-        self.prp_containers
-            .try_insert(command_id, prp_container)
-            .map_err(|_| Error::PrpContainerAlreadyExists(command_id))?;
+    /// Whether the `buffer` is described by an SGL instead of PRPs:
+    /// PRPs require buffers aligned to 8 bytes, SGLs (if supported) at most to dwords.
+    fn prefers_sgl<T>(&self, buffer: &Dma<T>) -> bool {
+        let virtual_address = buffer.virtual_address() as usize;
+        if virtual_address & 0b0111 == 0 {
+            return false;
+        }
+        match self.sgl_support {
+            SglSupport::NotSupported => false,
+            SglSupport::Supported => true,
+            SglSupport::DwordAligned => {
+                virtual_address & 0b11 == 0 && buffer.size().is_multiple_of(4)
+            }
+        }
+    }
 
-        let command = NvmeCommand::io_write(
-            command_id,
-            self.namespace.id.0,
-            logical_block_address,
-            blocks as u16 - 1,
-            prp_1,
-            prp_2,
+    /// Submits the command created by `command_init` with the data pointer of the `sgl`.
+    /// Its descriptor list is kept until the command is completed.
+    fn submit_with_sgl<F: FnOnce(u16, u64, u64) -> NvmeCommand>(
+        &mut self,
+        sgl: Sgl,
+        command_init: F,
+    ) -> Result<u16, Error> {
+        let (data_pointer_1, data_pointer_2) = sgl.data_pointer;
+        let result = self.submit(
+            |command_id| {
+                let mut command = command_init(command_id, data_pointer_1, data_pointer_2);
+                command.flags |= sgl::PSDT_SGL;
+                command
+            },
+            None,
         );
+        match (result, sgl.list) {
+            (Ok(command_id), Some(list)) => {
+                self.sgl_lists.insert(command_id, list);
+                Ok(command_id)
+            }
+            (Err(error), Some(list)) => {
+                let _ = list.deallocate(self.allocator.as_ref());
+                Err(error)
+            }
+            (result, None) => result,
+        }
+    }
+
+    /// Submits the command created by `command_init` with the PRPs of the prebuilt `prp_list`.
+    fn submit_with_prp_list<T, F: FnOnce(u16, u64, u64) -> NvmeCommand>(
+        &mut self,
+        buffer: &Dma<T>,
+        prp_list: &PrpList,
+        command_init: F,
+    ) -> Result<u16, Error> {
+        if !prp_list.describes(buffer) {
+            return Err(Error::PrpListDoesNotDescribeBuffer);
+        }
+        #[cfg(feature = "std")]
+        if self.is_passed_through() {
+            return self.submit_passthrough(
+                |command_id| command_init(command_id, 0, 0),
+                buffer.virtual_address() as *mut u8,
+                buffer.size(),
+            );
+        }
+        let prp_1 = prp_list.container.prp_1() as u64;
+        let prp_2 = prp_list
+            .container
+            .prp_2()
+            .map(|prp_2| prp_2 as u64)
+            .unwrap_or(0);
+        self.submit(|command_id| command_init(command_id, prp_1, prp_2), None)
+    }
+
+    /// Submits the command created by `command_init`, which receives the command ID,
+    /// and rings the submission queue tail doorbell.
+    /// The `prp_container` is kept until the command is completed.
+    fn submit<F: FnOnce(u16) -> NvmeCommand>(
+        &mut self,
+        command_init: F,
+        prp_container: Option<prp::PrpContainer>,
+    ) -> Result<u16, Error> {
+        #[cfg(feature = "std")]
+        if self.is_passed_through() {
+            // commands with data are passed through in `submit_with_buffer`
+            return self.submit_passthrough(command_init, core::ptr::null_mut(), 0);
+        }
+        let checked = self
+            .check_completion_queue_overflow()
+            .and_then(|()| self.next_command_id());
+        let command_id = match checked {
+            Ok(command_id) => command_id,
+            Err(error) => {
+                // nothing is reserved yet, only the PRP lists are returned to the pool;
+                // the reason the command was not submitted takes precedence over freeing them
+                if let Some(prp_container) = prp_container {
+                    let _ = prp::deallocate(
+                        prp_container,
+                        self.allocator.as_ref(),
+                        &mut self.prp_list_pool,
+                    );
+                }
+                return Err(error);
+            }
+        };
+        if let Some(prp_container) = prp_container {
+            self.prp_containers.insert(command_id, prp_container);
+        }
 
-        let tail = self.submission.submit(command);
+        let tail = self.submission.submit(command_init(command_id));
+        self.outstanding_commands += 1;
         set_submission_queue_tail_doorbell(
             self.id.0,
             tail as u32,
             self.device_address as *mut u8,
             self.doorbell_stride,
         );
-        Ok(())
+        Ok(command_id)
     }
 
-    pub fn complete_io(&mut self) -> Result<(), Error> {
-        let (tail, completion_queue_entry, _) = self.completion.complete()?;
-        unsafe {
-            core::ptr::write_volatile(self.completion.doorbell as *mut u32, tail as u32);
+    /// Passes the command created by `command_init` through the character device,
+    /// transferring `data_length` bytes at `data`.
+    /// The kernel completes the command before returning, its status is kept until it is reaped.
+    #[cfg(feature = "std")]
+    fn submit_passthrough<F: FnOnce(u16) -> NvmeCommand>(
+        &mut self,
+        command_init: F,
+        data: *mut u8,
+        data_length: usize,
+    ) -> Result<u16, Error> {
+        self.check_completion_queue_overflow()?;
+        let char_device = self
+            .char_device
+            .clone()
+            .expect("queue pair is not passed through");
+        let command_id = self.next_command_id()?;
+        let command = command_init(command_id);
+        self.submission.submit(command);
+        self.submission.head = self.submission.tail;
+        let status = match char_device.io(self.id, &command, data, data_length) {
+            Ok(_) => Ok(()),
+            Err(error @ (Error::IoCompletionQueueFailure(..) | Error::FormatInProgress)) => {
+                Err(error)
+            }
+            Err(error) => {
+                self.command_ids.release(command_id);
+                return Err(error);
+            }
+        };
+        self.passthrough_completions.push_back((command_id, status));
+        self.outstanding_commands += 1;
+        Ok(command_id)
+    }
+
+    /// Reserve the next command ID not in use, see [`CommandIds`].
+    /// Command IDs are independent of the position of the command in the submission queue.
+    fn next_command_id(&mut self) -> Result<u16, Error> {
+        self.command_ids
+            .next()
+            .ok_or(Error::NoFreeCommandId(self.completion.len()))
+    }
+
+    fn is_passed_through(&self) -> bool {
+        #[cfg(feature = "std")]
+        return self.char_device.is_some();
+        #[cfg(not(feature = "std"))]
+        false
+    }
+
+    /// Spin until the command with `command_id` is completed and return its status.
+    fn complete_io_spin(&mut self, command_id: u16) -> Result<(), Error> {
+        self.complete_command(command_id, u64::MAX)
+    }
+
+    /// Complete every outstanding command, so the controller has consumed every submission
+    /// queue entry. The first failed command is returned after all are completed.
+    /// Fails with [`Error::DrainTimeout`] once the `deadline` has passed.
+    pub(crate) fn drain(&mut self, deadline: &mut Deadline) -> Result<(), Error> {
+        let mut first_failure = None;
+        while self.outstanding_commands > 0 || !self.stashed_completions.is_empty() {
+            let Some((_, result)) =
+                self.complete_next_within(CONTROLLER_STATUS_POLL_INTERVAL as u64)?
+            else {
+                if deadline.expired() {
+                    return Err(Error::DrainTimeout(self.id));
+                }
+                self.check_controller_status()?;
+                continue;
+            };
+            if let Err(error) = result {
+                first_failure.get_or_insert(error);
+            }
         }
-        self.submission.head = completion_queue_entry.sq_head as usize;
-        let status = completion_queue_entry.status >> 1;
-        if status != 0 {
-            return Err(Error::IoCompletionQueueFailure(status));
+        first_failure.map_or(Ok(()), Err)
+    }
+
+    /// The Deallocate bit of Write Zeroes is silently ignored by controllers not supporting it,
+    /// so it must only be set if both the controller and the namespace support it.
+    fn check_write_zeroes_deallocate(&self, deallocate: bool) -> Result<(), Error> {
+        if deallocate
+            && !(self.optional_nvm_command_support.write_zeroes
+                && self.namespace.deallocate_in_write_zeroes_supported)
+        {
+            return Err(Error::DeallocateNotSupported);
         }
-        let command_id = completion_queue_entry.command_id;
-        let prp_container = self.prp_containers.remove(&command_id);
-        if let Some(prp_container) = prp_container {
-            prp::deallocate(prp_container, self.allocator.as_ref())?;
+        Ok(())
+    }
+
+    /// The controller can post at most one completion queue entry less than the queue depth
+    /// before the host has to advance the head doorbell.
+    /// Submitting more commands than that would overflow the completion queue.
+    fn check_completion_queue_overflow(&self) -> Result<(), Error> {
+        if self.outstanding_commands >= self.completion.len() - 1 {
+            return Err(Error::CompletionQueueOverflow(self.completion.len()));
         }
         Ok(())
     }
@@ -247,3 +1834,42 @@ fn set_completion_queue_head_doorbell(
             as *mut u32;
     unsafe { core::ptr::write_volatile(head_address, value) };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::CommandIds;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn command_ids_are_unique_until_released() {
+        let number_of_ids = 130;
+        let mut command_ids = CommandIds::new(number_of_ids);
+        let mut reserved: Vec<u16> = (0..number_of_ids)
+            .map(|_| command_ids.next().expect("a command ID is free"))
+            .collect();
+        assert_eq!(command_ids.next(), None);
+        reserved.sort_unstable();
+        reserved.dedup();
+        assert_eq!(reserved.len(), number_of_ids);
+
+        command_ids.release(77);
+        assert_eq!(command_ids.next(), Some(77));
+        assert_eq!(command_ids.next(), None);
+    }
+
+    #[test]
+    fn command_ids_skip_outstanding_ids_when_wrapping() {
+        let mut command_ids = CommandIds::new(4);
+        for expected in 0..4 {
+            assert_eq!(command_ids.next(), Some(expected));
+        }
+        // a timed out command keeps its ID while the others complete
+        command_ids.release(0);
+        command_ids.release(2);
+        command_ids.release(3);
+        assert_eq!(command_ids.next(), Some(0));
+        assert_eq!(command_ids.next(), Some(2));
+        assert_eq!(command_ids.next(), Some(3));
+        assert_eq!(command_ids.next(), None);
+    }
+}
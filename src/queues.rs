@@ -1,7 +1,6 @@
 use crate::cmd::NvmeCommand;
 use crate::dma::{Allocator, Dma};
 use crate::error::Error;
-use core::hint::spin_loop;
 
 #[derive(Debug)]
 pub(crate) struct SubmissionQueue {
@@ -38,6 +37,25 @@ pub(crate) struct CompletionQueueEntry {
     pub(crate) status: u16,
 }
 
+/// Allocate the memory of a queue with `number_of_queue_entries` entries.
+/// The controller accesses a queue by its base address, so the memory must be physically
+/// contiguous; fails with [`Error::QueueNotPhysicallyContiguous`] if the allocator
+/// did not provide that.
+fn allocate_queue_memory<T, A: Allocator>(
+    number_of_queue_entries: usize,
+    page_size: usize,
+    allocator: &A,
+) -> Result<Dma<T>, Error> {
+    let memory: Dma<T> = Dma::allocate(number_of_queue_entries, page_size, allocator)?;
+    let error = match memory.is_physically_contiguous(page_size, allocator) {
+        Ok(true) => return Ok(memory),
+        Ok(false) => Error::QueueNotPhysicallyContiguous(number_of_queue_entries),
+        Err(error) => error,
+    };
+    memory.deallocate(allocator)?;
+    Err(error)
+}
+
 impl SubmissionQueue {
     pub(crate) fn new<A: Allocator>(
         number_of_queue_entries: usize,
@@ -45,13 +63,23 @@ impl SubmissionQueue {
         doorbell: usize,
         allocator: &A,
     ) -> Result<Self, Error> {
-        Ok(Self {
-            commands: Dma::allocate(number_of_queue_entries, page_size, allocator)?,
+        let commands = allocate_queue_memory(number_of_queue_entries, page_size, allocator)?;
+        Ok(Self::with_memory(commands, doorbell))
+    }
+
+    /// A queue with an entry for every element of the `commands` memory.
+    pub(crate) fn with_memory(mut commands: Dma<NvmeCommand>, doorbell: usize) -> Self {
+        // The controller only fetches submitted entries, so zeroing is optional.
+        if cfg!(feature = "zero-submission-queues") {
+            commands.zero();
+        }
+        Self {
+            len: commands.number_of_elements(),
+            commands,
             head: 0,
             tail: 0,
-            len: number_of_queue_entries,
             doorbell,
-        })
+        }
     }
 
     #[allow(dead_code)]
@@ -85,6 +113,17 @@ impl SubmissionQueue {
     pub(crate) fn get_addr(&self) -> usize {
         self.commands.physical_address() as usize
     }
+
+    /// Start again at the first entry, as the controller does after a reset.
+    pub(crate) fn reset(&mut self) {
+        self.head = 0;
+        self.tail = 0;
+    }
+
+    /// Deallocate the memory of a queue allocated with [`Self::new`].
+    pub(crate) fn deallocate<A: Allocator>(self, allocator: &A) -> Result<(), Error> {
+        self.commands.deallocate(allocator)
+    }
 }
 
 impl CompletionQueue {
@@ -94,13 +133,27 @@ impl CompletionQueue {
         doorbell: usize,
         allocator: &A,
     ) -> Result<Self, Error> {
-        Ok(Self {
-            commands: Dma::allocate(number_of_queue_entries, page_size, allocator)?,
+        let commands = allocate_queue_memory(number_of_queue_entries, page_size, allocator)?;
+        Ok(Self::with_memory(commands, doorbell))
+    }
+
+    /// A queue with an entry for every element of the `commands` memory.
+    pub(crate) fn with_memory(mut commands: Dma<CompletionQueueEntry>, doorbell: usize) -> Self {
+        // Uninitialized memory may contain set phase tags, which would be reaped as completions.
+        commands.zero();
+        Self {
+            len: commands.number_of_elements(),
+            commands,
             head: 0,
             phase: true,
-            len: number_of_queue_entries,
             doorbell,
-        })
+        }
+    }
+
+    /// Whether the entry at the head is a new completion, without reaping it.
+    #[cfg(feature = "std")]
+    pub(crate) fn is_completion_available(&self) -> bool {
+        ((self.commands[self.head].status & 1) == 1) == self.phase
     }
 
     #[inline(always)]
@@ -119,17 +172,23 @@ impl CompletionQueue {
         }
     }
 
-    #[inline(always)]
-    pub(crate) fn complete_spin(&mut self) -> (usize, CompletionQueueEntry, usize) {
-        loop {
-            if let Ok(val) = self.complete() {
-                return val;
-            }
-            spin_loop();
-        }
-    }
-
     pub(crate) fn get_addr(&self) -> usize {
         self.commands.physical_address() as usize
     }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Start again at the first entry with a cleared queue, as the controller does after a reset.
+    pub(crate) fn reset(&mut self) {
+        self.commands.zero();
+        self.head = 0;
+        self.phase = true;
+    }
+
+    /// Deallocate the memory of a queue allocated with [`Self::new`].
+    pub(crate) fn deallocate<A: Allocator>(self, allocator: &A) -> Result<(), Error> {
+        self.commands.deallocate(allocator)
+    }
 }
@@ -0,0 +1,131 @@
+use crate::dma::{Allocator, Dma};
+use crate::error::Error;
+use alloc::vec::Vec;
+
+// A scatter gather list (SGL) describes a buffer by the address and length of each of its
+// physically contiguous segments, unlike PRPs it has no alignment requirements on them.
+
+/// SGL Descriptor Type of a Data Block descriptor, describing a segment of the buffer.
+const DATA_BLOCK: u8 = 0x0;
+/// SGL Descriptor Type of a Last Segment descriptor, pointing to the list of data blocks.
+const LAST_SEGMENT: u8 = 0x3;
+/// PRP or SGL for Data Transfer (PSDT) in the command flags: SGLs are used for the data,
+/// the metadata pointer is the address of a contiguous buffer.
+pub(crate) const PSDT_SGL: u8 = 0b01 << 6;
+
+/// SGL Support (SGLS) of the controller for the NVM command set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SglSupport {
+    #[default]
+    NotSupported,
+    /// SGLs are supported without alignment or granularity requirements.
+    Supported,
+    /// SGLs are supported for dword aligned and dword granular buffers only.
+    DwordAligned,
+}
+
+impl SglSupport {
+    pub(crate) fn from_u32(sgls: u32) -> Self {
+        match sgls & 0b11 {
+            0b01 => SglSupport::Supported,
+            0b10 => SglSupport::DwordAligned,
+            _ => SglSupport::NotSupported,
+        }
+    }
+}
+
+/// NVMe base specification 4.3.2 SGL Descriptor
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SglDescriptor {
+    address: u64,
+    length: u32,
+    _reserved: [u8; 3],
+    /// SGL Descriptor Type (4 bits) | SGL Descriptor Sub Type (4 bits)
+    identifier: u8,
+}
+
+impl SglDescriptor {
+    fn new(address: u64, length: u32, descriptor_type: u8) -> Self {
+        Self {
+            address,
+            length,
+            _reserved: [0; 3],
+            identifier: descriptor_type << 4,
+        }
+    }
+
+    /// The descriptor as the two quadwords of the data pointer of a command.
+    fn to_data_pointer(self) -> (u64, u64) {
+        (
+            self.address,
+            self.length as u64 | (self.identifier as u64) << 56,
+        )
+    }
+}
+
+/// The data pointer of a command transferring a buffer described by an SGL and
+/// the list of data block descriptors it points to, if the buffer is not physically contiguous.
+#[derive(Debug)]
+pub(crate) struct Sgl {
+    pub(crate) data_pointer: (u64, u64),
+    pub(crate) list: Option<Dma<SglDescriptor>>,
+}
+
+/// Describe the `buffer` by an SGL, or return `None` if its list of descriptors would not fit
+/// into one page and is therefore not guaranteed to be physically contiguous.
+pub(crate) fn build<A: Allocator, T>(
+    buffer: &Dma<T>,
+    page_size: usize,
+    allocator: &A,
+) -> Result<Option<Sgl>, Error> {
+    let virtual_address = buffer.virtual_address() as usize;
+    // physical address and length of each physically contiguous segment
+    let mut segments: Vec<(u64, u32)> = Vec::new();
+    let mut offset = 0;
+    while offset < buffer.size() {
+        let address = virtual_address + offset;
+        let length = (page_size - address % page_size).min(buffer.size() - offset);
+        let physical_address = if offset == 0 {
+            buffer.physical_address() as u64
+        } else {
+            allocator
+                .translate_virtual_to_physical(address as *const u8)
+                .map_err(Error::TranslateVirtualToPhysical)? as u64
+        };
+        match segments.last_mut() {
+            Some((start, segment_length))
+                if *start + *segment_length as u64 == physical_address
+                    && (*segment_length as usize + length) <= u32::MAX as usize =>
+            {
+                *segment_length += length as u32;
+            }
+            _ => segments.push((physical_address, length as u32)),
+        }
+        offset += length;
+    }
+
+    if let [(address, length)] = segments[..] {
+        return Ok(Some(Sgl {
+            data_pointer: SglDescriptor::new(address, length, DATA_BLOCK).to_data_pointer(),
+            list: None,
+        }));
+    }
+    let list_size = segments.len() * core::mem::size_of::<SglDescriptor>();
+    if list_size > page_size {
+        return Ok(None);
+    }
+    let mut list: Dma<SglDescriptor> = Dma::allocate(segments.len(), page_size, allocator)?;
+    for (index, &(address, length)) in segments.iter().enumerate() {
+        list[index] = SglDescriptor::new(address, length, DATA_BLOCK);
+    }
+    let last_segment = SglDescriptor::new(
+        list.physical_address() as u64,
+        list_size as u32,
+        LAST_SEGMENT,
+    );
+    Ok(Some(Sgl {
+        data_pointer: last_segment.to_data_pointer(),
+        list: Some(list),
+    }))
+}
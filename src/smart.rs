@@ -0,0 +1,86 @@
+/// NVMe base specification 5.16.1.3 SMART / Health Information log page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmartHealthLog {
+    /// Critical Warning, each set bit is a critical condition of the controller.
+    pub critical_warning: u8,
+    /// Composite Temperature in Kelvin.
+    pub composite_temperature: u16,
+    /// Available Spare in percent of the spare capacity.
+    pub available_spare: u8,
+    /// Percentage Used, an estimate of the life used, which may exceed 100.
+    pub percentage_used: u8,
+    /// Data Units Read in thousands of 512 byte units.
+    pub data_units_read: u128,
+    /// Data Units Written in thousands of 512 byte units.
+    pub data_units_written: u128,
+    pub power_cycles: u128,
+    pub power_on_hours: u128,
+    pub unsafe_shutdowns: u128,
+    /// Media and Data Integrity Errors, the number of unrecovered data integrity errors.
+    pub media_and_data_integrity_errors: u128,
+}
+
+/// The decoded Critical Warning field of the SMART / Health Information log page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CriticalWarnings {
+    /// The available spare capacity fell below the threshold.
+    pub available_spare_below_threshold: bool,
+    /// A temperature is above an over temperature or below an under temperature threshold.
+    pub temperature_out_of_range: bool,
+    /// The reliability is degraded by media related or internal errors.
+    pub reliability_degraded: bool,
+    /// All media is in read-only mode.
+    pub read_only: bool,
+    /// The volatile memory backup device failed.
+    pub volatile_memory_backup_failed: bool,
+    /// The persistent memory region became read-only or unreliable.
+    pub persistent_memory_region_read_only: bool,
+}
+
+impl CriticalWarnings {
+    pub(crate) fn from_u8(critical_warning: u8) -> Self {
+        Self {
+            available_spare_below_threshold: critical_warning & (1 << 0) != 0,
+            temperature_out_of_range: critical_warning & (1 << 1) != 0,
+            reliability_degraded: critical_warning & (1 << 2) != 0,
+            read_only: critical_warning & (1 << 3) != 0,
+            volatile_memory_backup_failed: critical_warning & (1 << 4) != 0,
+            persistent_memory_region_read_only: critical_warning & (1 << 5) != 0,
+        }
+    }
+
+    /// Whether any warning is set.
+    pub fn any(&self) -> bool {
+        *self != Self::default()
+    }
+}
+
+/// Size of the log page in bytes.
+pub(crate) const SMART_HEALTH_LOG_SIZE: usize = 512;
+
+impl SmartHealthLog {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let read_u128 = |offset: usize| {
+            let mut value = [0u8; 16];
+            value.copy_from_slice(&bytes[offset..offset + 16]);
+            u128::from_le_bytes(value)
+        };
+        Self {
+            critical_warning: bytes[0],
+            composite_temperature: u16::from_le_bytes([bytes[1], bytes[2]]),
+            available_spare: bytes[3],
+            percentage_used: bytes[5],
+            data_units_read: read_u128(32),
+            data_units_written: read_u128(48),
+            power_cycles: read_u128(112),
+            power_on_hours: read_u128(128),
+            unsafe_shutdowns: read_u128(144),
+            media_and_data_integrity_errors: read_u128(160),
+        }
+    }
+
+    /// The decoded [`Self::critical_warning`].
+    pub fn critical_warnings(&self) -> CriticalWarnings {
+        CriticalWarnings::from_u8(self.critical_warning)
+    }
+}
@@ -0,0 +1,198 @@
+use core::fmt;
+
+/// Status Code Type (SCT) of a completion queue entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCodeType {
+    Generic,
+    CommandSpecific,
+    MediaAndDataIntegrity,
+    Path,
+    VendorSpecific,
+    Reserved(u8),
+}
+
+impl StatusCodeType {
+    fn from_u8(status_code_type: u8) -> Self {
+        match status_code_type {
+            0x0 => StatusCodeType::Generic,
+            0x1 => StatusCodeType::CommandSpecific,
+            0x2 => StatusCodeType::MediaAndDataIntegrity,
+            0x3 => StatusCodeType::Path,
+            0x7 => StatusCodeType::VendorSpecific,
+            status_code_type => StatusCodeType::Reserved(status_code_type),
+        }
+    }
+}
+
+/// The status of a completion queue entry decoded from Status Code Type and Status Code
+/// (NVMe base specification 4.2.3 Status Field Definition).
+/// Combinations without a name are kept as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCode {
+    // Generic Command Status
+    Success,
+    InvalidOpcode,
+    InvalidField,
+    CommandIdConflict,
+    DataTransferError,
+    AbortedPowerLoss,
+    InternalError,
+    AbortRequested,
+    AbortedSubmissionQueueDeletion,
+    AbortedFailedFusedCommand,
+    AbortedMissingFusedCommand,
+    InvalidNamespaceOrFormat,
+    CommandSequenceError,
+    InvalidPrpOffset,
+    LbaOutOfRange,
+    CapacityExceeded,
+    NamespaceNotReady,
+    ReservationConflict,
+    FormatInProgress,
+    // Command Specific Status
+    CompletionQueueInvalid,
+    InvalidQueueIdentifier,
+    InvalidQueueSize,
+    AbortCommandLimitExceeded,
+    AsynchronousEventRequestLimitExceeded,
+    InvalidFirmwareSlot,
+    InvalidFirmwareImage,
+    InvalidInterruptVector,
+    InvalidLogPage,
+    InvalidFormat,
+    InvalidQueueDeletion,
+    FeatureIdentifierNotSaveable,
+    FeatureNotChangeable,
+    FeatureNotNamespaceSpecific,
+    ConflictingAttributes,
+    InvalidProtectionInformation,
+    WriteToReadOnlyRange,
+    // Media and Data Integrity Errors
+    WriteFault,
+    UnrecoveredReadError,
+    EndToEndGuardCheckError,
+    EndToEndApplicationTagCheckError,
+    EndToEndReferenceTagCheckError,
+    CompareFailure,
+    AccessDenied,
+    DeallocatedOrUnwrittenLogicalBlock,
+    // Path Related Status
+    InternalPathError,
+    AsymmetricAccessPersistentLoss,
+    AsymmetricAccessInaccessible,
+    AsymmetricAccessTransition,
+    ControllerPathingError,
+    HostPathingError,
+    CommandAbortedByHost,
+    Other(StatusCodeType, u8),
+}
+
+impl StatusCode {
+    fn from_parts(status_code_type: StatusCodeType, status_code: u8) -> Self {
+        use StatusCodeType::*;
+        match (status_code_type, status_code) {
+            (Generic, 0x00) => StatusCode::Success,
+            (Generic, 0x01) => StatusCode::InvalidOpcode,
+            (Generic, 0x02) => StatusCode::InvalidField,
+            (Generic, 0x03) => StatusCode::CommandIdConflict,
+            (Generic, 0x04) => StatusCode::DataTransferError,
+            (Generic, 0x05) => StatusCode::AbortedPowerLoss,
+            (Generic, 0x06) => StatusCode::InternalError,
+            (Generic, 0x07) => StatusCode::AbortRequested,
+            (Generic, 0x08) => StatusCode::AbortedSubmissionQueueDeletion,
+            (Generic, 0x09) => StatusCode::AbortedFailedFusedCommand,
+            (Generic, 0x0A) => StatusCode::AbortedMissingFusedCommand,
+            (Generic, 0x0B) => StatusCode::InvalidNamespaceOrFormat,
+            (Generic, 0x0C) => StatusCode::CommandSequenceError,
+            (Generic, 0x13) => StatusCode::InvalidPrpOffset,
+            (Generic, 0x80) => StatusCode::LbaOutOfRange,
+            (Generic, 0x81) => StatusCode::CapacityExceeded,
+            (Generic, 0x82) => StatusCode::NamespaceNotReady,
+            (Generic, 0x83) => StatusCode::ReservationConflict,
+            (Generic, 0x84) => StatusCode::FormatInProgress,
+            (CommandSpecific, 0x00) => StatusCode::CompletionQueueInvalid,
+            (CommandSpecific, 0x01) => StatusCode::InvalidQueueIdentifier,
+            (CommandSpecific, 0x02) => StatusCode::InvalidQueueSize,
+            (CommandSpecific, 0x03) => StatusCode::AbortCommandLimitExceeded,
+            (CommandSpecific, 0x05) => StatusCode::AsynchronousEventRequestLimitExceeded,
+            (CommandSpecific, 0x06) => StatusCode::InvalidFirmwareSlot,
+            (CommandSpecific, 0x07) => StatusCode::InvalidFirmwareImage,
+            (CommandSpecific, 0x08) => StatusCode::InvalidInterruptVector,
+            (CommandSpecific, 0x09) => StatusCode::InvalidLogPage,
+            (CommandSpecific, 0x0A) => StatusCode::InvalidFormat,
+            (CommandSpecific, 0x0C) => StatusCode::InvalidQueueDeletion,
+            (CommandSpecific, 0x0D) => StatusCode::FeatureIdentifierNotSaveable,
+            (CommandSpecific, 0x0E) => StatusCode::FeatureNotChangeable,
+            (CommandSpecific, 0x0F) => StatusCode::FeatureNotNamespaceSpecific,
+            (CommandSpecific, 0x80) => StatusCode::ConflictingAttributes,
+            (CommandSpecific, 0x81) => StatusCode::InvalidProtectionInformation,
+            (CommandSpecific, 0x82) => StatusCode::WriteToReadOnlyRange,
+            (MediaAndDataIntegrity, 0x80) => StatusCode::WriteFault,
+            (MediaAndDataIntegrity, 0x81) => StatusCode::UnrecoveredReadError,
+            (MediaAndDataIntegrity, 0x82) => StatusCode::EndToEndGuardCheckError,
+            (MediaAndDataIntegrity, 0x83) => StatusCode::EndToEndApplicationTagCheckError,
+            (MediaAndDataIntegrity, 0x84) => StatusCode::EndToEndReferenceTagCheckError,
+            (MediaAndDataIntegrity, 0x85) => StatusCode::CompareFailure,
+            (MediaAndDataIntegrity, 0x86) => StatusCode::AccessDenied,
+            (MediaAndDataIntegrity, 0x87) => StatusCode::DeallocatedOrUnwrittenLogicalBlock,
+            (Path, 0x00) => StatusCode::InternalPathError,
+            (Path, 0x01) => StatusCode::AsymmetricAccessPersistentLoss,
+            (Path, 0x02) => StatusCode::AsymmetricAccessInaccessible,
+            (Path, 0x03) => StatusCode::AsymmetricAccessTransition,
+            (Path, 0x60) => StatusCode::ControllerPathingError,
+            (Path, 0x70) => StatusCode::HostPathingError,
+            (Path, 0x71) => StatusCode::CommandAbortedByHost,
+            (status_code_type, status_code) => StatusCode::Other(status_code_type, status_code),
+        }
+    }
+}
+
+/// The Status Field of a completion queue entry (without the phase tag).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompletionStatus {
+    pub code: StatusCode,
+    /// The raw status field without the phase tag.
+    pub raw: u16,
+}
+
+impl CompletionStatus {
+    pub(crate) fn from_u16(status: u16) -> Self {
+        let status_code_type = StatusCodeType::from_u8(((status >> 8) & 0b111) as u8);
+        Self {
+            code: StatusCode::from_parts(status_code_type, status as u8),
+            raw: status,
+        }
+    }
+
+    /// Status Code Type (SCT).
+    pub fn status_code_type(&self) -> StatusCodeType {
+        StatusCodeType::from_u8(((self.raw >> 8) & 0b111) as u8)
+    }
+
+    /// Status Code (SC), whose meaning depends on the status code type.
+    pub fn status_code(&self) -> u8 {
+        self.raw as u8
+    }
+
+    /// More (M): more status information is available in the Error Information log page.
+    pub fn more(&self) -> bool {
+        (self.raw >> 13) & 0b1 == 1
+    }
+
+    /// Do Not Retry (DNR): the command is expected to fail again if it is resubmitted.
+    pub fn do_not_retry(&self) -> bool {
+        (self.raw >> 14) & 0b1 == 1
+    }
+}
+
+impl fmt::Display for CompletionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} (status code 0x{:X} and type 0x{:X})",
+            self.code,
+            self.status_code(),
+            (self.raw >> 8) & 0b111
+        )
+    }
+}
@@ -0,0 +1,80 @@
+use alloc::vec::Vec;
+
+/// Log page identifier of the Telemetry Host-Initiated log page.
+pub(crate) const TELEMETRY_HOST_INITIATED_LOG_IDENTIFIER: u8 = 0x07;
+
+/// Log Specific Field bit to create a new capture of the Telemetry Host-Initiated data.
+pub(crate) const CREATE_TELEMETRY_HOST_INITIATED_DATA: u8 = 0b1;
+
+/// Size of a telemetry block, which is also the size of the header, in bytes.
+pub(crate) const TELEMETRY_BLOCK_SIZE: usize = 512;
+
+/// NVMe base specification 5.16.1.8 Telemetry Host-Initiated log page header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelemetryHeader {
+    pub ieee_oui: u32,
+    /// Last block of data area 1, blocks are 512 bytes and block 0 is the header.
+    pub data_area_1_last_block: u16,
+    /// Last block of data area 2, not before the last block of data area 1.
+    pub data_area_2_last_block: u16,
+    /// Last block of data area 3, not before the last block of data area 2.
+    pub data_area_3_last_block: u16,
+    /// Incremented by the controller on every capture of the host-initiated data.
+    pub host_initiated_data_generation_number: u8,
+    /// Whether the controller has saved controller-initiated data in addition.
+    pub controller_initiated_data_available: bool,
+    pub controller_initiated_data_generation_number: u8,
+    /// Vendor specific identification of the reason for the capture.
+    pub reason_identifier: [u8; 128],
+}
+
+impl TelemetryHeader {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let read_u16 = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let mut reason_identifier = [0u8; 128];
+        reason_identifier.copy_from_slice(&bytes[384..512]);
+        Self {
+            ieee_oui: u32::from_le_bytes([bytes[5], bytes[6], bytes[7], 0]),
+            data_area_1_last_block: read_u16(8),
+            data_area_2_last_block: read_u16(10),
+            data_area_3_last_block: read_u16(12),
+            host_initiated_data_generation_number: bytes[381],
+            controller_initiated_data_available: bytes[382] & 0b1 == 1,
+            controller_initiated_data_generation_number: bytes[383],
+            reason_identifier,
+        }
+    }
+
+    /// Size of the log page up to the end of data area 3 in bytes, including the header.
+    pub fn size(&self) -> usize {
+        (self.data_area_3_last_block as usize + 1) * TELEMETRY_BLOCK_SIZE
+    }
+}
+
+/// A capture of the Telemetry Host-Initiated log page.
+#[derive(Debug, Clone)]
+pub struct Telemetry {
+    pub header: TelemetryHeader,
+    /// The whole log page: the header block followed by data areas 1 to 3.
+    pub data: Vec<u8>,
+}
+
+impl Telemetry {
+    /// The bytes of data area `area` (1 to 3), `None` for other areas.
+    /// Areas ending before the previous area are empty.
+    pub fn data_area(&self, area: u8) -> Option<&[u8]> {
+        let last_blocks = [
+            0,
+            self.header.data_area_1_last_block,
+            self.header.data_area_2_last_block,
+            self.header.data_area_3_last_block,
+        ];
+        if !(1..=3).contains(&area) {
+            return None;
+        }
+        let start = (last_blocks[area as usize - 1] as usize + 1) * TELEMETRY_BLOCK_SIZE;
+        let end = (last_blocks[area as usize] as usize + 1) * TELEMETRY_BLOCK_SIZE;
+        let end = end.min(self.data.len());
+        Some(&self.data[start.min(end)..end])
+    }
+}
@@ -0,0 +1,38 @@
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+/// A point in time after which waiting for the controller is given up.
+/// Without the standard library there is no clock, so the time is approximated
+/// by the number of polls, assuming a register read takes about a microsecond.
+#[derive(Debug)]
+pub(crate) struct Deadline {
+    #[cfg(feature = "std")]
+    end: Instant,
+    #[cfg(not(feature = "std"))]
+    remaining_polls: u64,
+}
+
+impl Deadline {
+    pub(crate) fn after_milliseconds(milliseconds: u64) -> Self {
+        Self {
+            #[cfg(feature = "std")]
+            end: Instant::now() + Duration::from_millis(milliseconds),
+            #[cfg(not(feature = "std"))]
+            remaining_polls: milliseconds * 1000,
+        }
+    }
+
+    /// Whether the deadline has passed; call once per poll.
+    pub(crate) fn expired(&mut self) -> bool {
+        #[cfg(feature = "std")]
+        return Instant::now() >= self.end;
+        #[cfg(not(feature = "std"))]
+        {
+            if self.remaining_polls == 0 {
+                return true;
+            }
+            self.remaining_polls -= 1;
+            false
+        }
+    }
+}
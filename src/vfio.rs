@@ -0,0 +1,396 @@
+use crate::dma::Allocator;
+use crate::pci::{MappedBar, BUS_MASTER_ENABLE_BIT, COMMAND_REGISTER_OFFSET};
+use core::error::Error;
+use core::ptr;
+use std::boxed::Box;
+use std::ffi::CString;
+use std::format;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::vec::Vec;
+
+// _IO(VFIO_TYPE, VFIO_BASE + n), see linux/vfio.h
+const VFIO_GET_API_VERSION: u64 = 0x3B64;
+const VFIO_CHECK_EXTENSION: u64 = 0x3B65;
+const VFIO_SET_IOMMU: u64 = 0x3B66;
+const VFIO_GROUP_GET_STATUS: u64 = 0x3B67;
+const VFIO_GROUP_SET_CONTAINER: u64 = 0x3B68;
+const VFIO_GROUP_GET_DEVICE_FD: u64 = 0x3B6A;
+const VFIO_DEVICE_GET_REGION_INFO: u64 = 0x3B6C;
+const VFIO_DEVICE_GET_IRQ_INFO: u64 = 0x3B6D;
+const VFIO_DEVICE_SET_IRQS: u64 = 0x3B6E;
+const VFIO_IOMMU_MAP_DMA: u64 = 0x3B71;
+const VFIO_IOMMU_UNMAP_DMA: u64 = 0x3B72;
+
+const VFIO_API_VERSION: i32 = 0;
+const VFIO_TYPE1_IOMMU: u64 = 1;
+const VFIO_GROUP_FLAGS_VIABLE: u32 = 1 << 0;
+const VFIO_DMA_MAP_FLAG_READ: u32 = 1 << 0;
+const VFIO_DMA_MAP_FLAG_WRITE: u32 = 1 << 1;
+const VFIO_IRQ_SET_DATA_EVENTFD: u32 = 1 << 2;
+const VFIO_IRQ_SET_ACTION_TRIGGER: u32 = 1 << 5;
+
+const VFIO_PCI_BAR0_REGION_INDEX: u32 = 0;
+const VFIO_PCI_CONFIG_REGION_INDEX: u32 = 7;
+const VFIO_PCI_MSIX_IRQ_INDEX: u32 = 2;
+// bit 1: "memory space enable"
+const MEMORY_SPACE_ENABLE_BIT: u64 = 1;
+
+/// First I/O virtual address handed out, leaving the lowest addresses unmapped
+/// so a zero pointer given to the controller faults.
+const FIRST_IOVA: u64 = 1 << 20;
+
+/// `struct vfio_group_status`
+#[repr(C)]
+struct GroupStatus {
+    argsz: u32,
+    flags: u32,
+}
+
+/// `struct vfio_region_info`
+#[repr(C)]
+#[derive(Default)]
+struct RegionInfo {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    cap_offset: u32,
+    size: u64,
+    offset: u64,
+}
+
+/// `struct vfio_irq_info`
+#[repr(C)]
+#[derive(Default)]
+struct IrqInfo {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    count: u32,
+}
+
+/// Size of `struct vfio_irq_set` without its eventfds.
+const IRQ_SET_HEADER_SIZE: usize = 20;
+
+/// `struct vfio_iommu_type1_dma_map`
+#[repr(C)]
+struct DmaMap {
+    argsz: u32,
+    flags: u32,
+    vaddr: u64,
+    iova: u64,
+    size: u64,
+}
+
+/// `struct vfio_iommu_type1_dma_unmap`
+#[repr(C)]
+struct DmaUnmap {
+    argsz: u32,
+    flags: u32,
+    iova: u64,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct Mapping {
+    virtual_address: usize,
+    size: usize,
+    iova: u64,
+}
+
+/// Allocates memory mapped for DMA by the IOMMU through a VFIO container,
+/// for devices opened with [`crate::NvmeDevice::from_vfio`].
+/// Needs neither huge pages nor physically contiguous memory nor root privileges,
+/// only access to the VFIO group; the "physical address" of its memory is the I/O virtual
+/// address (IOVA) it is mapped at.
+#[derive(Debug)]
+pub struct VfioAllocator {
+    container: File,
+    // the group must stay open for its devices to stay usable
+    _group: File,
+    next_iova: AtomicU64,
+    mappings: Mutex<Vec<Mapping>>,
+}
+
+impl Allocator for VfioAllocator {
+    fn allocate<T>(&self, layout: core::alloc::Layout) -> Result<*mut [T], Box<dyn Error>> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size = layout.size().max(1).next_multiple_of(page_size);
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err("failed to mmap pages".into());
+        }
+        let iova = self.next_iova.fetch_add(size as u64, Ordering::Relaxed);
+        let mut map = DmaMap {
+            argsz: core::mem::size_of::<DmaMap>() as u32,
+            flags: VFIO_DMA_MAP_FLAG_READ | VFIO_DMA_MAP_FLAG_WRITE,
+            vaddr: ptr as u64,
+            iova,
+            size: size as u64,
+        };
+        if let Err(error) = ioctl(
+            &self.container,
+            VFIO_IOMMU_MAP_DMA,
+            &mut map as *mut _ as u64,
+        ) {
+            unsafe { libc::munmap(ptr, size) };
+            return Err(format!("failed to map {size} bytes for DMA: {error}").into());
+        }
+        self.mappings.lock().unwrap().push(Mapping {
+            virtual_address: ptr as usize,
+            size,
+            iova,
+        });
+        let slice = core::ptr::slice_from_raw_parts_mut(ptr, size);
+        Ok(slice as *mut [T])
+    }
+
+    fn deallocate<T>(&self, slice: *mut [T]) -> Result<(), Box<dyn Error>> {
+        let virtual_address = slice as *mut u8 as usize;
+        let mapping = {
+            let mut mappings = self.mappings.lock().unwrap();
+            let index = mappings
+                .iter()
+                .position(|mapping| mapping.virtual_address == virtual_address)
+                .ok_or("memory was not allocated by this allocator")?;
+            mappings.swap_remove(index)
+        };
+        let mut unmap = DmaUnmap {
+            argsz: core::mem::size_of::<DmaUnmap>() as u32,
+            flags: 0,
+            iova: mapping.iova,
+            size: mapping.size as u64,
+        };
+        ioctl(
+            &self.container,
+            VFIO_IOMMU_UNMAP_DMA,
+            &mut unmap as *mut _ as u64,
+        )?;
+        if unsafe { libc::munmap(virtual_address as *mut libc::c_void, mapping.size) } != 0 {
+            return Err("failed to munmap pages".into());
+        }
+        Ok(())
+    }
+
+    fn translate_virtual_to_physical<T>(
+        &self,
+        virtual_address: *const T,
+    ) -> Result<*const T, Box<dyn Error>> {
+        let address = virtual_address as usize;
+        let mappings = self.mappings.lock().unwrap();
+        let mapping = mappings
+            .iter()
+            .find(|mapping| {
+                (mapping.virtual_address..mapping.virtual_address + mapping.size).contains(&address)
+            })
+            .ok_or("memory is not mapped for DMA")?;
+        Ok((mapping.iova + (address - mapping.virtual_address) as u64) as *const T)
+    }
+}
+
+/// Attaches the VFIO `group` to a new container with a type 1 IOMMU, enables DMA for the device
+/// at `pci_address` in it and maps its BAR 0.
+/// Returns the allocator of the container and the mapping, which keeps the device file open.
+pub(crate) fn open(
+    group: u32,
+    pci_address: &str,
+) -> Result<(VfioAllocator, MappedBar), Box<dyn Error>> {
+    let container = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/vfio/vfio")?;
+    if ioctl(&container, VFIO_GET_API_VERSION, 0)? != VFIO_API_VERSION {
+        return Err("unknown VFIO API version".into());
+    }
+    if ioctl(&container, VFIO_CHECK_EXTENSION, VFIO_TYPE1_IOMMU)? != 1 {
+        return Err("VFIO does not support the type 1 IOMMU".into());
+    }
+
+    let group_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!("/dev/vfio/{group}"))?;
+    let mut status = GroupStatus {
+        argsz: core::mem::size_of::<GroupStatus>() as u32,
+        flags: 0,
+    };
+    ioctl(
+        &group_file,
+        VFIO_GROUP_GET_STATUS,
+        &mut status as *mut _ as u64,
+    )?;
+    if status.flags & VFIO_GROUP_FLAGS_VIABLE == 0 {
+        return Err(format!(
+            "VFIO group {group} is not viable - are all its devices bound to vfio-pci?"
+        )
+        .into());
+    }
+    let container_fd = container.as_raw_fd();
+    ioctl(
+        &group_file,
+        VFIO_GROUP_SET_CONTAINER,
+        &container_fd as *const _ as u64,
+    )?;
+    ioctl(&container, VFIO_SET_IOMMU, VFIO_TYPE1_IOMMU)?;
+
+    let name = CString::new(pci_address)?;
+    let device_fd = ioctl(&group_file, VFIO_GROUP_GET_DEVICE_FD, name.as_ptr() as u64)?;
+    let device = unsafe { File::from_raw_fd(device_fd) };
+
+    // enable memory space accesses and DMA
+    let config = region_info(&device, VFIO_PCI_CONFIG_REGION_INDEX)?;
+    let mut command = [0u8; 2];
+    device.read_exact_at(&mut command, config.offset + COMMAND_REGISTER_OFFSET)?;
+    let command =
+        u16::from_le_bytes(command) | (1 << BUS_MASTER_ENABLE_BIT) | (1 << MEMORY_SPACE_ENABLE_BIT);
+    device.write_all_at(
+        &command.to_le_bytes(),
+        config.offset + COMMAND_REGISTER_OFFSET,
+    )?;
+
+    let bar = region_info(&device, VFIO_PCI_BAR0_REGION_INDEX)?;
+    let length = bar.size as usize;
+    if length == 0 {
+        return Err(format!("BAR 0 of {pci_address} is empty").into());
+    }
+    let ptr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            length,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            device.as_raw_fd(),
+            bar.offset as libc::off_t,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        let error = io::Error::last_os_error();
+        return Err(format!("VFIO mapping of BAR 0 of {pci_address} failed: {error}").into());
+    }
+
+    let allocator = VfioAllocator {
+        container,
+        _group: group_file,
+        next_iova: AtomicU64::new(FIRST_IOVA),
+        mappings: Mutex::new(Vec::new()),
+    };
+    Ok((allocator, MappedBar::new(ptr as *mut u8, length, device)))
+}
+
+/// The MSI-X vectors of a device opened through VFIO, each signaling its own eventfd.
+#[derive(Debug)]
+pub(crate) struct MsixInterrupts {
+    device: File,
+    /// Number of vectors in the MSI-X table of the device.
+    vector_count: u32,
+    /// Set once MSI-X is enabled, which happens with the first vector.
+    enabled: bool,
+}
+
+impl MsixInterrupts {
+    pub(crate) fn new(device: &File) -> Result<Self, Box<dyn Error>> {
+        let mut info = IrqInfo {
+            argsz: core::mem::size_of::<IrqInfo>() as u32,
+            index: VFIO_PCI_MSIX_IRQ_INDEX,
+            ..Default::default()
+        };
+        ioctl(device, VFIO_DEVICE_GET_IRQ_INFO, &mut info as *mut _ as u64)?;
+        Ok(Self {
+            device: device.try_clone()?,
+            vector_count: info.count,
+            enabled: false,
+        })
+    }
+
+    pub(crate) fn vector_count(&self) -> u32 {
+        self.vector_count
+    }
+
+    /// Let the MSI-X `vector` signal a new eventfd, which is returned.
+    pub(crate) fn enable(&mut self, vector: u32) -> Result<File, Box<dyn Error>> {
+        if vector >= self.vector_count {
+            return Err(format!(
+                "MSI-X vector {vector} exceeds the {} vectors of the device",
+                self.vector_count
+            )
+            .into());
+        }
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if eventfd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let eventfd = unsafe { File::from_raw_fd(eventfd) };
+        if self.enabled {
+            self.set_triggers(vector, &[eventfd.as_raw_fd()])?;
+        } else {
+            // MSI-X is enabled with the whole table, vectors without an eventfd stay unused
+            let mut eventfds = std::vec![-1; self.vector_count as usize];
+            eventfds[vector as usize] = eventfd.as_raw_fd();
+            self.set_triggers(0, &eventfds)?;
+            self.enabled = true;
+        }
+        Ok(eventfd)
+    }
+
+    /// Stop the MSI-X `vector` from signaling its eventfd.
+    pub(crate) fn disable(&mut self, vector: u32) -> Result<(), Box<dyn Error>> {
+        self.set_triggers(vector, &[-1])
+    }
+
+    /// Signal the `eventfds` on the MSI-X vectors from `start` on, `-1` for none.
+    fn set_triggers(&self, start: u32, eventfds: &[i32]) -> Result<(), Box<dyn Error>> {
+        let mut irq_set = Vec::with_capacity(IRQ_SET_HEADER_SIZE + eventfds.len() * 4);
+        let argsz = (IRQ_SET_HEADER_SIZE + eventfds.len() * 4) as u32;
+        let flags = VFIO_IRQ_SET_DATA_EVENTFD | VFIO_IRQ_SET_ACTION_TRIGGER;
+        for field in [
+            argsz,
+            flags,
+            VFIO_PCI_MSIX_IRQ_INDEX,
+            start,
+            eventfds.len() as u32,
+        ] {
+            irq_set.extend_from_slice(&field.to_ne_bytes());
+        }
+        for eventfd in eventfds {
+            irq_set.extend_from_slice(&eventfd.to_ne_bytes());
+        }
+        ioctl(&self.device, VFIO_DEVICE_SET_IRQS, irq_set.as_ptr() as u64)?;
+        Ok(())
+    }
+}
+
+fn region_info(device: &File, index: u32) -> Result<RegionInfo, Box<dyn Error>> {
+    let mut info = RegionInfo {
+        argsz: core::mem::size_of::<RegionInfo>() as u32,
+        index,
+        ..Default::default()
+    };
+    ioctl(
+        device,
+        VFIO_DEVICE_GET_REGION_INFO,
+        &mut info as *mut _ as u64,
+    )?;
+    Ok(info)
+}
+
+/// Issues the VFIO `request` with the `argument` (a value or a pointer) on the `file`.
+fn ioctl(file: &File, request: u64, argument: u64) -> Result<i32, io::Error> {
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, argument) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result)
+}
@@ -0,0 +1,111 @@
+use crate::nvme::Namespace;
+
+/// The state of a zone in a zoned namespace (ZNS command set specification 2.1.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneState {
+    Empty,
+    ImplicitlyOpened,
+    ExplicitlyOpened,
+    Closed,
+    ReadOnly,
+    Full,
+    Offline,
+    Reserved(u8),
+}
+
+impl ZoneState {
+    pub(crate) fn from_u8(state: u8) -> Self {
+        match state {
+            0x1 => ZoneState::Empty,
+            0x2 => ZoneState::ImplicitlyOpened,
+            0x3 => ZoneState::ExplicitlyOpened,
+            0x4 => ZoneState::Closed,
+            0xD => ZoneState::ReadOnly,
+            0xE => ZoneState::Full,
+            0xF => ZoneState::Offline,
+            state => ZoneState::Reserved(state),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(
+            self,
+            ZoneState::ImplicitlyOpened | ZoneState::ExplicitlyOpened
+        )
+    }
+}
+
+/// ZNS command set specification 3.4.2.2.3 Zone Descriptor
+#[derive(Debug, Clone, Copy)]
+pub struct ZoneDescriptor {
+    pub zone_type: u8,
+    pub state: ZoneState,
+    pub attributes: u8,
+    /// Number of writable logical blocks in the zone.
+    pub capacity: u64,
+    pub start_logical_block_address: u64,
+    pub write_pointer: u64,
+}
+
+/// Size of the report zones header and of each zone descriptor in bytes.
+pub(crate) const ZONE_REPORT_ENTRY_SIZE: usize = 64;
+
+impl ZoneDescriptor {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let read_u64 = |offset: usize| {
+            let mut value = [0u8; 8];
+            value.copy_from_slice(&bytes[offset..offset + 8]);
+            u64::from_le_bytes(value)
+        };
+        Self {
+            zone_type: bytes[0] & 0b1111,
+            state: ZoneState::from_u8(bytes[1] >> 4),
+            attributes: bytes[2],
+            capacity: read_u64(8),
+            start_logical_block_address: read_u64(16),
+            write_pointer: read_u64(24),
+        }
+    }
+}
+
+/// Command set identifier (CSI) of the zoned namespace command set.
+pub(crate) const ZONED_NAMESPACE_COMMAND_SET: u8 = 0x02;
+
+/// A namespace of the zoned namespace command set with its zone limits
+/// (ZNS command set specification 4.1.5.1 I/O Command Set Specific Identify Namespace).
+/// Opening or activating more zones than the limits allow fails the command.
+#[derive(Debug, Clone, Copy)]
+pub struct ZonedNamespace {
+    pub namespace: Namespace,
+    /// Zone size (ZSZE) in logical blocks of the LBA format the namespace is formatted with.
+    pub zone_size: u64,
+    /// Maximum Open Resources (MOR) converted to a count; `None` if there is no limit.
+    pub maximum_open_zones: Option<u32>,
+    /// Maximum Active Resources (MAR) converted to a count; `None` if there is no limit.
+    pub maximum_active_zones: Option<u32>,
+}
+
+impl ZonedNamespace {
+    pub(crate) fn from_bytes(namespace: Namespace, bytes: &[u8]) -> Self {
+        let read_u32 = |offset: usize| {
+            let mut value = [0u8; 4];
+            value.copy_from_slice(&bytes[offset..offset + 4]);
+            u32::from_le_bytes(value)
+        };
+        // 0's based values, all bits set means no limit
+        let maximum_resources = |offset: usize| match read_u32(offset) {
+            u32::MAX => None,
+            value => Some(value + 1),
+        };
+        // LBA format extension data structures, one of 16 bytes per LBA format
+        let extension_offset = 2816 + namespace.lba_format_index as usize * 16;
+        let mut zone_size = [0u8; 8];
+        zone_size.copy_from_slice(&bytes[extension_offset..extension_offset + 8]);
+        Self {
+            namespace,
+            zone_size: u64::from_le_bytes(zone_size),
+            maximum_open_zones: maximum_resources(8),
+            maximum_active_zones: maximum_resources(4),
+        }
+    }
+}